@@ -0,0 +1,149 @@
+//! Plays an NSF through the sound card, or renders it to a WAV file.
+//!
+//!     nsf FILE [--song N] [--seconds S] [--wav OUT]
+//!
+//! Songs count from 1, like players show them. Without `--wav` the song plays
+//! until it is interrupted or `--seconds` have passed; with it, 60 seconds are
+//! rendered unless told otherwise. Playing needs the `sdl` feature.
+//!
+//! The 2A03's own channels aren't emulated yet, so tunes that use no expansion
+//! chip are refused, and the rest play without them.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process;
+#[cfg(feature = "sdl")]
+use std::thread;
+#[cfg(feature = "sdl")]
+use std::time::Duration;
+
+use rust_NES::nsf::{Nsf, Player};
+use rust_NES::wav;
+
+const SAMPLE_RATE: u32 = 44_100;
+const DEFAULT_WAV_SECONDS: f64 = 60.0;
+/// How much audio is handed to SDL at a time, and about how much is kept queued.
+#[cfg(feature = "sdl")]
+const CHUNK_SAMPLES: usize = 2048;
+
+struct Args {
+    path: String,
+    song: Option<u8>,
+    seconds: Option<f64>,
+    wav: Option<String>,
+}
+
+fn usage() -> ! {
+    eprintln!("usage: nsf FILE [--song N] [--seconds S] [--wav OUT]");
+    process::exit(2);
+}
+
+fn parse_args() -> Args {
+    let mut args = std::env::args().skip(1);
+    let mut path = None;
+    let mut song = None;
+    let mut seconds = None;
+    let mut wav = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--song" => song = args.next().and_then(|n| n.parse().ok()).or_else(|| usage()),
+            "--seconds" => seconds = args.next().and_then(|s| s.parse().ok()).or_else(|| usage()),
+            "--wav" => wav = Some(args.next().unwrap_or_else(|| usage())),
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    Args {
+        path: path.unwrap_or_else(|| usage()),
+        song,
+        seconds,
+        wav,
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn play(player: &mut Player, seconds: Option<f64>) -> Result<(), String> {
+    let sdl_context = sdl2::init()?;
+    let audio = sdl_context.audio()?;
+    let desired = sdl2::audio::AudioSpecDesired {
+        freq: Some(SAMPLE_RATE as i32),
+        channels: Some(1),
+        samples: None,
+    };
+    let queue = audio.open_queue::<f32, _>(None, &desired)?;
+    queue.resume();
+
+    let total = seconds.map(|seconds| (seconds * SAMPLE_RATE as f64) as usize);
+    let mut played = 0;
+    while total.is_none_or(|total| played < total) {
+        let count = total.map_or(CHUNK_SAMPLES, |total| CHUNK_SAMPLES.min(total - played));
+        if !queue.queue(&player.render(count)) {
+            return Err(sdl2::get_error());
+        }
+        played += count;
+        while queue.size() as usize > CHUNK_SAMPLES * 2 * std::mem::size_of::<f32>() {
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+    while queue.size() > 0 {
+        thread::sleep(Duration::from_millis(5));
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "sdl"))]
+fn play(_player: &mut Player, _seconds: Option<f64>) -> Result<(), String> {
+    Err("built without the sdl feature, only --wav works".to_string())
+}
+
+fn write_wav(path: &str, samples: &[f32]) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    wav::write(&mut out, SAMPLE_RATE, samples)?;
+    out.flush()
+}
+
+fn main() {
+    let args = parse_args();
+    let raw = std::fs::read(&args.path).unwrap_or_else(|err| {
+        eprintln!("{}: {}", args.path, err);
+        process::exit(1);
+    });
+    let nsf = Nsf::parse(&raw).unwrap_or_else(|err| {
+        eprintln!("{}: {}", args.path, err);
+        process::exit(1);
+    });
+
+    let song = args.song.unwrap_or(nsf.starting_song).max(1);
+    if song > nsf.songs {
+        eprintln!("{} has {} songs", args.path, nsf.songs);
+        process::exit(1);
+    }
+    if !nsf.is_audible() {
+        eprintln!(
+            "{}: only uses the 2A03, which isn't emulated yet, so there is nothing to play",
+            args.path
+        );
+        process::exit(1);
+    }
+    eprintln!("{} - {} ({})", nsf.name, nsf.artist, nsf.copyright);
+    eprintln!("Song {} of {}", song, nsf.songs);
+    eprintln!("Warning: the 2A03 channels aren't emulated, only the expansion chips are heard");
+
+    let mut player = Player::new(&nsf, song - 1, SAMPLE_RATE);
+    match args.wav {
+        Some(path) => {
+            let seconds = args.seconds.unwrap_or(DEFAULT_WAV_SECONDS);
+            let samples = player.render((seconds * SAMPLE_RATE as f64) as usize);
+            if let Err(err) = write_wav(&path, &samples) {
+                eprintln!("{}: {}", path, err);
+                process::exit(1);
+            }
+        }
+        None => {
+            if let Err(err) = play(&mut player, args.seconds) {
+                eprintln!("Audio playback failed: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+}
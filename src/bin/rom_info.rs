@@ -0,0 +1,67 @@
+//! Prints what loading a ROM file learns about it.
+//!
+//!     rom_info [--json] FILE...
+//!
+//! With `--json` each file gets one line holding `{"file": ..., "rom": {...}}`,
+//! or `{"file": ..., "error": ...}` if it doesn't load.
+
+use std::process;
+
+use rust_NES::info::{json_string, RomInfo};
+
+fn main() {
+    let mut json = false;
+    let mut paths = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--json" => json = true,
+            _ if arg.starts_with("--") => {
+                eprintln!("usage: rom_info [--json] FILE...");
+                process::exit(2);
+            }
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        eprintln!("usage: rom_info [--json] FILE...");
+        process::exit(2);
+    }
+
+    let mut failed = false;
+    for (i, path) in paths.iter().enumerate() {
+        let info = std::fs::read(path)
+            .map_err(|err| err.to_string())
+            .and_then(|raw| RomInfo::new(&raw).map_err(|err| err.to_string()));
+        match (info, json) {
+            (Ok(info), true) => {
+                println!(
+                    "{{\"file\":{},\"rom\":{}}}",
+                    json_string(path),
+                    info.to_json()
+                )
+            }
+            (Err(err), true) => {
+                failed = true;
+                println!(
+                    "{{\"file\":{},\"error\":{}}}",
+                    json_string(path),
+                    json_string(&err)
+                )
+            }
+            (Ok(info), false) => {
+                if i > 0 {
+                    println!();
+                }
+                println!("File:             {}", path);
+                println!("{}", info);
+            }
+            (Err(err), false) => {
+                failed = true;
+                eprintln!("{}: {}", path, err);
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
@@ -1,16 +1,151 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
 use crate::cartridge::Rom;
 use crate::cpu::Mem;
+use crate::mapper::{self, Mapper, Nametable};
+use crate::save;
+
+/// How often battery-backed memory is written out while running, so a crash
+/// loses at most a few seconds of progress. About five seconds of CPU time.
+const SAVE_INTERVAL_CYCLES: u32 = 5 * 1_789_773;
+
+/// 2K of console CIRAM plus the 2K more that four-screen boards carry.
+const PPU_VRAM_SIZE: usize = 0x1000;
 
 pub struct Bus {
     cpu_vram: [u8; 2048],
-    rom: Rom,
+    ppu_vram: [u8; PPU_VRAM_SIZE],
+    mapper: Box<dyn Mapper>,
+    save_path: Option<PathBuf>,
+    // battery-backed memory as of the last write to the save file
+    saved: Option<Vec<u8>>,
+    cycles_since_save: u32,
+    cycles: u64,
 }
 
 impl Bus {
     pub fn new(rom: Rom) -> Self {
+        Bus::with_mapper(mapper::new(rom))
+    }
+
+    /// A bus around hardware that isn't a cartridge, such as an NSF player.
+    pub fn with_mapper(mapper: Box<dyn Mapper>) -> Self {
         Bus {
             cpu_vram: [0; 2048],
-            rom: rom,
+            ppu_vram: [0; PPU_VRAM_SIZE],
+            mapper,
+            save_path: None,
+            saved: None,
+            cycles_since_save: 0,
+            cycles: 0,
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            self.mapper.clock_cpu();
+        }
+        self.cycles += cycles as u64;
+
+        self.cycles_since_save += cycles as u32;
+        if self.cycles_since_save >= SAVE_INTERVAL_CYCLES {
+            self.cycles_since_save = 0;
+            if let Err(err) = self.flush_save() {
+                eprintln!("Failed to write save file: {}", err);
+            }
+        }
+    }
+
+    /// CPU cycles run since power on.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn poll_irq(&self) -> bool {
+        self.mapper.irq()
+    }
+
+    pub fn expansion_audio(&self) -> f32 {
+        self.mapper.audio_output()
+    }
+
+    pub fn disk_sides(&self) -> usize {
+        self.mapper.disk_sides()
+    }
+
+    /// Ejects the disk (`None`) or switches to another side. Disk System games ask
+    /// for this when they need a different side.
+    pub fn insert_disk(&mut self, side: Option<usize>) {
+        self.mapper.insert_disk(side);
+    }
+
+    /// A fetch on the PPU bus: pattern tables come from the cartridge, nametables
+    /// from VRAM or whatever the cartridge maps there. There is no PPU yet to
+    /// make these fetches, but going through here is what lets mappers that
+    /// watch the PPU bus (A12, tile latches, MMC5's split and fill modes) see
+    /// them. Palette RAM lives in the PPU, so $3F00-$3FFF isn't handled here.
+    pub fn ppu_read(&mut self, addr: u16) -> u8 {
+        let addr = addr & 0x3FFF;
+        if addr < 0x2000 {
+            return self.mapper.read_chr(addr);
+        }
+        match self.mapper.read_nametable(addr) {
+            Nametable::Vram(page) => self.ppu_vram[vram_index(page, addr)],
+            Nametable::Data(data) => data,
+        }
+    }
+
+    pub fn ppu_write(&mut self, addr: u16, data: u8) {
+        let addr = addr & 0x3FFF;
+        if addr < 0x2000 {
+            self.mapper.write_chr(addr, data);
+        } else if let Some(page) = self.mapper.write_nametable(addr, data) {
+            self.ppu_vram[vram_index(page, addr)] = data;
+        }
+    }
+
+    /// The PPU putting `addr` on its bus without a fetch, as $2006 writes do.
+    pub fn ppu_address(&mut self, addr: u16) {
+        self.mapper.notify_ppu_address(addr & 0x3FFF);
+    }
+
+    /// Restores the cartridge's battery-backed memory from the save file at `path`,
+    /// if there is one, and keeps saving back to it: every few seconds while it
+    /// changes, and when the bus is dropped.
+    pub fn load_save(&mut self, path: &Path) -> io::Result<()> {
+        if let Some(data) = save::load(path)? {
+            self.mapper.load_battery_ram(&data);
+        }
+        self.saved = self.mapper.battery_ram().map(<[u8]>::to_vec);
+        self.save_path = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Writes the cartridge's battery-backed memory to the save file if it
+    /// changed since the last write. Does nothing for cartridges without any, or
+    /// before `load_save`.
+    pub fn flush_save(&mut self) -> io::Result<()> {
+        let (Some(path), Some(data)) = (&self.save_path, self.mapper.battery_ram()) else {
+            return Ok(());
+        };
+        if self.saved.as_deref() == Some(data) {
+            return Ok(());
+        }
+        save::store(path, data)?;
+        self.saved = Some(data.to_vec());
+        Ok(())
+    }
+}
+
+fn vram_index(page: u8, addr: u16) -> usize {
+    page as usize * 0x0400 + (addr & 0x03FF) as usize
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush_save() {
+            eprintln!("Failed to write save file: {}", err);
         }
     }
 }
@@ -19,6 +154,10 @@ const RAM: u16 = 0x0000;
 const RAM_MIRRORS_END: u16 = 0x1FFF;
 const PPU_REGISTERS: u16 = 0x2000;
 const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+const APU_REGISTERS: u16 = 0x4000;
+const APU_REGISTERS_END: u16 = 0x4017;
+const CARTRIDGE_SPACE: u16 = 0x4020;
+const CARTRIDGE_SPACE_END: u16 = 0xFFFF;
 
 impl Mem for Bus {
     fn mem_read(&self, addr: u16) -> u8 {
@@ -31,13 +170,13 @@ impl Mem for Bus {
                 let _mirror_down_addr = addr & 0b00100000_00000111;
                 todo!("PPU is not supported yet")
             }
+            // there is no APU yet
+            APU_REGISTERS..=APU_REGISTERS_END => 0,
+            CARTRIDGE_SPACE..=CARTRIDGE_SPACE_END => self.mapper.read_prg(addr),
             _ => {
                 println!("Ignoring mem access at {}", addr);
                 0
             }
-            0x8000..=0xFFFF => {
-                panic!("Attempt to write to Cartridge ROM space")
-            }
         }
     }
 
@@ -48,9 +187,12 @@ impl Mem for Bus {
                 self.cpu_vram[mirror_down_addr as usize] = data;
             }
             PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                let _mirror_down_addr = addr & 0b00100000_00000111;
+                let mirror_down_addr = addr & 0b00100000_00000111;
+                self.mapper.notify_cpu_write(mirror_down_addr, data);
                 todo!("PPU is not supported yet");
             }
+            APU_REGISTERS..=APU_REGISTERS_END => {}
+            CARTRIDGE_SPACE..=CARTRIDGE_SPACE_END => self.mapper.write_prg(addr, data),
 
             _ => {
                 println!("Ignoring mem write-access at {}", addr);
@@ -58,13 +200,3 @@ impl Mem for Bus {
         }
     }
 }
-
-impl Bus {
-    fn read_prg_rom(&self, mut addr: u16) -> u8 {
-        addr -= 0x8000;
-        if self.rom.prg_rom.len() == 0x4000 && addr >= 0x4000 {
-            addr = addr % 0x4000;
-        }
-        self.rom.prg_rom[addr as usize]
-    }
-}
@@ -1,55 +1,851 @@
+use std::fmt;
+
+use crate::fds;
+use crate::gamedb::{self, HeaderFix};
+use crate::mapper;
+use crate::unif;
+
 const NES_TAG: [u8; 4] = [0x4e, 0x45, 0x53, 0x1a];
 const PRG_ROM_PAGE_SIZE: usize = 16384;
 const CHR_ROM_PAGE_SIZE: usize = 8192;
+const PRG_RAM_PAGE_SIZE: usize = 8192;
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[allow(non_camel_case_types)]
 pub enum Mirroring {
     VERTICAL,
     HORIZONTAL,
     FOUR_SCREEN,
+    SINGLE_SCREEN_LOWER,
+    SINGLE_SCREEN_UPPER,
+}
+
+/// CPU/PPU timing the game was made for.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum Timing {
+    NTSC,
+    PAL,
+    /// Runs on both NTSC and PAL consoles.
+    MULTI_REGION,
+    DENDY,
+}
+
+/// The console the cartridge is meant for.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum ConsoleType {
+    NES,
+    /// Vs. System arcade board. `ppu` is the NES 2.0 Vs. PPU type (0 for RP2C03B,
+    /// 2-5 for the RP2C04 palettes, ...) and `hardware` the Vs. hardware type (0 for
+    /// a normal Unisystem, 5 for a normal Dual System, ...); both 0 for iNES headers.
+    VS_SYSTEM {
+        ppu: u8,
+        hardware: u8,
+    },
+    PLAYCHOICE_10,
+    /// NES 2.0 extended console type from byte 13, e.g. 3 for VT01 famiclones.
+    EXTENDED(u8),
+}
+
+/// Why a ROM image couldn't be loaded. Offsets and lengths are in bytes from the
+/// start of the file.
+#[derive(Debug, PartialEq, Clone)]
+pub enum RomError {
+    /// The file is shorter than the 16-byte header.
+    TruncatedHeader {
+        len: usize,
+    },
+    /// The file doesn't start with "NES\x1A".
+    BadMagic {
+        found: Vec<u8>,
+    },
+    /// The header declares a trainer the file doesn't hold.
+    TruncatedTrainer {
+        offset: usize,
+        available: usize,
+    },
+    /// The file ends before the PRG-ROM the header declares does.
+    TruncatedPrg {
+        offset: usize,
+        expected: usize,
+        available: usize,
+    },
+    /// The file ends before the CHR-ROM the header declares does.
+    TruncatedChr {
+        offset: usize,
+        expected: usize,
+        available: usize,
+    },
+    /// There are bytes after the last ROM the header declares, usually an overdump.
+    /// Only `Rom::new_strict` refuses them.
+    TrailingData {
+        offset: usize,
+        len: usize,
+    },
+    UnsupportedMapper {
+        mapper: u16,
+        submapper: u8,
+    },
+    /// The ROM has a trainer but the board has no RAM at $7000 to load it into.
+    UnsupportedTrainer {
+        mapper: u16,
+    },
+    /// A UNIF chunk runs past the end of the file.
+    TruncatedChunk {
+        id: String,
+        offset: usize,
+        expected: usize,
+        available: usize,
+    },
+    /// A UNIF file lacks a chunk every cartridge needs.
+    MissingChunk {
+        id: &'static str,
+    },
+    /// A UNIF board name with no mapper implementing it.
+    UnsupportedBoard {
+        name: String,
+    },
+    /// The FDS BIOS isn't the 8K it should be.
+    BadBios {
+        len: usize,
+    },
+    /// An FDS disk image ends partway through a side.
+    TruncatedDiskSide {
+        side: usize,
+        offset: usize,
+        available: usize,
+    },
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::TruncatedHeader { len } => {
+                write!(f, "File is {} bytes, too short for an iNES header", len)
+            }
+            RomError::BadMagic { found } => {
+                write!(f, "File is not in iNES file format (magic {:02X?})", found)
+            }
+            RomError::TruncatedTrainer { offset, available } => write!(
+                f,
+                "Trainer at offset {} is truncated: {} of {} bytes present",
+                offset, available, TRAINER_SIZE
+            ),
+            RomError::TruncatedPrg {
+                offset,
+                expected,
+                available,
+            } => write!(
+                f,
+                "PRG-ROM at offset {} is truncated: {} of {} bytes present",
+                offset, available, expected
+            ),
+            RomError::TruncatedChr {
+                offset,
+                expected,
+                available,
+            } => write!(
+                f,
+                "CHR-ROM at offset {} is truncated: {} of {} bytes present",
+                offset, available, expected
+            ),
+            RomError::TrailingData { offset, len } => {
+                write!(f, "{} bytes of unexpected data at offset {}", len, offset)
+            }
+            RomError::UnsupportedMapper { mapper, submapper } => write!(
+                f,
+                "Mapper {} (submapper {}) is not supported",
+                mapper, submapper
+            ),
+            RomError::UnsupportedTrainer { mapper } => {
+                write!(f, "Mapper {} has no RAM at $7000 for the trainer", mapper)
+            }
+            RomError::TruncatedChunk {
+                id,
+                offset,
+                expected,
+                available,
+            } => write!(
+                f,
+                "{} chunk at offset {} is truncated: {} of {} bytes present",
+                id, offset, available, expected
+            ),
+            RomError::MissingChunk { id } => write!(f, "File has no {} chunk", id),
+            RomError::UnsupportedBoard { name } => write!(f, "Board {} is not supported", name),
+            RomError::BadBios { len } => {
+                write!(f, "FDS BIOS is {} bytes instead of {}", len, fds::BIOS_SIZE)
+            }
+            RomError::TruncatedDiskSide {
+                side,
+                offset,
+                available,
+            } => write!(
+                f,
+                "Disk side {} at offset {} is truncated: {} of {} bytes present",
+                side,
+                offset,
+                available,
+                fds::SIDE_SIZE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+/// Splits `len` bytes at `offset` off `raw`, or reports how many are actually there.
+pub(crate) fn section(raw: &[u8], offset: usize, len: usize) -> Result<&[u8], usize> {
+    let available = raw.len().saturating_sub(offset);
+    if len <= available {
+        Ok(&raw[offset..offset + len])
+    } else {
+        Err(available)
+    }
 }
 
+#[derive(Debug, PartialEq, Clone)]
 pub struct Rom {
     pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
-    pub mapper: u8,
+    /// The 512-byte trainer (flags 6 bit 2), preloaded into the work RAM at
+    /// $7000-$71FF on power-on.
+    pub trainer: Option<Vec<u8>>,
+    /// Mapper number, up to 12 bits with NES 2.0 headers.
+    pub mapper: u16,
+    /// NES 2.0 submapper, 0 for plain iNES headers.
+    pub submapper: u8,
     pub screen_mirroring: Mirroring,
+    /// Flags 6 bit 0 as written. Four-screen mirroring normally overrides it, but
+    /// UNROM 512 (mapper 30) uses it to tell true four-screen boards from ones
+    /// with switchable one-screen mirroring.
+    pub vertical_mirroring_bit: bool,
+    /// Size of the volatile work RAM at $6000-$7FFF.
+    pub prg_ram_size: usize,
+    /// Size of the battery-backed work RAM at $6000-$7FFF. iNES headers can't tell
+    /// the two apart, so with the battery bit set all of it counts as NVRAM.
+    pub prg_nvram_size: usize,
+    /// Size of the CHR-RAM. iNES headers imply 8K when there is no CHR-ROM.
+    pub chr_ram_size: usize,
+    /// Size of the battery-backed CHR-RAM.
+    pub chr_nvram_size: usize,
+    /// The cartridge keeps its memory powered with a battery (flags 6 bit 1), so
+    /// it should be saved between sessions.
+    pub battery: bool,
+    /// Emulate bus conflicts on discrete logic boards (UxROM, CNROM, AxROM), where a
+    /// write to ROM space is ANDed with the byte the ROM drives at that address.
+    /// NES 2.0 headers declare them with submapper 2; for iNES ones they come from
+    /// the game database.
+    pub bus_conflicts: bool,
+    /// The header is in NES 2.0 format rather than iNES.
+    pub nes2: bool,
+    /// The header is archaic iNES, with only the lower mapper nibble and no flags
+    /// past byte 6.
+    pub archaic: bool,
+    pub timing: Timing,
+    pub console_type: ConsoleType,
+    /// Number of miscellaneous ROMs (NES 2.0 byte 14) after the CHR-ROM.
+    pub misc_roms: u8,
+    /// NES 2.0 default expansion device (byte 15), e.g. 1 for standard controllers
+    /// or 8 for the Zapper. 0 means unspecified.
+    pub expansion_device: u8,
+    /// Header fields the game database corrected for this dump.
+    pub header_fixes: Vec<HeaderFix>,
+    /// Famicom Disk System disk sides in `.fds` layout; empty for cartridges.
+    pub disk_sides: Vec<Vec<u8>>,
+}
+
+/// Decodes an NES 2.0 ROM size. With the most significant nibble at 0xF the
+/// least significant byte holds an exponent and multiplier instead: 2^E * (MM*2+1)
+/// bytes.
+fn nes2_rom_size(lsb: u8, msb: u8, page_size: usize) -> usize {
+    if msb == 0xF {
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        1usize
+            .checked_shl((lsb >> 2) as u32)
+            .map_or(usize::MAX, |size| size.saturating_mul(multiplier))
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * page_size
+    }
+}
+
+/// NES 2.0 submapper 2 of the discrete logic mappers marks boards with bus
+/// conflicts, submapper 1 boards without.
+fn has_bus_conflicts(mapper: u16, submapper: u8) -> bool {
+    matches!(mapper, 2 | 3 | 7) && submapper == 2
+}
+
+/// Decodes an NES 2.0 RAM size shift count: 64 << shift bytes, or none for 0.
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+/// Encodes an NES 2.0 ROM size as its least significant byte and most
+/// significant nibble, using the exponent-multiplier form when it isn't a whole
+/// number of pages or too many of them.
+fn nes2_rom_size_bytes(size: usize, page_size: usize) -> (u8, u8) {
+    let pages = size / page_size;
+    if size.is_multiple_of(page_size) && pages < 0xF00 {
+        return (pages as u8, (pages >> 8) as u8);
+    }
+    let exponent = size.trailing_zeros();
+    let multiplier = size >> exponent;
+    assert!(
+        exponent < 64 && multiplier <= 7,
+        "ROM size {} can't be encoded in an NES 2.0 header",
+        size
+    );
+    ((exponent << 2) as u8 | (multiplier >> 1) as u8, 0xF)
+}
+
+/// The NES 2.0 shift count for a RAM size, the inverse of `nes2_ram_size`.
+fn nes2_ram_shift(size: usize) -> u8 {
+    if size == 0 {
+        return 0;
+    }
+    assert!(
+        size.is_power_of_two() && size >= 128,
+        "RAM size {} can't be encoded in an NES 2.0 header",
+        size
+    );
+    (size.trailing_zeros() - 6) as u8
+}
+
+fn ines_pages(size: usize, page_size: usize, what: &str) -> u8 {
+    assert!(
+        size.is_multiple_of(page_size) && size / page_size < 0x100,
+        "{} size {} can't be encoded in an iNES header",
+        what,
+        size
+    );
+    (size / page_size) as u8
 }
 
 impl Rom {
-    pub fn new(raw: &Vec<u8>) -> Result<Rom, String> {
-        if &raw[0..4] != NES_TAG {
-            return Err("File is not in iNES file format".to_string());
+    /// Loads an image in any of the supported formats, told apart by their magic:
+    /// iNES/NES 2.0 or UNIF. Disk images need a BIOS as well, see `fds::load`.
+    pub fn load(raw: &[u8]) -> Result<Rom, RomError> {
+        if raw.starts_with(unif::MAGIC) {
+            unif::load(raw)
+        } else {
+            Rom::parse(raw, false)
+        }
+    }
+
+    /// Parses an iNES or NES 2.0 image. Anything after the ROM data is ignored,
+    /// since plenty of overdumps padded with junk are around.
+    pub fn new(raw: &Vec<u8>) -> Result<Rom, RomError> {
+        Rom::parse(raw, false)
+    }
+
+    /// Like `new`, but refuses files with data after the ROMs, for checking dumps.
+    pub fn new_strict(raw: &[u8]) -> Result<Rom, RomError> {
+        Rom::parse(raw, true)
+    }
+
+    fn parse(raw: &[u8], strict: bool) -> Result<Rom, RomError> {
+        if raw.len() < HEADER_SIZE {
+            return Err(RomError::TruncatedHeader { len: raw.len() });
+        }
+        if raw[0..4] != NES_TAG {
+            return Err(RomError::BadMagic {
+                found: raw[0..4].to_vec(),
+            });
         }
 
-        let mapper = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
+        let nes2 = (raw[7] >> 2) & 0b11 == 2;
+        // Headers from before byte 7 was defined often have junk from byte 7 on,
+        // like a "DiskDude!" signature. Only bytes 4-6 can be trusted in those.
+        let archaic = !nes2 && ((raw[7] >> 2) & 0b11 == 1 || raw[12..16] != [0; 4]);
+        let mut header = [0; HEADER_SIZE];
+        header.copy_from_slice(&raw[..HEADER_SIZE]);
+        if archaic {
+            header[7..].fill(0);
+        }
 
-        let ines_ver = (raw[7] >> 2) & 0b11;
-        if ines_ver != 0 {
-            return Err("NES2.0 format is not supported".to_string());
+        let mut mapper = ((header[7] & 0b1111_0000) | (header[6] >> 4)) as u16;
+        let mut submapper = 0;
+        if nes2 {
+            mapper |= ((header[8] & 0b1111) as u16) << 8;
+            submapper = header[8] >> 4;
         }
 
-        let four_screen = raw[6] & 0b1000 != 0;
-        let vertical_mirroring = raw[6] & 0b1 != 0;
+        let four_screen = header[6] & 0b1000 != 0;
+        let vertical_mirroring = header[6] & 0b1 != 0;
         let screen_mirroring = match (four_screen, vertical_mirroring) {
             (true, _) => Mirroring::FOUR_SCREEN,
             (false, true) => Mirroring::VERTICAL,
             (false, false) => Mirroring::HORIZONTAL,
         };
 
-        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
-        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+        let battery = header[6] & 0b10 != 0;
 
-        let skip_trainer = raw[6] & 0b100 != 0;
+        let prg_rom_size;
+        let chr_rom_size;
+        let prg_ram_size;
+        let prg_nvram_size;
+        let chr_ram_size;
+        let chr_nvram_size;
+        let timing;
+        let console_type;
+        let misc_roms;
+        let expansion_device;
+        if nes2 {
+            prg_rom_size = nes2_rom_size(header[4], header[9] & 0b1111, PRG_ROM_PAGE_SIZE);
+            chr_rom_size = nes2_rom_size(header[5], header[9] >> 4, CHR_ROM_PAGE_SIZE);
+            prg_ram_size = nes2_ram_size(header[10] & 0b1111);
+            prg_nvram_size = nes2_ram_size(header[10] >> 4);
+            chr_ram_size = nes2_ram_size(header[11] & 0b1111);
+            chr_nvram_size = nes2_ram_size(header[11] >> 4);
+            timing = match header[12] & 0b11 {
+                0 => Timing::NTSC,
+                1 => Timing::PAL,
+                2 => Timing::MULTI_REGION,
+                _ => Timing::DENDY,
+            };
+            console_type = match header[7] & 0b11 {
+                0 => ConsoleType::NES,
+                1 => ConsoleType::VS_SYSTEM {
+                    ppu: header[13] & 0b1111,
+                    hardware: header[13] >> 4,
+                },
+                2 => ConsoleType::PLAYCHOICE_10,
+                _ => ConsoleType::EXTENDED(header[13] & 0b1111),
+            };
+            misc_roms = header[14] & 0b11;
+            expansion_device = header[15] & 0b11_1111;
+        } else {
+            prg_rom_size = header[4] as usize * PRG_ROM_PAGE_SIZE;
+            chr_rom_size = header[5] as usize * CHR_ROM_PAGE_SIZE;
+            // 0 means 8K, for compatibility with headers from before the field existed
+            let work_ram_size = header[8].max(1) as usize * PRG_RAM_PAGE_SIZE;
+            (prg_ram_size, prg_nvram_size) = if battery {
+                (0, work_ram_size)
+            } else {
+                (work_ram_size, 0)
+            };
+            chr_ram_size = if chr_rom_size == 0 {
+                CHR_ROM_PAGE_SIZE
+            } else {
+                0
+            };
+            chr_nvram_size = 0;
+            timing = if header[9] & 1 != 0 {
+                Timing::PAL
+            } else {
+                Timing::NTSC
+            };
+            console_type = match header[7] & 0b11 {
+                1 => ConsoleType::VS_SYSTEM {
+                    ppu: 0,
+                    hardware: 0,
+                },
+                2 => ConsoleType::PLAYCHOICE_10,
+                _ => ConsoleType::NES,
+            };
+            misc_roms = 0;
+            expansion_device = 0;
+        }
 
-        let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
+        let has_trainer = header[6] & 0b100 != 0;
+
+        let trainer = if has_trainer {
+            let trainer = section(raw, HEADER_SIZE, TRAINER_SIZE).map_err(|available| {
+                RomError::TruncatedTrainer {
+                    offset: HEADER_SIZE,
+                    available,
+                }
+            })?;
+            Some(trainer.to_vec())
+        } else {
+            None
+        };
+        let prg_rom_start = HEADER_SIZE + trainer.as_ref().map_or(0, Vec::len);
+        let prg_rom = section(raw, prg_rom_start, prg_rom_size).map_err(|available| {
+            RomError::TruncatedPrg {
+                offset: prg_rom_start,
+                expected: prg_rom_size,
+                available,
+            }
+        })?;
         let chr_rom_start = prg_rom_start + prg_rom_size;
+        let chr_rom = section(raw, chr_rom_start, chr_rom_size).map_err(|available| {
+            RomError::TruncatedChr {
+                offset: chr_rom_start,
+                expected: chr_rom_size,
+                available,
+            }
+        })?;
+
+        // NES 2.0 miscellaneous ROMs fill the rest of the file
+        let end = chr_rom_start + chr_rom_size;
+        if end < raw.len() && misc_roms == 0 && strict {
+            return Err(RomError::TrailingData {
+                offset: end,
+                len: raw.len() - end,
+            });
+        }
+
+        let mut rom = Rom {
+            prg_rom: prg_rom.to_vec(),
+            chr_rom: chr_rom.to_vec(),
+            trainer,
+            mapper,
+            submapper,
+            screen_mirroring,
+            vertical_mirroring_bit: vertical_mirroring,
+            prg_ram_size,
+            prg_nvram_size,
+            chr_ram_size,
+            chr_nvram_size,
+            battery,
+            bus_conflicts: nes2 && has_bus_conflicts(mapper, submapper),
+            nes2,
+            archaic,
+            timing,
+            console_type,
+            misc_roms,
+            expansion_device,
+            header_fixes: Vec::new(),
+            disk_sides: Vec::new(),
+        };
+
+        // NES 2.0 headers are written with care, iNES ones often aren't
+        if !nes2 {
+            rom.header_fixes = gamedb::correct(&mut rom);
+        }
+
+        rom.check_mapper()?;
+        Ok(rom)
+    }
+
+    // Refuses cartridges `mapper::new` can't build.
+    fn check_mapper(&self) -> Result<(), RomError> {
+        if !mapper::is_supported(self.mapper, self.submapper) {
+            return Err(RomError::UnsupportedMapper {
+                mapper: self.mapper,
+                submapper: self.submapper,
+            });
+        }
+        if self.trainer.is_some() && !mapper::supports_trainer(self.mapper) {
+            return Err(RomError::UnsupportedTrainer {
+                mapper: self.mapper,
+            });
+        }
+        Ok(())
+    }
+
+    /// Serializes the cartridge as a `.nes` file, with a NES 2.0 header if `nes2`
+    /// is set and an iNES one otherwise. `Rom::new` reads it back as the same
+    /// cartridge.
+    ///
+    /// Panics if a size can't be encoded in the header: iNES needs whole 16K PRG
+    /// and 8K CHR pages and work RAM in 8K pages, NES 2.0 RAM sizes are powers of
+    /// two. iNES headers have no room for mappers above 255, submappers, Vs.
+    /// System types or the NES 2.0 fields from byte 10 on, and single-screen
+    /// mirroring is written as horizontal since only the mapper can select it.
+    pub fn to_ines(&self) -> Vec<u8> {
+        let mut header = [0; HEADER_SIZE];
+        header[0..4].copy_from_slice(&NES_TAG);
+        header[6] = ((self.mapper as u8 & 0b1111) << 4)
+            | match self.screen_mirroring {
+                Mirroring::VERTICAL => 0b1,
+                Mirroring::FOUR_SCREEN => 0b1000 | self.vertical_mirroring_bit as u8,
+                _ => 0,
+            };
+        if self.battery {
+            header[6] |= 0b10;
+        }
+        if self.trainer.is_some() {
+            header[6] |= 0b100;
+        }
+        header[7] = (self.mapper as u8 & 0b1111_0000)
+            | match self.console_type {
+                ConsoleType::NES => 0,
+                ConsoleType::VS_SYSTEM { .. } => 1,
+                ConsoleType::PLAYCHOICE_10 => 2,
+                ConsoleType::EXTENDED(_) => 3,
+            };
+
+        if self.nes2 {
+            header[7] |= 0b1000;
+            let (prg_lsb, prg_msb) = nes2_rom_size_bytes(self.prg_rom.len(), PRG_ROM_PAGE_SIZE);
+            let (chr_lsb, chr_msb) = nes2_rom_size_bytes(self.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+            header[4] = prg_lsb;
+            header[5] = chr_lsb;
+            header[8] = ((self.mapper >> 8) as u8 & 0b1111) | (self.submapper << 4);
+            header[9] = prg_msb | (chr_msb << 4);
+            header[10] =
+                nes2_ram_shift(self.prg_ram_size) | (nes2_ram_shift(self.prg_nvram_size) << 4);
+            header[11] =
+                nes2_ram_shift(self.chr_ram_size) | (nes2_ram_shift(self.chr_nvram_size) << 4);
+            header[12] = match self.timing {
+                Timing::NTSC => 0,
+                Timing::PAL => 1,
+                Timing::MULTI_REGION => 2,
+                Timing::DENDY => 3,
+            };
+            header[13] = match self.console_type {
+                ConsoleType::VS_SYSTEM { ppu, hardware } => ppu | (hardware << 4),
+                ConsoleType::EXTENDED(console) => console,
+                _ => 0,
+            };
+            header[14] = self.misc_roms;
+            header[15] = self.expansion_device;
+        } else {
+            assert!(
+                self.mapper < 0x100,
+                "Mapper {} can't be encoded in an iNES header",
+                self.mapper
+            );
+            header[4] = ines_pages(self.prg_rom.len(), PRG_ROM_PAGE_SIZE, "PRG-ROM");
+            header[5] = ines_pages(self.chr_rom.len(), CHR_ROM_PAGE_SIZE, "CHR-ROM");
+            header[8] = ines_pages(
+                self.prg_ram_size + self.prg_nvram_size,
+                PRG_RAM_PAGE_SIZE,
+                "PRG-RAM",
+            );
+            if self.timing == Timing::PAL {
+                header[9] = 1;
+            }
+        }
+
+        let mut raw = header.to_vec();
+        if let Some(trainer) = &self.trainer {
+            raw.extend_from_slice(trainer);
+        }
+        raw.extend_from_slice(&self.prg_rom);
+        raw.extend_from_slice(&self.chr_rom);
+        raw
+    }
+}
+
+/// Puts a cartridge together from its ROM data, for tests and for tools that
+/// make ROMs, without writing header bytes by hand.
+///
+/// Anything not set gets the value `Rom::new` would read from a header that
+/// leaves it out: mapper 0, horizontal mirroring, NTSC, 8K of work RAM (battery
+/// backed if there's a battery) and 8K of CHR-RAM when there is no CHR-ROM.
+#[derive(Debug, Clone)]
+pub struct RomBuilder {
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+    trainer: Option<Vec<u8>>,
+    mapper: u16,
+    submapper: u8,
+    mirroring: Mirroring,
+    vertical_mirroring_bit: bool,
+    battery: bool,
+    nes2: bool,
+    prg_ram_size: Option<usize>,
+    prg_nvram_size: Option<usize>,
+    chr_ram_size: Option<usize>,
+    chr_nvram_size: usize,
+    timing: Timing,
+    console_type: ConsoleType,
+    expansion_device: u8,
+}
+
+impl RomBuilder {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>) -> Self {
+        RomBuilder {
+            prg_rom,
+            chr_rom,
+            trainer: None,
+            mapper: 0,
+            submapper: 0,
+            mirroring: Mirroring::HORIZONTAL,
+            vertical_mirroring_bit: false,
+            battery: false,
+            nes2: false,
+            prg_ram_size: None,
+            prg_nvram_size: None,
+            chr_ram_size: None,
+            chr_nvram_size: 0,
+            timing: Timing::NTSC,
+            console_type: ConsoleType::NES,
+            expansion_device: 0,
+        }
+    }
+
+    pub fn mapper(mut self, mapper: u16) -> Self {
+        self.mapper = mapper;
+        self
+    }
+
+    /// Needs an NES 2.0 header, see `nes2`.
+    pub fn submapper(mut self, submapper: u8) -> Self {
+        self.submapper = submapper;
+        self
+    }
+
+    pub fn mirroring(mut self, mirroring: Mirroring) -> Self {
+        self.mirroring = mirroring;
+        self
+    }
+
+    /// Sets flags 6 bit 0 along with four-screen mirroring, see
+    /// `Rom::vertical_mirroring_bit`.
+    pub fn vertical_mirroring_bit(mut self, bit: bool) -> Self {
+        self.vertical_mirroring_bit = bit;
+        self
+    }
+
+    pub fn battery(mut self, battery: bool) -> Self {
+        self.battery = battery;
+        self
+    }
+
+    pub fn trainer(mut self, trainer: [u8; TRAINER_SIZE]) -> Self {
+        self.trainer = Some(trainer.to_vec());
+        self
+    }
+
+    /// Writes an NES 2.0 header rather than an iNES one, which the submapper,
+    /// separate RAM sizes, Vs. System types and expansion device need.
+    pub fn nes2(mut self, nes2: bool) -> Self {
+        self.nes2 = nes2;
+        self
+    }
+
+    /// Volatile work RAM. iNES headers only hold the total work RAM, all of it
+    /// battery-backed if there's a battery.
+    pub fn prg_ram_size(mut self, size: usize) -> Self {
+        self.prg_ram_size = Some(size);
+        self
+    }
+
+    pub fn prg_nvram_size(mut self, size: usize) -> Self {
+        self.prg_nvram_size = Some(size);
+        self
+    }
+
+    pub fn chr_ram_size(mut self, size: usize) -> Self {
+        self.chr_ram_size = Some(size);
+        self
+    }
+
+    pub fn chr_nvram_size(mut self, size: usize) -> Self {
+        self.chr_nvram_size = size;
+        self
+    }
+
+    /// iNES headers only tell PAL from NTSC; other timings need NES 2.0.
+    pub fn timing(mut self, timing: Timing) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    pub fn console_type(mut self, console_type: ConsoleType) -> Self {
+        self.console_type = console_type;
+        self
+    }
+
+    pub fn expansion_device(mut self, expansion_device: u8) -> Self {
+        self.expansion_device = expansion_device;
+        self
+    }
+
+    /// The cartridge `Rom::new` would load from `to_ines`, or the same error for a
+    /// mapper it can't run.
+    pub fn build(self) -> Result<Rom, RomError> {
+        let rom = self.rom();
+        rom.check_mapper()?;
+        Ok(rom)
+    }
+
+    /// The `.nes` file for the cartridge, see `Rom::to_ines`. Any mapper number
+    /// is written, for making files `Rom::new` should refuse.
+    pub fn to_ines(&self) -> Vec<u8> {
+        self.clone().rom().to_ines()
+    }
+
+    fn rom(self) -> Rom {
+        let default_chr_ram = if self.chr_rom.is_empty() {
+            CHR_ROM_PAGE_SIZE
+        } else {
+            0
+        };
+        let (prg_ram_size, prg_nvram_size, chr_ram_size, chr_nvram_size);
+        if self.nes2 {
+            let default_ram = |battery: bool| {
+                if battery == self.battery {
+                    PRG_RAM_PAGE_SIZE
+                } else {
+                    0
+                }
+            };
+            prg_ram_size = self.prg_ram_size.unwrap_or(default_ram(false));
+            prg_nvram_size = self.prg_nvram_size.unwrap_or(default_ram(true));
+            chr_ram_size = self.chr_ram_size.unwrap_or(default_chr_ram);
+            chr_nvram_size = self.chr_nvram_size;
+        } else {
+            // the way `Rom::new` reads the header `to_ines` writes
+            let work_ram_size = self.prg_ram_size.unwrap_or(0) + self.prg_nvram_size.unwrap_or(0);
+            let work_ram_size = work_ram_size.max(PRG_RAM_PAGE_SIZE);
+            (prg_ram_size, prg_nvram_size) = if self.battery {
+                (0, work_ram_size)
+            } else {
+                (work_ram_size, 0)
+            };
+            chr_ram_size = default_chr_ram;
+            chr_nvram_size = 0;
+        }
+        Rom {
+            prg_rom: self.prg_rom,
+            chr_rom: self.chr_rom,
+            trainer: self.trainer,
+            mapper: self.mapper,
+            submapper: if self.nes2 { self.submapper } else { 0 },
+            screen_mirroring: self.mirroring,
+            vertical_mirroring_bit: match self.mirroring {
+                Mirroring::FOUR_SCREEN => self.vertical_mirroring_bit,
+                mirroring => mirroring == Mirroring::VERTICAL,
+            },
+            prg_ram_size,
+            prg_nvram_size,
+            chr_ram_size,
+            chr_nvram_size,
+            battery: self.battery,
+            bus_conflicts: self.nes2 && has_bus_conflicts(self.mapper, self.submapper),
+            nes2: self.nes2,
+            archaic: false,
+            timing: match self.timing {
+                Timing::PAL if !self.nes2 => Timing::PAL,
+                _ if !self.nes2 => Timing::NTSC,
+                timing => timing,
+            },
+            console_type: match self.console_type {
+                ConsoleType::VS_SYSTEM { .. } if !self.nes2 => ConsoleType::VS_SYSTEM {
+                    ppu: 0,
+                    hardware: 0,
+                },
+                ConsoleType::EXTENDED(_) if !self.nes2 => ConsoleType::NES,
+                console_type => console_type,
+            },
+            misc_roms: 0,
+            expansion_device: if self.nes2 { self.expansion_device } else { 0 },
+            header_fixes: Vec::new(),
+            disk_sides: Vec::new(),
+        }
+    }
+}
+
+/// Cartridges for tests, behind the `test-utils` feature.
+#[cfg(feature = "test-utils")]
+pub mod test {
+    use super::*;
 
-        Ok(Rom {
-            prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
-            chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
-            mapper: mapper,
-            screen_mirroring: screen_mirroring,
-        })
+    /// An NROM cartridge with 32K of zeroed PRG-ROM and 8K of CHR-ROM.
+    pub fn test_rom() -> Rom {
+        RomBuilder::new(vec![0; 2 * PRG_ROM_PAGE_SIZE], vec![0; CHR_ROM_PAGE_SIZE])
+            .build()
+            .unwrap()
     }
 }
@@ -21,6 +21,7 @@ pub enum AddressingMode {
 
 const STACK: u16 = 0x0100;
 const STACK_RESET: u8 = 0xfd;
+const IRQ_VECTOR: u16 = 0xfffe;
 
 /// # Status Register (P) http://wiki.nesdev.com/w/index.php/Status_flags
 ///
@@ -78,11 +79,11 @@ impl Mem for CPU {
     }
 
     fn mem_write(&mut self, addr: u16, data: u8) {
-        self.mem_write(addr, data)
+        self.bus.mem_write(addr, data)
     }
 
     fn mem_read_u16(&self, pos: u16) -> u16 {
-        self.mem_read_u16(pos)
+        self.bus.mem_read_u16(pos)
     }
 
     fn mem_write_u16(&mut self, pos: u16, data: u16) {
@@ -147,177 +148,218 @@ impl CPU {
     where
         F: FnMut(&mut CPU),
     {
-        let ref opcodes: HashMap<u8, &'static opcode::OpCode> = *opcode::OPCODES_MAP;
-
         loop {
+            if self.bus.poll_irq() && self.status & INTERRUPT_DISABLE == 0 {
+                self.interrupt(IRQ_VECTOR);
+            }
+
             callback(self);
 
-            let code = self.mem_read(self.program_counter);
-            self.program_counter += 1;
+            if !self.step(true) {
+                return;
+            }
+        }
+    }
 
-            let program_counter_state = self.program_counter;
+    /// Runs the subroutine at `addr` as if it was called with JSR, and returns
+    /// once it does. Registers other than the program counter are left as the
+    /// subroutine leaves them, so they can be used to pass arguments and results.
+    pub fn call(&mut self, addr: u16) {
+        self.call_with_callback(addr, |_| {});
+    }
 
-            let opcode = *opcodes
-                .get(&code)
-                .unwrap_or_else(|| panic!("OpCode {:x} is not recognized", code));
+    pub fn call_with_callback<F>(&mut self, addr: u16, mut callback: F)
+    where
+        F: FnMut(&mut CPU),
+    {
+        let stack_pointer = self.stack_pointer;
+        // RTS lands back on the current instruction
+        self.stack_push_u16(self.program_counter.wrapping_sub(1));
+        self.program_counter = addr;
+
+        while self.stack_pointer != stack_pointer {
+            callback(self);
 
+            if !self.step(false) {
+                return;
+            }
+        }
+    }
+
+    /// Executes one instruction. Returns false on BRK.
+    fn step(&mut self, trace: bool) -> bool {
+        let opcodes: &HashMap<u8, &'static opcode::OpCode> = &opcode::OPCODES_MAP;
+
+        let code = self.mem_read(self.program_counter);
+        self.program_counter += 1;
+
+        let program_counter_state = self.program_counter;
+
+        let opcode = *opcodes
+            .get(&code)
+            .unwrap_or_else(|| panic!("OpCode {:x} is not recognized", code));
+
+        if trace {
             self.print_state();
 
             println!(
                 "opcode mnemonic: {} | opcode value: {:x}",
                 opcode.mnemonic, opcode.code
             );
+        }
 
-            match code {
-                //BRK
-                0x00 => return,
-                //ADC
-                0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => self.adc(&opcode.mode),
-                //AND
-                0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => self.and(&opcode.mode),
-                //ASL Accumulator
-                0x0a => self.asl_accumulator(),
-                //ASL
-                0x06 | 0x16 | 0x0e | 0x1e => self.asl(&opcode.mode),
-                //BCC
-                0x90 => self.branch(self.status & CARRY == 0),
-                //BCS
-                0xb0 => self.branch(self.status & CARRY != 0),
-                //BEQ
-                0xf0 => self.branch(self.status & ZERO != 0),
-                //BIT
-                0x24 | 0x2c => self.bit(&opcode.mode),
-                //BMI
-                0x30 => self.branch(self.status & NEGATIVE != 0),
-                //BNE
-                0xd0 => self.branch(self.status & ZERO == 0),
-                //BPL
-                0x10 => self.branch(self.status & NEGATIVE == 0),
-                //BVC
-                0x50 => self.branch(self.status & OVERFLOW == 0),
-                //BVS
-                0x70 => self.branch(self.status & OVERFLOW != 0),
-                //CLC
-                0x18 => self.reset_status_flag(CARRY),
-                //CLD
-                0xd8 => self.reset_status_flag(DECIMAL_MODE),
-                //CLI
-                0x58 => self.reset_status_flag(INTERRUPT_DISABLE),
-                //CLV
-                0xb8 => self.reset_status_flag(OVERFLOW),
-                //CMP
-                0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
-                    self.compare(self.accumulator, &opcode.mode)
-                }
-                //CPX
-                0xe0 | 0xe4 | 0xec => self.compare(self.register_x, &opcode.mode),
-                //CPY
-                0xc0 | 0xc4 | 0xcc => self.compare(self.register_y, &opcode.mode),
-                //DEC
-                0xc6 | 0xd6 | 0xce | 0xde => self.dec(&opcode.mode),
-                //DEX
-                0xca => self.dex(),
-                //DEY
-                0x88 => self.dey(),
-                //EOR
-                0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => self.eor(&opcode.mode),
-                //INC
-                0xe6 | 0xf6 | 0xee | 0xfe => self.inc(&opcode.mode),
-                //INX
-                0xe8 => self.inx(),
-                //INY
-                0xc8 => self.iny(),
-                //JMP Absolute
-                0x4c => {
-                    self.program_counter = self.mem_read_u16(self.program_counter);
-                }
-                //JMP Indirect
-                0x6c => {
-                    let addr = self.mem_read_u16(self.program_counter);
-
-                    let indirect = if addr & 0xff == 0xff {
-                        let low = self.mem_read(addr) as u16;
-                        let high = self.mem_read(addr & 0xFF00) as u16;
-                        self.mem_read_u16(high << 8 | low)
-                    } else {
-                        self.mem_read_u16(addr)
-                    };
-
-                    self.program_counter = indirect;
-                }
-                //JSR
-                0x20 => {
-                    self.stack_push_u16(self.program_counter + 2 - 1); //return point should be the next instruction
-                    self.program_counter = self.mem_read_u16(self.program_counter);
-                }
-                //LDA
-                0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 => self.lda(&opcode.mode),
-                //LDX
-                0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => self.ldx(&opcode.mode),
-                //LDY
-                0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => self.ldy(&opcode.mode),
-                //LSR Accumulator
-                0x4a => self.lsr_accumulator(),
-                //LSR
-                0x46 | 0x56 | 0x4e | 0x5e => self.lsr(&opcode.mode),
-                //NOP
-                0xea => self.nop(),
-                //ORA
-                0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => self.ora(&opcode.mode),
-                //PHA
-                0x48 => self.pha(),
-                //PHP
-                0x08 => self.php(),
-                //PLA
-                0x68 => self.pla(),
-                //PLP
-                0x28 => self.plp(),
-                //ROL Accumulator
-                0x2a => self.rol_accumulator(),
-                //ROL
-                0x26 | 0x36 | 0x2e | 0x3e => self.rol(&opcode.mode),
-                //ROR Accumulator
-                0x6a => self.ror_accumulator(),
-                //ROR
-                0x66 | 0x76 | 0x6e | 0x7e => self.ror(&opcode.mode),
-                //RTI
-                0x40 => self.rti(),
-                //RTS
-                0x60 => self.rts(),
-                //SBC
-                0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 => self.sbc(&opcode.mode),
-                //SEC
-                0x38 => self.set_status_flag(CARRY),
-                //SED
-                0xf8 => self.set_status_flag(DECIMAL_MODE),
-                //SEI
-                0x78 => self.set_status_flag(INTERRUPT_DISABLE),
-                //STA
-                0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => self.sta(&opcode.mode),
-                //STX
-                0x86 | 0x96 | 0x8e => self.stx(&opcode.mode),
-                //STY
-                0x84 | 0x94 | 0x8c => self.sty(&opcode.mode),
-                //TAX
-                0xaa => self.tax(),
-                //TAY
-                0xa8 => self.tay(),
-                //TSX
-                0xba => self.tsx(),
-                //TXA
-                0x8a => self.txa(),
-                //TXS
-                0x9a => self.txs(),
-                //TYA
-                0x98 => self.tya(),
-                //Not implemented case
-                _ => todo!("This is gna break if we make it here"),
+        match code {
+            //BRK
+            0x00 => return false,
+            //ADC
+            0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => self.adc(&opcode.mode),
+            //AND
+            0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => self.and(&opcode.mode),
+            //ASL Accumulator
+            0x0a => self.asl_accumulator(),
+            //ASL
+            0x06 | 0x16 | 0x0e | 0x1e => self.asl(&opcode.mode),
+            //BCC
+            0x90 => self.branch(self.status & CARRY == 0),
+            //BCS
+            0xb0 => self.branch(self.status & CARRY != 0),
+            //BEQ
+            0xf0 => self.branch(self.status & ZERO != 0),
+            //BIT
+            0x24 | 0x2c => self.bit(&opcode.mode),
+            //BMI
+            0x30 => self.branch(self.status & NEGATIVE != 0),
+            //BNE
+            0xd0 => self.branch(self.status & ZERO == 0),
+            //BPL
+            0x10 => self.branch(self.status & NEGATIVE == 0),
+            //BVC
+            0x50 => self.branch(self.status & OVERFLOW == 0),
+            //BVS
+            0x70 => self.branch(self.status & OVERFLOW != 0),
+            //CLC
+            0x18 => self.reset_status_flag(CARRY),
+            //CLD
+            0xd8 => self.reset_status_flag(DECIMAL_MODE),
+            //CLI
+            0x58 => self.reset_status_flag(INTERRUPT_DISABLE),
+            //CLV
+            0xb8 => self.reset_status_flag(OVERFLOW),
+            //CMP
+            0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
+                self.compare(self.accumulator, &opcode.mode)
             }
-
-            if self.program_counter == program_counter_state {
-                self.program_counter += (opcode.len - 1) as u16;
+            //CPX
+            0xe0 | 0xe4 | 0xec => self.compare(self.register_x, &opcode.mode),
+            //CPY
+            0xc0 | 0xc4 | 0xcc => self.compare(self.register_y, &opcode.mode),
+            //DEC
+            0xc6 | 0xd6 | 0xce | 0xde => self.dec(&opcode.mode),
+            //DEX
+            0xca => self.dex(),
+            //DEY
+            0x88 => self.dey(),
+            //EOR
+            0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => self.eor(&opcode.mode),
+            //INC
+            0xe6 | 0xf6 | 0xee | 0xfe => self.inc(&opcode.mode),
+            //INX
+            0xe8 => self.inx(),
+            //INY
+            0xc8 => self.iny(),
+            //JMP Absolute
+            0x4c => {
+                self.program_counter = self.mem_read_u16(self.program_counter);
             }
+            //JMP Indirect
+            0x6c => {
+                let addr = self.mem_read_u16(self.program_counter);
+
+                let indirect = if addr & 0xff == 0xff {
+                    let low = self.mem_read(addr) as u16;
+                    let high = self.mem_read(addr & 0xFF00) as u16;
+                    self.mem_read_u16(high << 8 | low)
+                } else {
+                    self.mem_read_u16(addr)
+                };
+
+                self.program_counter = indirect;
+            }
+            //JSR
+            0x20 => {
+                self.stack_push_u16(self.program_counter + 2 - 1); //return point should be the next instruction
+                self.program_counter = self.mem_read_u16(self.program_counter);
+            }
+            //LDA
+            0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 => self.lda(&opcode.mode),
+            //LDX
+            0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => self.ldx(&opcode.mode),
+            //LDY
+            0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => self.ldy(&opcode.mode),
+            //LSR Accumulator
+            0x4a => self.lsr_accumulator(),
+            //LSR
+            0x46 | 0x56 | 0x4e | 0x5e => self.lsr(&opcode.mode),
+            //NOP
+            0xea => self.nop(),
+            //ORA
+            0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => self.ora(&opcode.mode),
+            //PHA
+            0x48 => self.pha(),
+            //PHP
+            0x08 => self.php(),
+            //PLA
+            0x68 => self.pla(),
+            //PLP
+            0x28 => self.plp(),
+            //ROL Accumulator
+            0x2a => self.rol_accumulator(),
+            //ROL
+            0x26 | 0x36 | 0x2e | 0x3e => self.rol(&opcode.mode),
+            //ROR Accumulator
+            0x6a => self.ror_accumulator(),
+            //ROR
+            0x66 | 0x76 | 0x6e | 0x7e => self.ror(&opcode.mode),
+            //RTI
+            0x40 => self.rti(),
+            //RTS
+            0x60 => self.rts(),
+            //SBC
+            0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 => self.sbc(&opcode.mode),
+            //SEC
+            0x38 => self.set_status_flag(CARRY),
+            //SED
+            0xf8 => self.set_status_flag(DECIMAL_MODE),
+            //SEI
+            0x78 => self.set_status_flag(INTERRUPT_DISABLE),
+            //STA
+            0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => self.sta(&opcode.mode),
+            //STX
+            0x86 | 0x96 | 0x8e => self.stx(&opcode.mode),
+            //STY
+            0x84 | 0x94 | 0x8c => self.sty(&opcode.mode),
+            //TAX
+            0xaa => self.tax(),
+            //TAY
+            0xa8 => self.tay(),
+            //TSX
+            0xba => self.tsx(),
+            //TXA
+            0x8a => self.txa(),
+            //TXS
+            0x9a => self.txs(),
+            //TYA
+            0x98 => self.tya(),
+            //Not implemented case
+            _ => todo!("This is gna break if we make it here"),
         }
+
+        self.bus.tick(opcode.cycles);
+
+        if self.program_counter == program_counter_state {
+            self.program_counter += (opcode.len - 1) as u16;
+        }
+        true
     }
 
     pub fn load_and_run(&mut self, program: Vec<u8>) {
@@ -720,6 +762,15 @@ impl CPU {
         self.status &= !flag
     }
 
+    fn interrupt(&mut self, vector: u16) {
+        self.stack_push_u16(self.program_counter);
+        //B is only set when the status is pushed by BRK/PHP
+        self.stack_push((self.status & !BREAK) | BREAK2);
+        self.set_status_flag(INTERRUPT_DISABLE);
+        self.bus.tick(7);
+        self.program_counter = self.mem_read_u16(vector);
+    }
+
     fn stack_push(&mut self, value: u8) {
         self.mem_write(STACK + self.stack_pointer as u16, value);
         self.stack_pointer = self.stack_pointer.wrapping_sub(1);
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::RwLock;
+
+use crate::cartridge::{Mirroring, Rom, Timing};
+use crate::hash;
+
+/// What the database knows about a dump. Fields left `None` are trusted from the
+/// header.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Entry {
+    /// CRC-32 of the PRG-ROM followed by the CHR-ROM.
+    pub crc32: u32,
+    /// SHA-1 of the same bytes, checked as well when present.
+    pub sha1: Option<[u8; 20]>,
+    pub mapper: Option<u16>,
+    pub submapper: Option<u8>,
+    pub mirroring: Option<Mirroring>,
+    pub battery: Option<bool>,
+    pub timing: Option<Timing>,
+    /// Whether a discrete logic board has bus conflicts, which iNES headers can't
+    /// say.
+    pub bus_conflicts: Option<bool>,
+}
+
+/// A header field the database corrected, with the value from the header and
+/// the one used instead.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum HeaderFix {
+    Mapper {
+        header: u16,
+        database: u16,
+    },
+    Submapper {
+        header: u8,
+        database: u8,
+    },
+    Mirroring {
+        header: Mirroring,
+        database: Mirroring,
+    },
+    Battery {
+        header: bool,
+        database: bool,
+    },
+    Timing {
+        header: Timing,
+        database: Timing,
+    },
+}
+
+impl fmt::Display for HeaderFix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeaderFix::Mapper { header, database } => {
+                write!(f, "mapper {} corrected to {}", header, database)
+            }
+            HeaderFix::Submapper { header, database } => {
+                write!(f, "submapper {} corrected to {}", header, database)
+            }
+            HeaderFix::Mirroring { header, database } => {
+                write!(f, "mirroring {:?} corrected to {:?}", header, database)
+            }
+            HeaderFix::Battery { header, database } => {
+                write!(f, "battery {} corrected to {}", header, database)
+            }
+            HeaderFix::Timing { header, database } => {
+                write!(f, "timing {:?} corrected to {:?}", header, database)
+            }
+        }
+    }
+}
+
+// Keyed by CRC-32; entries sharing one are told apart by SHA-1.
+type Database = HashMap<u32, Vec<Entry>>;
+
+lazy_static::lazy_static! {
+    static ref DATABASE: RwLock<Database> = {
+        let mut database = Database::new();
+        for entry in parse(include_str!("../data/gamedb.txt")).expect("data/gamedb.txt") {
+            database.entry(entry.crc32).or_default().push(entry);
+        }
+        RwLock::new(database)
+    };
+}
+
+fn parse_hex(text: &str, bytes: &mut [u8]) -> Option<()> {
+    if text.len() != bytes.len() * 2 {
+        return None;
+    }
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(text.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(())
+}
+
+fn parse_entry(line: &str) -> Option<Entry> {
+    let mut fields = line.split_whitespace();
+    let mut entry = Entry {
+        crc32: u32::from_str_radix(fields.next()?, 16).ok()?,
+        ..Entry::default()
+    };
+    entry.sha1 = match fields.next()? {
+        "-" => None,
+        text => {
+            let mut sha1 = [0; 20];
+            parse_hex(text, &mut sha1)?;
+            Some(sha1)
+        }
+    };
+    for field in fields {
+        let (key, value) = field.split_once('=')?;
+        match key {
+            "mapper" => entry.mapper = Some(value.parse().ok()?),
+            "submapper" => entry.submapper = Some(value.parse().ok()?),
+            "mirroring" => {
+                entry.mirroring = Some(match value {
+                    "horizontal" => Mirroring::HORIZONTAL,
+                    "vertical" => Mirroring::VERTICAL,
+                    "four" => Mirroring::FOUR_SCREEN,
+                    _ => return None,
+                })
+            }
+            "battery" => {
+                entry.battery = Some(match value {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return None,
+                })
+            }
+            "bus_conflicts" => {
+                entry.bus_conflicts = Some(match value {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return None,
+                })
+            }
+            "timing" => {
+                entry.timing = Some(match value {
+                    "ntsc" => Timing::NTSC,
+                    "pal" => Timing::PAL,
+                    "multi" => Timing::MULTI_REGION,
+                    "dendy" => Timing::DENDY,
+                    _ => return None,
+                })
+            }
+            _ => return None,
+        }
+    }
+    Some(entry)
+}
+
+/// Parses database text in the format described in data/gamedb.txt.
+pub fn parse(text: &str) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        match parse_entry(line) {
+            Some(entry) => entries.push(entry),
+            None => return Err(format!("line {}: can't parse {:?}", number + 1, line)),
+        }
+    }
+    Ok(entries)
+}
+
+/// Adds an entry to the database, on top of the built-in ones.
+pub fn register(entry: Entry) {
+    DATABASE
+        .write()
+        .unwrap()
+        .entry(entry.crc32)
+        .or_default()
+        .push(entry);
+}
+
+/// Finds the entry for a dump from its PRG-ROM and CHR-ROM.
+pub fn lookup(prg_rom: &[u8], chr_rom: &[u8]) -> Option<Entry> {
+    let crc32 = hash::crc32_update(hash::crc32(prg_rom), chr_rom);
+    let database = DATABASE.read().unwrap();
+    let candidates = database.get(&crc32)?;
+
+    let mut sha1 = None;
+    candidates
+        .iter()
+        .rev()
+        .find(|entry| match entry.sha1 {
+            None => true,
+            Some(expected) => {
+                let actual = *sha1.get_or_insert_with(|| hash::sha1(&[prg_rom, chr_rom].concat()));
+                actual == expected
+            }
+        })
+        .cloned()
+}
+
+/// Applies the database entry for `rom`, if there is one, and returns what it
+/// changed. Bus conflicts aren't in the header, so setting them isn't reported.
+pub fn correct(rom: &mut Rom) -> Vec<HeaderFix> {
+    let Some(entry) = lookup(&rom.prg_rom, &rom.chr_rom) else {
+        return Vec::new();
+    };
+
+    let mut fixes = Vec::new();
+    if let Some(mapper) = entry.mapper.filter(|&mapper| mapper != rom.mapper) {
+        fixes.push(HeaderFix::Mapper {
+            header: rom.mapper,
+            database: mapper,
+        });
+        rom.mapper = mapper;
+    }
+    if let Some(submapper) = entry.submapper.filter(|&sub| sub != rom.submapper) {
+        fixes.push(HeaderFix::Submapper {
+            header: rom.submapper,
+            database: submapper,
+        });
+        rom.submapper = submapper;
+    }
+    if let Some(mirroring) = entry
+        .mirroring
+        .filter(|&mirroring| mirroring != rom.screen_mirroring)
+    {
+        fixes.push(HeaderFix::Mirroring {
+            header: rom.screen_mirroring,
+            database: mirroring,
+        });
+        rom.screen_mirroring = mirroring;
+        if mirroring != Mirroring::FOUR_SCREEN {
+            rom.vertical_mirroring_bit = mirroring == Mirroring::VERTICAL;
+        }
+    }
+    if let Some(battery) = entry.battery.filter(|&battery| battery != rom.battery) {
+        fixes.push(HeaderFix::Battery {
+            header: rom.battery,
+            database: battery,
+        });
+        rom.battery = battery;
+        // the work RAM the header declared moves to the side the battery is on
+        let work_ram_size = rom.prg_ram_size + rom.prg_nvram_size;
+        (rom.prg_ram_size, rom.prg_nvram_size) = if battery {
+            (0, work_ram_size)
+        } else {
+            (work_ram_size, 0)
+        };
+    }
+    if let Some(timing) = entry.timing.filter(|&timing| timing != rom.timing) {
+        fixes.push(HeaderFix::Timing {
+            header: rom.timing,
+            database: timing,
+        });
+        rom.timing = timing;
+    }
+    if let Some(bus_conflicts) = entry.bus_conflicts {
+        rom.bus_conflicts = bus_conflicts;
+    }
+    fixes
+}
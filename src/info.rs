@@ -0,0 +1,183 @@
+//! Everything loading a ROM file learns about it, as text or JSON, for
+//! cataloguing ROMs and for bug reports.
+
+use std::fmt;
+
+use crate::cartridge::{ConsoleType, Rom, RomError};
+use crate::{hash, mapper, unif};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum Format {
+    ARCHAIC_INES,
+    INES,
+    NES2,
+    UNIF,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Format::ARCHAIC_INES => "archaic iNES",
+            Format::INES => "iNES",
+            Format::NES2 => "NES 2.0",
+            Format::UNIF => "UNIF",
+        })
+    }
+}
+
+pub struct RomInfo {
+    pub format: Format,
+    pub rom: Rom,
+    /// CRC-32 of the whole file.
+    pub file_crc32: u32,
+    /// CRC-32 and SHA-1 of the PRG-ROM followed by the CHR-ROM, the way the game
+    /// database identifies dumps.
+    pub crc32: u32,
+    pub sha1: [u8; 20],
+}
+
+fn console(console_type: ConsoleType) -> String {
+    match console_type {
+        ConsoleType::VS_SYSTEM { ppu, hardware } => {
+            format!("VS_SYSTEM (PPU {}, hardware {})", ppu, hardware)
+        }
+        ConsoleType::EXTENDED(console) => format!("EXTENDED ({})", console),
+        _ => format!("{:?}", console_type),
+    }
+}
+
+fn size(bytes: usize) -> String {
+    match bytes {
+        0 => "none".to_string(),
+        _ if bytes.is_multiple_of(1024) => format!("{} KiB", bytes / 1024),
+        _ => format!("{} bytes", bytes),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// `text` as a JSON string literal.
+pub fn json_string(text: &str) -> String {
+    let mut json = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+impl RomInfo {
+    /// Loads `raw` the way the emulator would, see `Rom::load`.
+    pub fn new(raw: &[u8]) -> Result<RomInfo, RomError> {
+        let rom = Rom::load(raw)?;
+        let format = if raw.starts_with(unif::MAGIC) {
+            Format::UNIF
+        } else if rom.nes2 {
+            Format::NES2
+        } else if rom.archaic {
+            Format::ARCHAIC_INES
+        } else {
+            Format::INES
+        };
+        Ok(RomInfo {
+            format,
+            file_crc32: hash::crc32(raw),
+            crc32: hash::crc32_update(hash::crc32(&rom.prg_rom), &rom.chr_rom),
+            sha1: hash::sha1(&[&rom.prg_rom[..], &rom.chr_rom[..]].concat()),
+            rom,
+        })
+    }
+
+    /// A single-line JSON object. Sizes are in bytes, hashes in hex.
+    pub fn to_json(&self) -> String {
+        let rom = &self.rom;
+        let fixes: Vec<String> = rom
+            .header_fixes
+            .iter()
+            .map(|fix| json_string(&fix.to_string()))
+            .collect();
+        let fields = [
+            ("format", json_string(&self.format.to_string())),
+            ("mapper", rom.mapper.to_string()),
+            (
+                "mapper_name",
+                mapper::name(rom.mapper).map_or("null".to_string(), json_string),
+            ),
+            ("submapper", rom.submapper.to_string()),
+            ("prg_rom_size", rom.prg_rom.len().to_string()),
+            ("chr_rom_size", rom.chr_rom.len().to_string()),
+            ("prg_ram_size", rom.prg_ram_size.to_string()),
+            ("prg_nvram_size", rom.prg_nvram_size.to_string()),
+            ("chr_ram_size", rom.chr_ram_size.to_string()),
+            ("chr_nvram_size", rom.chr_nvram_size.to_string()),
+            (
+                "mirroring",
+                json_string(&format!("{:?}", rom.screen_mirroring)),
+            ),
+            ("battery", rom.battery.to_string()),
+            ("trainer", rom.trainer.is_some().to_string()),
+            ("region", json_string(&format!("{:?}", rom.timing))),
+            ("console", json_string(&console(rom.console_type))),
+            ("misc_roms", rom.misc_roms.to_string()),
+            ("expansion_device", rom.expansion_device.to_string()),
+            (
+                "file_crc32",
+                json_string(&format!("{:08x}", self.file_crc32)),
+            ),
+            ("crc32", json_string(&format!("{:08x}", self.crc32))),
+            ("sha1", json_string(&hex(&self.sha1))),
+            ("header_fixes", format!("[{}]", fixes.join(","))),
+        ];
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|(name, value)| format!("{}:{}", json_string(name), value))
+            .collect();
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+impl fmt::Display for RomInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rom = &self.rom;
+        let yes_no = |value: bool| if value { "yes" } else { "no" };
+        writeln!(f, "Format:           {}", self.format)?;
+        match mapper::name(rom.mapper) {
+            Some(name) => writeln!(f, "Mapper:           {} ({})", rom.mapper, name)?,
+            None => writeln!(f, "Mapper:           {}", rom.mapper)?,
+        }
+        writeln!(f, "Submapper:        {}", rom.submapper)?;
+        writeln!(f, "PRG-ROM:          {}", size(rom.prg_rom.len()))?;
+        writeln!(f, "CHR-ROM:          {}", size(rom.chr_rom.len()))?;
+        writeln!(f, "PRG-RAM:          {}", size(rom.prg_ram_size))?;
+        writeln!(f, "PRG-NVRAM:        {}", size(rom.prg_nvram_size))?;
+        writeln!(f, "CHR-RAM:          {}", size(rom.chr_ram_size))?;
+        writeln!(f, "CHR-NVRAM:        {}", size(rom.chr_nvram_size))?;
+        writeln!(f, "Mirroring:        {:?}", rom.screen_mirroring)?;
+        writeln!(f, "Battery:          {}", yes_no(rom.battery))?;
+        writeln!(f, "Trainer:          {}", yes_no(rom.trainer.is_some()))?;
+        writeln!(f, "Region:           {:?}", rom.timing)?;
+        writeln!(f, "Console:          {}", console(rom.console_type))?;
+        writeln!(f, "Misc ROMs:        {}", rom.misc_roms)?;
+        writeln!(f, "Expansion device: {}", rom.expansion_device)?;
+        writeln!(f, "File CRC32:       {:08x}", self.file_crc32)?;
+        writeln!(f, "ROM CRC32:        {:08x}", self.crc32)?;
+        writeln!(f, "ROM SHA-1:        {}", hex(&self.sha1))?;
+        if rom.header_fixes.is_empty() {
+            write!(f, "Database:         no corrections")
+        } else {
+            let fixes: Vec<String> = rom.header_fixes.iter().map(|fix| fix.to_string()).collect();
+            write!(f, "Database:         {}", fixes.join(", "))
+        }
+    }
+}
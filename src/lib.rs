@@ -1,4 +1,15 @@
 pub mod bus;
 pub mod cartridge;
 pub mod cpu;
+pub mod fds;
+pub mod gamedb;
+pub mod hash;
+pub mod info;
+pub mod mapper;
+pub mod nsf;
 pub mod opcode;
+pub mod opll;
+pub mod patch;
+pub mod save;
+pub mod unif;
+pub mod wav;
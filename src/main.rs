@@ -10,11 +10,23 @@ use sdl2::EventPump;
 
 use cpu::CPU;
 use sdl2::video;
+use std::path::Path;
 
 pub mod bus;
 pub mod cartridge;
 pub mod cpu;
+pub mod fds;
+pub mod gamedb;
+pub mod hash;
+pub mod info;
+pub mod mapper;
+pub mod nsf;
 pub mod opcode;
+pub mod opll;
+pub mod patch;
+pub mod save;
+pub mod unif;
+pub mod wav;
 
 fn color(byte: u8) -> Color {
     match byte {
@@ -101,9 +113,29 @@ fn main() {
         .unwrap();
 
     //Load game
-    let bytes: Vec<u8> = std::fs::read("nestest.nes").unwrap();
-    let rom = Rom::new(&bytes).unwrap();
-    let bus = Bus::new(rom);
+    let rom_path = Path::new("nestest.nes");
+    let save_path = save::sav_path(rom_path);
+    let mut bytes: Vec<u8> = std::fs::read(rom_path).unwrap();
+    // IPS/UPS/BPS patches given on the command line, applied in order
+    for patch_path in std::env::args().skip(1) {
+        let patch_bytes = std::fs::read(&patch_path).unwrap();
+        bytes = patch::apply(&bytes, &patch_bytes).unwrap_or_else(|err| {
+            eprintln!("{}: {}", patch_path, err);
+            std::process::exit(1);
+        });
+    }
+    let rom = if rom_path.extension().is_some_and(|ext| ext == "fds") {
+        // the Disk System BIOS isn't part of the image, it sits next to it
+        let bios = std::fs::read(rom_path.with_file_name("disksys.rom")).unwrap();
+        fds::load(&bios, &bytes).unwrap()
+    } else {
+        Rom::load(&bytes).unwrap()
+    };
+    for fix in &rom.header_fixes {
+        eprintln!("Game database: {}", fix);
+    }
+    let mut bus = Bus::new(rom);
+    bus.load_save(&save_path).unwrap();
 
     let mut cpu = CPU::new(bus);
 
@@ -113,4 +145,5 @@ fn main() {
     cpu.run_with_callback(move |cpu| {
         println!("{}", trace(cpu));
     });
+    cpu.bus.flush_save().unwrap();
 }
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use crate::cartridge::{Mirroring, Rom};
+
+mod axrom;
+mod bandai;
+mod chr;
+mod cnrom;
+mod eeprom;
+mod fds;
+mod fds_audio;
+mod flash;
+mod fme7;
+mod gtrom;
+mod mmc2;
+mod mmc3;
+mod mmc5;
+mod namco108;
+mod namco163;
+mod nrom;
+mod nsf;
+mod prg_ram;
+mod rambo1;
+mod unrom512;
+mod uxrom;
+mod vrc4;
+mod vrc6;
+mod vrc7;
+mod vrc_irq;
+
+pub use axrom::AxRom;
+pub use bandai::BandaiFcg;
+pub use cnrom::CnRom;
+pub use fds::Fds;
+pub use fme7::Fme7;
+pub use gtrom::Gtrom;
+pub use mmc2::{Mmc2, Mmc4};
+pub use mmc3::Mmc3;
+pub use mmc5::Mmc5;
+pub use namco108::Namco108;
+pub use namco163::Namco163;
+pub use nrom::NRom;
+pub use nsf::NsfMapper;
+pub use rambo1::Rambo1;
+pub use unrom512::Unrom512;
+pub use uxrom::UxRom;
+pub use vrc4::Vrc4;
+pub use vrc6::Vrc6;
+pub use vrc7::Vrc7;
+
+/// Where a nametable access ($2000-$2FFF) ends up.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Nametable {
+    /// A 1K page of nametable VRAM: 0-1 are the console's CIRAM, 2-3 the extra
+    /// VRAM on four-screen boards.
+    Vram(u8),
+    /// A byte the cartridge drives onto the bus itself.
+    Data(u8),
+}
+
+/// The VRAM page a nametable address lands in under `mirroring`.
+pub fn vram_page(mirroring: Mirroring, addr: u16) -> u8 {
+    let quadrant = ((addr >> 10) & 0b11) as u8;
+    match mirroring {
+        Mirroring::VERTICAL => quadrant & 1,
+        Mirroring::HORIZONTAL => quadrant >> 1,
+        Mirroring::FOUR_SCREEN => quadrant,
+        Mirroring::SINGLE_SCREEN_LOWER => 0,
+        Mirroring::SINGLE_SCREEN_UPPER => 1,
+    }
+}
+
+/// Cartridge hardware as seen from the CPU and PPU buses.
+///
+/// The CPU side covers cartridge space ($4020-$FFFF), the PPU side covers the
+/// pattern tables ($0000-$1FFF) and decides where nametable accesses go.
+pub trait Mapper {
+    fn read_prg(&self, addr: u16) -> u8;
+
+    fn write_prg(&mut self, addr: u16, data: u8);
+
+    fn read_chr(&mut self, addr: u16) -> u8;
+
+    fn write_chr(&mut self, addr: u16, data: u8);
+
+    fn mirroring(&self) -> Mirroring;
+
+    /// Called once per CPU cycle (M2).
+    fn clock_cpu(&mut self) {}
+
+    /// PPU bus activity that doesn't go through the CHR or nametable accessors,
+    /// such as $2006 writes, passed on by `Bus::ppu_address`. Mappers that watch
+    /// A12 need to see it.
+    fn notify_ppu_address(&mut self, _addr: u16) {}
+
+    /// CPU writes outside cartridge space. The cartridge sees the whole CPU bus,
+    /// and some mappers snoop the PPU registers.
+    fn notify_cpu_write(&mut self, _addr: u16, _data: u8) {}
+
+    fn read_nametable(&mut self, addr: u16) -> Nametable {
+        self.notify_ppu_address(addr);
+        Nametable::Vram(vram_page(self.mirroring(), addr))
+    }
+
+    /// Returns the VRAM page to store `data` in, or `None` if the cartridge took
+    /// the write itself.
+    fn write_nametable(&mut self, addr: u16, _data: u8) -> Option<u8> {
+        self.notify_ppu_address(addr);
+        Some(vram_page(self.mirroring(), addr))
+    }
+
+    /// Level of the cartridge /IRQ line, true when asserted.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Current level of the cartridge's expansion audio, in the same units as the
+    /// APU mixer output so the two can simply be added.
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    /// Battery-backed memory to keep between sessions, if the cartridge has any.
+    fn battery_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Restores memory previously returned by `battery_ram`.
+    fn load_battery_ram(&mut self, _data: &[u8]) {}
+
+    /// Number of disk sides in the drive, for the Famicom Disk System. 0 for
+    /// cartridges.
+    fn disk_sides(&self) -> usize {
+        0
+    }
+
+    /// Ejects the disk, or inserts side `side` of it.
+    fn insert_disk(&mut self, _side: Option<usize>) {}
+}
+
+/// Builds a mapper for a ROM. Registered with `register`.
+pub type MapperConstructor = fn(Rom) -> Box<dyn Mapper>;
+
+// Keyed by mapper number and submapper, `None` covering every submapper.
+type Registry = HashMap<(u16, Option<u8>), MapperConstructor>;
+
+lazy_static::lazy_static! {
+    static ref REGISTRY: RwLock<Registry> = RwLock::new(built_in_mappers());
+}
+
+fn built_in_mappers() -> Registry {
+    let mappers: [(&[u16], MapperConstructor); 19] = [
+        (&[0], |rom| Box::new(NRom::new(rom))),
+        (&[2], |rom| Box::new(UxRom::new(rom))),
+        (&[3], |rom| Box::new(CnRom::new(rom))),
+        (&[4], |rom| Box::new(Mmc3::new(rom))),
+        (&[5], |rom| Box::new(Mmc5::new(rom))),
+        (&[7], |rom| Box::new(AxRom::new(rom))),
+        (&[9], |rom| Box::new(Mmc2::new(rom))),
+        (&[10], |rom| Box::new(Mmc4::new(rom))),
+        (&[16, 153, 157, 159], |rom| Box::new(BandaiFcg::new(rom))),
+        (&[19], |rom| Box::new(Namco163::new(rom))),
+        (&[20], |rom| Box::new(Fds::new(rom))),
+        (&[21, 22, 23, 25], |rom| Box::new(Vrc4::new(rom))),
+        (&[24, 26], |rom| Box::new(Vrc6::new(rom))),
+        (&[30], |rom| Box::new(Unrom512::new(rom))),
+        (&[64], |rom| Box::new(Rambo1::new(rom))),
+        (&[69], |rom| Box::new(Fme7::new(rom))),
+        (&[85], |rom| Box::new(Vrc7::new(rom))),
+        (&[88, 95, 154, 206], |rom| Box::new(Namco108::new(rom))),
+        (&[111], |rom| Box::new(Gtrom::new(rom))),
+    ];
+    let mut registry = Registry::new();
+    for (numbers, constructor) in mappers {
+        for &number in numbers {
+            registry.insert((number, None), constructor);
+        }
+    }
+    registry
+}
+
+/// Registers a mapper implementation, replacing any built-in one. With a
+/// `submapper` it is only used for that submapper, and takes precedence over a
+/// registration for all of them.
+pub fn register(mapper: u16, submapper: Option<u8>, constructor: MapperConstructor) {
+    REGISTRY
+        .write()
+        .unwrap()
+        .insert((mapper, submapper), constructor);
+}
+
+fn constructor(mapper: u16, submapper: u8) -> Option<MapperConstructor> {
+    let registry = REGISTRY.read().unwrap();
+    registry
+        .get(&(mapper, Some(submapper)))
+        .or_else(|| registry.get(&(mapper, None)))
+        .copied()
+}
+
+// What the built-in mappers are usually called, for display.
+const NAMES: &[(u16, &str)] = &[
+    (0, "NROM"),
+    (2, "UxROM"),
+    (3, "CNROM"),
+    (4, "MMC3/TxROM"),
+    (5, "MMC5/ExROM"),
+    (7, "AxROM"),
+    (9, "MMC2/PxROM"),
+    (10, "MMC4/FxROM"),
+    (16, "Bandai FCG/LZ93D50"),
+    (19, "Namco 129/163"),
+    (20, "Famicom Disk System"),
+    (21, "Konami VRC4a/VRC4c"),
+    (22, "Konami VRC2a"),
+    (23, "Konami VRC2b/VRC4e"),
+    (24, "Konami VRC6a"),
+    (25, "Konami VRC4b/VRC4d"),
+    (26, "Konami VRC6b"),
+    (30, "UNROM 512"),
+    (64, "Tengen RAMBO-1"),
+    (69, "Sunsoft FME-7/5B"),
+    (85, "Konami VRC7"),
+    (88, "Namco 118 (NAMCOT-3443)"),
+    (95, "Namco 118 (NAMCOT-3425)"),
+    (111, "GTROM"),
+    (153, "Bandai LZ93D50 with SRAM"),
+    (154, "Namco 118 (NAMCOT-3453)"),
+    (157, "Bandai Datach"),
+    (159, "Bandai LZ93D50 with 24C01"),
+    (206, "Namco 108/DxROM"),
+];
+
+/// The usual name of a mapper number, if it's one of the built-in ones.
+pub fn name(mapper: u16) -> Option<&'static str> {
+    NAMES
+        .iter()
+        .find(|&&(number, _)| number == mapper)
+        .map(|&(_, name)| name)
+}
+
+/// Whether a mapper is registered for `mapper` and `submapper`.
+pub fn is_supported(mapper: u16, submapper: u8) -> bool {
+    constructor(mapper, submapper).is_some()
+}
+
+/// Whether a trainer can be loaded with `mapper`. Boards with registers or an
+/// EEPROM at $7000 have nowhere to put it.
+pub fn supports_trainer(mapper: u16) -> bool {
+    !matches!(mapper, 16 | 111 | 157 | 159)
+}
+
+/// Builds the registered mapper for `rom`.
+///
+/// Panics if there is none; `Rom::new` and `RomBuilder::build` already refuse
+/// those.
+pub fn new(rom: Rom) -> Box<dyn Mapper> {
+    match constructor(rom.mapper, rom.submapper) {
+        Some(constructor) => constructor(rom),
+        None => panic!(
+            "no mapper registered for mapper {} submapper {}",
+            rom.mapper, rom.submapper
+        ),
+    }
+}
+
+/// Maps `addr` into `bank` of size `bank_size` within a memory of `len` bytes.
+/// Bank numbers wrap around the number of banks present, like the unconnected
+/// high bank lines on a real board.
+pub(crate) fn bank_addr(len: usize, bank_size: usize, bank: usize, addr: u16) -> usize {
+    let banks = (len / bank_size).max(1);
+    (bank % banks) * bank_size + (addr as usize % bank_size)
+}
+
+/// Index of the last `bank_size` bank within a memory of `len` bytes.
+pub(crate) fn last_bank(len: usize, bank_size: usize) -> usize {
+    (len / bank_size).max(1) - 1
+}
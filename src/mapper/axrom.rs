@@ -0,0 +1,73 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::chr::Chr;
+use crate::mapper::prg_ram::PrgRam;
+use crate::mapper::{bank_addr, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+/// Mapper 7: switchable 32K PRG bank, single-screen mirroring selected by bit 4.
+pub struct AxRom {
+    rom: Rom,
+    chr: Chr,
+    prg_ram: PrgRam,
+    prg_bank: u8,
+    mirroring: Mirroring,
+}
+
+impl AxRom {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::new(&mut rom);
+        let prg_ram = PrgRam::for_trainer(&rom);
+        AxRom {
+            rom,
+            chr,
+            prg_ram,
+            prg_bank: 0,
+            mirroring: Mirroring::SINGLE_SCREEN_LOWER,
+        }
+    }
+}
+
+impl Mapper for AxRom {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram.read(addr),
+            0x8000..=0xFFFF => {
+                let len = self.rom.prg_rom.len();
+                let bank = (self.prg_bank & 0b1111) as usize;
+                self.rom.prg_rom[bank_addr(len, PRG_BANK_SIZE, bank, addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, mut data: u8) {
+        if addr < 0x8000 {
+            if addr >= 0x6000 {
+                self.prg_ram.write(addr, data);
+            }
+            return;
+        }
+        if self.rom.bus_conflicts {
+            data &= self.read_prg(addr);
+        }
+        self.prg_bank = data;
+        self.mirroring = if data & 0b1_0000 != 0 {
+            Mirroring::SINGLE_SCREEN_UPPER
+        } else {
+            Mirroring::SINGLE_SCREEN_LOWER
+        };
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
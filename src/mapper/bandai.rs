@@ -0,0 +1,252 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::chr::Chr;
+use crate::mapper::eeprom::{Eeprom, EepromKind};
+use crate::mapper::prg_ram::PrgRam;
+use crate::mapper::{bank_addr, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const PRG_RAM_SIZE: usize = 0x2000;
+
+/// PRG banks per outer 256K bank on mapper 153.
+const OUTER_PRG_BANKS: usize = 16;
+
+/// Mappers 16, 153, 157 and 159: Bandai FCG-1/2 and LZ93D50, as used by the
+/// Dragon Ball and SD Gundam games.
+///
+/// Sixteen registers, mirrored every 16 bytes: eight 1K CHR banks, a 16K PRG bank
+/// at $8000 with the last bank fixed at $C000, mirroring, and a 16-bit IRQ counter
+/// decremented every CPU cycle. The FCG chips answer at $6000-$7FFF and load the
+/// counter directly, the LZ93D50 answers at $8000-$FFFF and loads it from a latch.
+/// Mapper 16 without a submapper listens at both and picks by address.
+///
+/// The LZ93D50 boards keep their saves in a serial EEPROM bit-banged through
+/// register $D and read back through bit 4 at $6000-$7FFF:
+/// - 16 has a 24C02 and 159 a 24C01.
+/// - 157 (Datach) has a 24C02 plus a 24C01 clocked by bit 3 of the CHR registers,
+///   and CHR-RAM instead of CHR banking.
+/// - 153 has battery-backed PRG-RAM instead, and uses bit 0 of the CHR
+///   registers to pick a 256K half of PRG.
+///
+/// Only 153 has RAM at $7000 to load a trainer into; `Rom::new` refuses trainers
+/// for the others.
+pub struct BandaiFcg {
+    rom: Rom,
+    chr: Chr,
+    chr_banks: [u8; 8],
+    prg_bank: u8,
+    outer_prg_bank: u8,
+    mirroring: Mirroring,
+
+    irq_enabled: bool,
+    irq_counter: u16,
+    irq_latch: u16,
+    irq_pending: bool,
+
+    // EEPROM contents; 157 keeps the 24C02 first
+    save: Vec<u8>,
+    // only on mapper 153
+    prg_ram: PrgRam,
+    prg_ram_enabled: bool,
+    eeprom: Option<Eeprom>,
+    datach_eeprom: Option<Eeprom>,
+    scl: bool,
+    sda: bool,
+    datach_scl: bool,
+}
+
+impl BandaiFcg {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::new(&mut rom);
+        let (eeprom, datach_eeprom) = match rom.mapper {
+            153 => (None, None),
+            157 => (Some(EepromKind::X24C02), Some(EepromKind::X24C01)),
+            159 => (Some(EepromKind::X24C01), None),
+            _ if rom.submapper == 4 => (None, None),
+            _ => (Some(EepromKind::X24C02), None),
+        };
+        let save_size =
+            eeprom.map_or(0, EepromKind::size) + datach_eeprom.map_or(0, EepromKind::size);
+        let prg_ram = match rom.mapper {
+            153 => PrgRam::with_min_size(&rom, PRG_RAM_SIZE),
+            _ => PrgRam::for_trainer(&rom),
+        };
+        let mirroring = rom.screen_mirroring;
+        BandaiFcg {
+            rom,
+            chr,
+            chr_banks: [0; 8],
+            prg_bank: 0,
+            outer_prg_bank: 0,
+            mirroring,
+            irq_enabled: false,
+            irq_counter: 0,
+            irq_latch: 0,
+            irq_pending: false,
+            save: vec![0; save_size],
+            prg_ram,
+            prg_ram_enabled: false,
+            eeprom: eeprom.map(Eeprom::new),
+            datach_eeprom: datach_eeprom.map(Eeprom::new),
+            scl: false,
+            sda: true,
+            datach_scl: false,
+        }
+    }
+
+    fn responds_at(&self, addr: u16) -> bool {
+        let lz93d50 = addr >= 0x8000;
+        match (self.rom.mapper, self.rom.submapper) {
+            (16, 4) => !lz93d50,
+            (16, 5) | (153 | 157 | 159, _) => lz93d50,
+            _ => true,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        let lz93d50 = addr >= 0x8000;
+        match addr & 0x0F {
+            register @ 0x0..=0x7 => {
+                self.chr_banks[register as usize] = data;
+                match self.rom.mapper {
+                    153 => self.outer_prg_bank = data & 1,
+                    157 => {
+                        self.datach_scl = data & 0b1000 != 0;
+                        self.clock_eeproms();
+                    }
+                    _ => {}
+                }
+            }
+            0x8 => self.prg_bank = data & 0b1111,
+            0x9 => {
+                self.mirroring = match data & 0b11 {
+                    0 => Mirroring::VERTICAL,
+                    1 => Mirroring::HORIZONTAL,
+                    2 => Mirroring::SINGLE_SCREEN_LOWER,
+                    _ => Mirroring::SINGLE_SCREEN_UPPER,
+                }
+            }
+            0xA => {
+                self.irq_enabled = data & 1 != 0;
+                self.irq_pending = false;
+                if lz93d50 {
+                    self.irq_counter = self.irq_latch;
+                }
+            }
+            0xB if lz93d50 => self.irq_latch = (self.irq_latch & 0xFF00) | data as u16,
+            0xC if lz93d50 => self.irq_latch = (self.irq_latch & 0x00FF) | (data as u16) << 8,
+            0xB => self.irq_counter = (self.irq_counter & 0xFF00) | data as u16,
+            0xC => self.irq_counter = (self.irq_counter & 0x00FF) | (data as u16) << 8,
+            0xD => {
+                self.prg_ram_enabled = data & 0b10_0000 != 0;
+                self.scl = data & 0b10_0000 != 0;
+                // with bit 7 set the mapper releases SDA so the EEPROM can drive it
+                self.sda = data & 0b1100_0000 != 0;
+                self.clock_eeproms();
+            }
+            _ => {}
+        }
+    }
+
+    fn clock_eeproms(&mut self) {
+        // the 24C01 on mapper 159 is the only first chip that isn't a 24C02
+        let split = match (&self.eeprom, self.rom.mapper) {
+            (None, _) => 0,
+            (Some(_), 159) => EepromKind::X24C01.size(),
+            (Some(_), _) => EepromKind::X24C02.size(),
+        };
+        let (memory, datach_memory) = self.save.split_at_mut(split);
+        if let Some(eeprom) = &mut self.eeprom {
+            eeprom.write_lines(memory, self.scl, self.sda);
+        }
+        if let Some(eeprom) = &mut self.datach_eeprom {
+            eeprom.write_lines(datach_memory, self.datach_scl, self.sda);
+        }
+    }
+
+    /// SDA as seen by the CPU: open drain, so either chip can pull it low.
+    fn eeprom_output(&self) -> bool {
+        self.eeprom.as_ref().is_none_or(Eeprom::output)
+            && self.datach_eeprom.as_ref().is_none_or(Eeprom::output)
+    }
+}
+
+impl Mapper for BandaiFcg {
+    fn read_prg(&self, addr: u16) -> u8 {
+        let len = self.rom.prg_rom.len();
+        let outer = self.outer_prg_bank as usize * OUTER_PRG_BANKS;
+        match addr {
+            0x6000..=0x7FFF if self.rom.mapper == 153 && self.prg_ram_enabled => {
+                self.prg_ram.read(addr)
+            }
+            0x6000..=0x7FFF if self.eeprom.is_some() => (self.eeprom_output() as u8) << 4,
+            0x8000..=0xBFFF => {
+                let bank = outer + self.prg_bank as usize;
+                self.rom.prg_rom[bank_addr(len, PRG_BANK_SIZE, bank, addr)]
+            }
+            0xC000..=0xFFFF => {
+                let bank = outer + OUTER_PRG_BANKS - 1;
+                self.rom.prg_rom[bank_addr(len, PRG_BANK_SIZE, bank, addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.rom.mapper == 153 && self.prg_ram_enabled => {
+                self.prg_ram.write(addr, data)
+            }
+            0x6000..=0xFFFF if self.responds_at(addr) => self.write_register(addr, data),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        if self.chr.is_ram() {
+            return self.chr.read(addr);
+        }
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize;
+        self.chr.read_bank(CHR_BANK_SIZE, bank, addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock_cpu(&mut self) {
+        if self.irq_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0 {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    /// EEPROMs keep their contents without a battery, so those boards always
+    /// have something to save.
+    fn battery_ram(&self) -> Option<&[u8]> {
+        match self.rom.mapper {
+            153 => self.prg_ram.battery_ram(),
+            _ if self.save.is_empty() => None,
+            _ => Some(&self.save),
+        }
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        if self.rom.mapper == 153 {
+            self.prg_ram.load(data);
+            return;
+        }
+        let len = data.len().min(self.save.len());
+        self.save[..len].copy_from_slice(&data[..len]);
+    }
+}
@@ -0,0 +1,63 @@
+use crate::cartridge::Rom;
+use crate::mapper::bank_addr;
+
+const WINDOW_SIZE: usize = 0x2000;
+
+/// The pattern table memory on the cartridge: the CHR-ROM, or CHR-RAM on boards
+/// without one.
+///
+/// CHR-RAM is sized from the header (8K for iNES) and written by the PPU through
+/// `Mapper::write_chr`; writes to CHR-ROM are ignored. Either way mappers bank it
+/// the same way.
+pub(crate) struct Chr {
+    data: Vec<u8>,
+    ram: bool,
+}
+
+impl Chr {
+    /// Takes the CHR-ROM out of `rom`, leaving `rom.chr_rom` empty.
+    pub(crate) fn new(rom: &mut Rom) -> Self {
+        Chr::with_min_ram_size(rom, 0)
+    }
+
+    /// For boards with more CHR-RAM than an iNES header can declare.
+    pub(crate) fn with_min_ram_size(rom: &mut Rom, min_size: usize) -> Self {
+        if rom.chr_rom.is_empty() {
+            let size = (rom.chr_ram_size + rom.chr_nvram_size).max(min_size);
+            Chr {
+                data: vec![0; if size == 0 { WINDOW_SIZE } else { size }],
+                ram: true,
+            }
+        } else {
+            Chr {
+                data: std::mem::take(&mut rom.chr_rom),
+                ram: false,
+            }
+        }
+    }
+
+    pub(crate) fn is_ram(&self) -> bool {
+        self.ram
+    }
+
+    /// Reads `addr` within the unbanked 8K of pattern tables.
+    pub(crate) fn read(&self, addr: u16) -> u8 {
+        self.read_bank(WINDOW_SIZE, 0, addr)
+    }
+
+    pub(crate) fn write(&mut self, addr: u16, data: u8) {
+        self.write_bank(WINDOW_SIZE, 0, addr, data);
+    }
+
+    /// Reads `addr` within `bank` of `bank_size` bytes.
+    pub(crate) fn read_bank(&self, bank_size: usize, bank: usize, addr: u16) -> u8 {
+        self.data[bank_addr(self.data.len(), bank_size, bank, addr)]
+    }
+
+    pub(crate) fn write_bank(&mut self, bank_size: usize, bank: usize, addr: u16, data: u8) {
+        if self.ram {
+            let len = self.data.len();
+            self.data[bank_addr(len, bank_size, bank, addr)] = data;
+        }
+    }
+}
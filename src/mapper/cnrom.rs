@@ -0,0 +1,67 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::chr::Chr;
+use crate::mapper::prg_ram::PrgRam;
+use crate::mapper::Mapper;
+
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/// Mapper 3: fixed PRG like NROM, switchable 8K CHR bank.
+pub struct CnRom {
+    rom: Rom,
+    chr: Chr,
+    prg_ram: PrgRam,
+    chr_bank: u8,
+}
+
+impl CnRom {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::new(&mut rom);
+        let prg_ram = PrgRam::for_trainer(&rom);
+        CnRom {
+            rom,
+            chr,
+            prg_ram,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for CnRom {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram.read(addr),
+            0x8000..=0xFFFF => {
+                let addr = (addr - 0x8000) as usize % self.rom.prg_rom.len();
+                self.rom.prg_rom[addr]
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, mut data: u8) {
+        if addr < 0x8000 {
+            if addr >= 0x6000 {
+                self.prg_ram.write(addr, data);
+            }
+            return;
+        }
+        if self.rom.bus_conflicts {
+            data &= self.read_prg(addr);
+        }
+        self.chr_bank = data;
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        let bank = self.chr_bank as usize;
+        self.chr.read_bank(CHR_BANK_SIZE, bank, addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let bank = self.chr_bank as usize;
+        self.chr.write_bank(CHR_BANK_SIZE, bank, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.rom.screen_mirroring
+    }
+}
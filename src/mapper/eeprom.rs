@@ -0,0 +1,181 @@
+/// Which serial EEPROM is on the board. They share the wiring but not the
+/// protocol.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum EepromKind {
+    /// 128 bytes. Xicor's early protocol: no device address, the first byte after
+    /// a start condition is the word address and R/W bit, and bytes go LSB first.
+    X24C01,
+    /// 256 bytes. Standard I2C: a device address byte, then the word address,
+    /// bytes MSB first.
+    X24C02,
+}
+
+impl EepromKind {
+    pub(crate) fn size(self) -> usize {
+        match self {
+            EepromKind::X24C01 => 0x80,
+            EepromKind::X24C02 => 0x100,
+        }
+    }
+
+    /// Sequential writes wrap around within a page.
+    fn page_size(self) -> u8 {
+        match self {
+            EepromKind::X24C01 => 4,
+            EepromKind::X24C02 => 8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Phase {
+    Idle,
+    Device,
+    Address,
+    Write,
+    Read,
+}
+
+/// A 24C01/24C02 I2C EEPROM, clocked bit by bit through its SCL and SDA lines.
+///
+/// Bits are latched on the rising edge of SCL, and a start or stop is SDA falling
+/// or rising while SCL is high. Every ninth clock is an acknowledge: the chip
+/// pulls SDA low after receiving a byte, and after sending one it keeps going only
+/// if the host pulls SDA low. Like `Flash`, it only drives the protocol; the
+/// caller owns the memory so it can be saved with the rest of the cartridge.
+pub(crate) struct Eeprom {
+    kind: EepromKind,
+    phase: Phase,
+    scl: bool,
+    sda: bool,
+    shift: u8,
+    bits: u8,
+    address: u8,
+    output: bool,
+}
+
+impl Eeprom {
+    pub(crate) fn new(kind: EepromKind) -> Self {
+        Eeprom {
+            kind,
+            phase: Phase::Idle,
+            scl: false,
+            sda: true,
+            shift: 0,
+            bits: 0,
+            address: 0,
+            output: true,
+        }
+    }
+
+    /// Level the chip drives on SDA. It only ever pulls low, so `true` also means released.
+    pub(crate) fn output(&self) -> bool {
+        self.output
+    }
+
+    pub(crate) fn write_lines(&mut self, memory: &mut [u8], scl: bool, sda: bool) {
+        let (old_scl, old_sda) = (self.scl, self.sda);
+        self.scl = scl;
+        self.sda = sda;
+
+        if scl && old_scl && sda != old_sda {
+            if sda {
+                // stop
+                self.phase = Phase::Idle;
+                self.output = true;
+            } else {
+                // start, or a repeated start
+                self.phase = match self.kind {
+                    EepromKind::X24C01 => Phase::Address,
+                    EepromKind::X24C02 => Phase::Device,
+                };
+                self.shift = 0;
+                self.bits = 0;
+                self.output = true;
+            }
+            return;
+        }
+
+        if scl && !old_scl {
+            self.clock(memory, sda);
+        }
+    }
+
+    fn clock(&mut self, memory: &mut [u8], sda: bool) {
+        match self.phase {
+            Phase::Idle => {}
+            Phase::Read if self.bits < 8 => {
+                let bit = match self.kind {
+                    EepromKind::X24C01 => self.shift & (1 << self.bits),
+                    EepromKind::X24C02 => self.shift & (0x80 >> self.bits),
+                };
+                self.output = bit != 0;
+                self.bits += 1;
+            }
+            Phase::Read => {
+                // the host acknowledges to ask for the next byte
+                self.output = true;
+                self.bits = 0;
+                if sda {
+                    self.phase = Phase::Idle;
+                } else {
+                    self.address = self.address.wrapping_add(1);
+                    self.shift = memory[self.address as usize % memory.len()];
+                }
+            }
+            _ if self.bits < 8 => {
+                let sda = sda as u8;
+                self.shift = match self.kind {
+                    EepromKind::X24C01 => self.shift | (sda << self.bits),
+                    EepromKind::X24C02 => (self.shift << 1) | sda,
+                };
+                self.bits += 1;
+                self.output = true;
+            }
+            _ => {
+                let byte = self.shift;
+                self.shift = 0;
+                self.bits = 0;
+                self.output = false;
+                self.receive_byte(memory, byte);
+            }
+        }
+    }
+
+    fn receive_byte(&mut self, memory: &mut [u8], byte: u8) {
+        let size = memory.len();
+        match (self.phase, self.kind) {
+            (Phase::Device, _) if byte >> 4 != 0b1010 => {
+                // addressed to some other chip
+                self.output = true;
+                self.phase = Phase::Idle;
+            }
+            (Phase::Device, _) if byte & 1 != 0 => self.start_read(memory),
+            (Phase::Device, _) => self.phase = Phase::Address,
+            (Phase::Address, EepromKind::X24C01) => {
+                self.address = byte & 0x7F;
+                if byte & 0x80 != 0 {
+                    self.start_read(memory);
+                } else {
+                    self.phase = Phase::Write;
+                }
+            }
+            (Phase::Address, EepromKind::X24C02) => {
+                self.address = byte;
+                self.phase = Phase::Write;
+            }
+            (Phase::Write, _) => {
+                memory[self.address as usize % size] = byte;
+                let page_mask = self.kind.page_size() - 1;
+                self.address =
+                    (self.address & !page_mask) | (self.address.wrapping_add(1) & page_mask);
+            }
+            _ => {}
+        }
+    }
+
+    fn start_read(&mut self, memory: &[u8]) {
+        self.phase = Phase::Read;
+        self.shift = memory[self.address as usize % memory.len()];
+    }
+}
@@ -0,0 +1,427 @@
+use std::cell::Cell;
+
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::chr::Chr;
+use crate::mapper::fds_audio::FdsAudio;
+use crate::mapper::prg_ram::PrgRam;
+use crate::mapper::Mapper;
+
+/// Zeros before the first block, 28300 bits of them.
+const LEADING_GAP: usize = 28300 / 8;
+/// Zeros between blocks, 976 bits of them.
+const BLOCK_GAP: usize = 976 / 8;
+/// The drive finds the start of a block by the first 1 bit after a gap.
+const GAP_END_MARK: u8 = 0x80;
+
+const PRG_RAM_SIZE: usize = 0x8000;
+
+/// The drive moves about 96 kbit/s, a byte every 150 CPU cycles.
+const BYTE_CYCLES: u32 = 150;
+/// Time for the head to get from the end of the disk back to the start.
+const REWIND_CYCLES: u32 = 50_000;
+/// How long the drive stays empty when switching sides, about a second, so the
+/// BIOS notices the disk was changed.
+const DISK_CHANGE_CYCLES: u32 = 1_789_773;
+
+/// The drive's CRC-16 (polynomial 0x8408, bits taken LSB first).
+fn crc_update(crc: u16, data: u8) -> u16 {
+    let mut crc = crc;
+    for bit in 0..8 {
+        let carry = crc & 1 != 0;
+        crc >>= 1;
+        if carry {
+            crc ^= 0x8408;
+        }
+        if data & (1 << bit) != 0 {
+            crc ^= 0x8000;
+        }
+    }
+    crc
+}
+
+/// Lays a side out the way the drive sees it: the blocks of the `.fds` side with
+/// a gap, start mark and CRC around each, followed by the side's unused space.
+fn add_gaps(side: &[u8]) -> Vec<u8> {
+    let mut disk = vec![0; LEADING_GAP];
+    let mut position = 0;
+    let mut file_size = 0;
+    while position < side.len() {
+        let len = match side[position] {
+            1 => 56,
+            2 => 2,
+            3 => 16,
+            4 => 1 + file_size,
+            _ => break,
+        };
+        let Some(block) = side.get(position..position + len) else {
+            break;
+        };
+        if block[0] == 3 {
+            file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+        }
+
+        let mut crc = crc_update(0, GAP_END_MARK);
+        for &byte in block {
+            crc = crc_update(crc, byte);
+        }
+        crc = crc_update(crc_update(crc, 0), 0);
+
+        disk.push(GAP_END_MARK);
+        disk.extend_from_slice(block);
+        disk.extend_from_slice(&crc.to_le_bytes());
+        disk.extend(std::iter::repeat_n(0, BLOCK_GAP));
+        position += len;
+    }
+    disk.extend(std::iter::repeat_n(0, side.len() - position));
+    disk
+}
+
+/// Mapper 20: the Famicom Disk System RAM adapter.
+///
+/// 32K of PRG-RAM at $6000-$DFFF with the BIOS at $E000, 8K of CHR-RAM, a
+/// 16-bit timer IRQ, the disk drive and a wavetable sound channel. The drive
+/// streams a byte every 150 cycles while the motor runs, raising an IRQ for each
+/// one once it has found the start of a block. What the BIOS writes goes back
+/// into the disk image, which is what `battery_ram` saves.
+pub struct Fds {
+    rom: Rom,
+    prg_ram: PrgRam,
+    chr: Chr,
+    audio: FdsAudio,
+    mirroring: Mirroring,
+    disk_registers_enabled: bool,
+    sound_registers_enabled: bool,
+
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_repeat: bool,
+    irq_enabled: bool,
+    timer_irq: Cell<bool>,
+
+    // every side with its gaps, `side_size` bytes each
+    disk: Vec<u8>,
+    side_size: usize,
+    side: Option<usize>,
+    next_side: Option<usize>,
+    disk_change_delay: u32,
+    disk_modified: bool,
+
+    motor_on: bool,
+    transfer_reset: bool,
+    read_mode: bool,
+    crc_control: bool,
+    previous_crc_control: bool,
+    // $4025 bit 6: find the start of a block when reading, write data instead of
+    // gap when writing
+    transfer_started: bool,
+    disk_irq_enabled: bool,
+    disk_irq: Cell<bool>,
+    transfer_complete: Cell<bool>,
+    read_data: u8,
+    write_data: u8,
+    position: usize,
+    delay: u32,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+    crc: u16,
+}
+
+impl Fds {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::new(&mut rom);
+        let prg_ram = PrgRam::with_min_size(&rom, PRG_RAM_SIZE);
+        let sides: Vec<Vec<u8>> = rom.disk_sides.iter().map(|side| add_gaps(side)).collect();
+        let side_size = sides.iter().map(Vec::len).max().unwrap_or(0);
+        let mut disk = Vec::with_capacity(side_size * sides.len());
+        for mut side in sides {
+            side.resize(side_size, 0);
+            disk.extend(side);
+        }
+        let mirroring = rom.screen_mirroring;
+        let side = (side_size > 0).then_some(0);
+        Fds {
+            rom,
+            prg_ram,
+            chr,
+            audio: FdsAudio::new(),
+            mirroring,
+            disk_registers_enabled: true,
+            sound_registers_enabled: true,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_repeat: false,
+            irq_enabled: false,
+            timer_irq: Cell::new(false),
+            disk,
+            side_size,
+            side,
+            next_side: None,
+            disk_change_delay: 0,
+            disk_modified: false,
+            motor_on: false,
+            transfer_reset: false,
+            read_mode: true,
+            crc_control: false,
+            previous_crc_control: false,
+            transfer_started: false,
+            disk_irq_enabled: false,
+            disk_irq: Cell::new(false),
+            transfer_complete: Cell::new(false),
+            read_data: 0,
+            write_data: 0,
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            crc: 0,
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+        if self.irq_counter == 0 {
+            self.timer_irq.set(true);
+            self.irq_counter = self.irq_reload;
+            if !self.irq_repeat {
+                self.irq_enabled = false;
+            }
+        } else {
+            self.irq_counter -= 1;
+        }
+    }
+
+    fn clock_drive(&mut self) {
+        if self.disk_change_delay > 0 {
+            self.disk_change_delay -= 1;
+            if self.disk_change_delay == 0 {
+                self.side = self.next_side.take();
+            }
+        }
+
+        let Some(side) = self.side.filter(|_| self.motor_on) else {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        };
+        if self.transfer_reset && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = REWIND_CYCLES;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let offset = side * self.side_size + self.position;
+        if self.read_mode {
+            self.read_byte(offset);
+        } else {
+            self.write_byte(offset);
+        }
+        self.previous_crc_control = self.crc_control;
+
+        self.position += 1;
+        if self.position >= self.side_size {
+            self.motor_on = false;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+
+    fn read_byte(&mut self, offset: usize) {
+        let data = self.disk[offset];
+        let mut irq = self.disk_irq_enabled;
+        if !self.transfer_started {
+            self.gap_ended = false;
+        } else if data != 0 && !self.gap_ended {
+            // the start mark itself doesn't interrupt
+            self.gap_ended = true;
+            irq = false;
+        }
+        if self.gap_ended {
+            self.transfer_complete.set(true);
+            self.read_data = data;
+            if irq {
+                self.disk_irq.set(true);
+            }
+        }
+    }
+
+    fn write_byte(&mut self, offset: usize) {
+        let mut data = 0;
+        if !self.crc_control {
+            self.transfer_complete.set(true);
+            data = self.write_data;
+            if self.disk_irq_enabled {
+                self.disk_irq.set(true);
+            }
+        }
+        if !self.transfer_started {
+            data = 0;
+            self.crc = 0;
+        }
+        if !self.crc_control {
+            self.crc = crc_update(self.crc, data);
+        } else {
+            if !self.previous_crc_control {
+                self.crc = crc_update(crc_update(self.crc, 0), 0);
+            }
+            data = self.crc as u8;
+            self.crc >>= 8;
+        }
+        self.disk[offset] = data;
+        self.disk_modified = true;
+        self.gap_ended = false;
+    }
+
+    fn write_control(&mut self, data: u8) {
+        self.motor_on = data & 0b0000_0001 != 0;
+        self.transfer_reset = data & 0b0000_0010 != 0;
+        self.read_mode = data & 0b0000_0100 != 0;
+        self.mirroring = if data & 0b0000_1000 != 0 {
+            Mirroring::HORIZONTAL
+        } else {
+            Mirroring::VERTICAL
+        };
+        self.crc_control = data & 0b0001_0000 != 0;
+        self.transfer_started = data & 0b0100_0000 != 0;
+        self.disk_irq_enabled = data & 0b1000_0000 != 0;
+        self.disk_irq.set(false);
+    }
+}
+
+impl Mapper for Fds {
+    fn read_prg(&self, addr: u16) -> u8 {
+        let inserted = self.side.is_some();
+        match addr {
+            0x4030 if self.disk_registers_enabled => {
+                let status = self.timer_irq.get() as u8
+                    | (self.transfer_complete.get() as u8) << 1
+                    | (self.end_of_head as u8) << 6;
+                self.timer_irq.set(false);
+                self.disk_irq.set(false);
+                self.transfer_complete.set(false);
+                status
+            }
+            0x4031 if self.disk_registers_enabled => {
+                self.transfer_complete.set(false);
+                self.disk_irq.set(false);
+                self.read_data
+            }
+            0x4032 if self.disk_registers_enabled => {
+                let not_ready = !inserted || !self.scanning;
+                0x40 | !inserted as u8 | (not_ready as u8) << 1 | (!inserted as u8) << 2
+            }
+            // battery good on the external connector
+            0x4033 if self.disk_registers_enabled => 0x80,
+            0x4040..=0x4092 if self.sound_registers_enabled => self.audio.read(addr).unwrap_or(0),
+            0x6000..=0xDFFF => {
+                let bank = (addr as usize - 0x6000) / 0x2000;
+                self.prg_ram.read_bank(bank, addr)
+            }
+            0xE000..=0xFFFF => self.rom.prg_rom[(addr as usize - 0xE000) % self.rom.prg_rom.len()],
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4020 => self.irq_reload = (self.irq_reload & 0xFF00) | data as u16,
+            0x4021 => self.irq_reload = (self.irq_reload & 0x00FF) | (data as u16) << 8,
+            0x4022 => {
+                self.irq_repeat = data & 1 != 0;
+                self.irq_enabled = data & 0b10 != 0 && self.disk_registers_enabled;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_reload;
+                } else {
+                    self.timer_irq.set(false);
+                }
+            }
+            0x4023 => {
+                self.disk_registers_enabled = data & 1 != 0;
+                self.sound_registers_enabled = data & 0b10 != 0;
+                if !self.disk_registers_enabled {
+                    self.irq_enabled = false;
+                    self.timer_irq.set(false);
+                    self.disk_irq.set(false);
+                }
+            }
+            0x4024 if self.disk_registers_enabled => {
+                self.write_data = data;
+                self.transfer_complete.set(false);
+                self.disk_irq.set(false);
+            }
+            0x4025 if self.disk_registers_enabled => self.write_control(data),
+            0x4040..=0x408A if self.sound_registers_enabled => self.audio.write(addr, data),
+            0x6000..=0xDFFF => {
+                let bank = (addr as usize - 0x6000) / 0x2000;
+                self.prg_ram.write_bank(bank, addr, data);
+            }
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock_cpu(&mut self) {
+        self.clock_timer();
+        self.audio.clock();
+        self.clock_drive();
+    }
+
+    fn irq(&self) -> bool {
+        self.timer_irq.get() || self.disk_irq.get()
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    /// The whole disk, once something was written to it.
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.disk_modified.then_some(&self.disk[..])
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        // a save from some other disk image can't be laid over this one
+        if data.len() == self.disk.len() {
+            self.disk.copy_from_slice(data);
+            self.disk_modified = true;
+        }
+    }
+
+    fn disk_sides(&self) -> usize {
+        self.rom.disk_sides.len()
+    }
+
+    fn insert_disk(&mut self, side: Option<usize>) {
+        self.side = None;
+        self.next_side = side.filter(|&side| side < self.disk_sides());
+        self.disk_change_delay = if self.next_side.is_some() {
+            DISK_CHANGE_CYCLES
+        } else {
+            0
+        };
+    }
+}
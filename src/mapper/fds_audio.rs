@@ -0,0 +1,237 @@
+/// Output of one step of the 6-bit wave at full volume, in the same units as the
+/// APU mixer. At its loudest the FDS channel is about 2.4 times an APU pulse.
+const OUTPUT_STEP: f32 = 0.0043;
+
+/// $4089 master volume: 2/2, 2/3, 2/4 and 2/5, scaled so the product with a gain
+/// of 32 divides down by 1152.
+const MASTER_VOLUME: [u32; 4] = [36, 24, 17, 14];
+
+/// Mod table entries: how much each step moves the modulation counter. 4 resets
+/// it instead.
+const MOD_STEPS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+
+/// The volume and modulation units share this: a 6-bit speed and direction
+/// ($4080/$4084), or a fixed gain when the envelope is off.
+struct Envelope {
+    speed: u8,
+    gain: u8,
+    increase: bool,
+    disabled: bool,
+    timer: u32,
+}
+
+impl Envelope {
+    fn new() -> Self {
+        Envelope {
+            speed: 0,
+            gain: 0,
+            increase: false,
+            disabled: true,
+            timer: 0,
+        }
+    }
+
+    fn write(&mut self, data: u8, master_speed: u8) {
+        self.speed = data & 0x3F;
+        self.increase = data & 0x40 != 0;
+        self.disabled = data & 0x80 != 0;
+        if self.disabled {
+            self.gain = self.speed;
+        }
+        self.reset_timer(master_speed);
+    }
+
+    fn reset_timer(&mut self, master_speed: u8) {
+        self.timer = 8 * (self.speed as u32 + 1) * master_speed as u32;
+    }
+
+    fn clock(&mut self, master_speed: u8) {
+        if self.disabled || master_speed == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.reset_timer(master_speed);
+            if self.increase && self.gain < 32 {
+                self.gain += 1;
+            } else if !self.increase && self.gain > 0 {
+                self.gain -= 1;
+            }
+        }
+    }
+}
+
+/// The RAM adapter's sound channel: a 64-step, 6-bit wavetable with a volume
+/// envelope, and a second wavetable of pitch offsets that modulates it.
+pub(crate) struct FdsAudio {
+    wave: [u8; 64],
+    wave_write: bool,
+    wave_position: usize,
+    wave_accumulator: u16,
+    frequency: u16,
+    halt_wave: bool,
+    halt_envelopes: bool,
+    master_volume: usize,
+    master_speed: u8,
+    volume: Envelope,
+
+    mod_table: [u8; 64],
+    mod_position: usize,
+    mod_accumulator: u16,
+    mod_frequency: u16,
+    mod_halted: bool,
+    mod_counter: i8,
+    modulation: Envelope,
+
+    output: u8,
+}
+
+impl FdsAudio {
+    pub(crate) fn new() -> Self {
+        FdsAudio {
+            wave: [0; 64],
+            wave_write: false,
+            wave_position: 0,
+            wave_accumulator: 0,
+            frequency: 0,
+            halt_wave: true,
+            halt_envelopes: false,
+            master_volume: 0,
+            master_speed: 0xE8,
+            volume: Envelope::new(),
+            mod_table: [0; 64],
+            mod_position: 0,
+            mod_accumulator: 0,
+            mod_frequency: 0,
+            mod_halted: true,
+            mod_counter: 0,
+            modulation: Envelope::new(),
+            output: 0,
+        }
+    }
+
+    pub(crate) fn read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4040..=0x407F => Some(self.wave[(addr - 0x4040) as usize] | 0x40),
+            0x4090 => Some(self.volume.gain | 0x40),
+            0x4092 => Some(self.modulation.gain | 0x40),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4040..=0x407F if self.wave_write => {
+                self.wave[(addr - 0x4040) as usize] = data & 0x3F;
+            }
+            0x4080 => self.volume.write(data, self.master_speed),
+            0x4082 => self.frequency = (self.frequency & 0x0F00) | data as u16,
+            0x4083 => {
+                self.frequency = (self.frequency & 0x00FF) | ((data as u16 & 0x0F) << 8);
+                self.halt_wave = data & 0x80 != 0;
+                self.halt_envelopes = data & 0x40 != 0;
+                if self.halt_wave {
+                    self.wave_position = 0;
+                    self.wave_accumulator = 0;
+                }
+                if self.halt_envelopes {
+                    self.volume.reset_timer(self.master_speed);
+                    self.modulation.reset_timer(self.master_speed);
+                }
+            }
+            0x4084 => self.modulation.write(data, self.master_speed),
+            0x4085 => self.mod_counter = ((data << 1) as i8) >> 1,
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0x0F00) | data as u16,
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0x00FF) | ((data as u16 & 0x0F) << 8);
+                self.mod_halted = data & 0x80 != 0;
+                if self.mod_halted {
+                    self.mod_accumulator = 0;
+                }
+            }
+            // the table can only be filled while modulation is halted, and each
+            // entry is stepped through twice
+            0x4088 if self.mod_halted => {
+                for _ in 0..2 {
+                    self.mod_table[self.mod_position] = data & 0b111;
+                    self.mod_position = (self.mod_position + 1) & 0x3F;
+                }
+            }
+            0x4089 => {
+                self.wave_write = data & 0x80 != 0;
+                self.master_volume = (data & 0b11) as usize;
+            }
+            0x408A => self.master_speed = data,
+            _ => {}
+        }
+    }
+
+    /// The wave frequency after modulation, following the hardware's odd rounding.
+    fn modulated_frequency(&self) -> u16 {
+        let mut temp = self.mod_counter as i32 * self.modulation.gain as i32;
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if self.mod_counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        temp *= self.frequency as i32;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        (self.frequency as i32 + temp).clamp(0, 0xFFFF) as u16
+    }
+
+    fn clock_modulator(&mut self) {
+        if self.mod_halted || self.mod_frequency == 0 {
+            return;
+        }
+        let (accumulator, overflow) = self.mod_accumulator.overflowing_add(self.mod_frequency);
+        self.mod_accumulator = accumulator;
+        if overflow {
+            match self.mod_table[self.mod_position] {
+                4 => self.mod_counter = 0,
+                step => {
+                    // a 7-bit signed counter
+                    let counter = self.mod_counter.wrapping_add(MOD_STEPS[step as usize]);
+                    self.mod_counter = (counter << 1) >> 1;
+                }
+            }
+            self.mod_position = (self.mod_position + 1) & 0x3F;
+        }
+    }
+
+    /// Called once per CPU cycle.
+    pub(crate) fn clock(&mut self) {
+        if !self.halt_wave && !self.halt_envelopes {
+            self.volume.clock(self.master_speed);
+            self.modulation.clock(self.master_speed);
+        }
+        self.clock_modulator();
+
+        // the output holds its last value while the wave is being written
+        if self.wave_write {
+            return;
+        }
+        if !self.halt_wave {
+            let frequency = self.modulated_frequency();
+            let (accumulator, overflow) = self.wave_accumulator.overflowing_add(frequency);
+            self.wave_accumulator = accumulator;
+            if overflow {
+                self.wave_position = (self.wave_position + 1) & 0x3F;
+            }
+        }
+        let level = self.volume.gain.min(32) as u32 * MASTER_VOLUME[self.master_volume];
+        self.output = (self.wave[self.wave_position] as u32 * level / 1152) as u8;
+    }
+
+    pub(crate) fn output(&self) -> f32 {
+        self.output as f32 * OUTPUT_STEP
+    }
+}
@@ -0,0 +1,105 @@
+/// Command sequences are decoded on A0-A14 only.
+const COMMAND_ADDR_MASK: usize = 0x7FFF;
+const SECTOR_SIZE: usize = 0x1000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Ready,
+    Unlocked1,
+    Unlocked2,
+    Program,
+    Erase,
+    EraseUnlocked1,
+    EraseUnlocked2,
+}
+
+/// The command interface of an SST39SF040 flash chip, used as self-writable PRG
+/// by homebrew boards.
+///
+/// Every command starts with $AA to $5555 and $55 to $2AAA. Programming can only
+/// clear bits, so a save routine erases a 4K sector first and then programs it.
+/// The chip itself doesn't know where it's mapped: callers pass addresses
+/// already translated to chip space.
+pub(crate) struct Flash {
+    state: State,
+    id_mode: bool,
+    modified: bool,
+}
+
+impl Flash {
+    pub(crate) fn new() -> Self {
+        Flash {
+            state: State::Ready,
+            id_mode: false,
+            modified: false,
+        }
+    }
+
+    /// Whether anything has been erased or programmed since power on, or a save
+    /// was restored. Boards only have save data worth keeping once this is set.
+    pub(crate) fn modified(&self) -> bool {
+        self.modified
+    }
+
+    pub(crate) fn mark_modified(&mut self) {
+        self.modified = true;
+    }
+
+    /// Reads `addr`, which returns the manufacturer and device IDs while in
+    /// software ID mode.
+    pub(crate) fn read(&self, memory: &[u8], addr: usize) -> u8 {
+        if self.id_mode {
+            if addr & 1 == 0 {
+                0xBF
+            } else {
+                0xB7
+            }
+        } else {
+            memory[addr % memory.len()]
+        }
+    }
+
+    pub(crate) fn write(&mut self, memory: &mut [u8], addr: usize, data: u8) {
+        let addr = addr % memory.len();
+        let command_addr = addr & COMMAND_ADDR_MASK;
+
+        // $F0 anywhere resets, including out of software ID mode
+        if data == 0xF0 && self.state != State::Program {
+            self.state = State::Ready;
+            self.id_mode = false;
+            return;
+        }
+
+        self.state = match (self.state, command_addr, data) {
+            (State::Ready, 0x5555, 0xAA) => State::Unlocked1,
+            (State::Unlocked1, 0x2AAA, 0x55) => State::Unlocked2,
+            (State::Unlocked2, 0x5555, 0xA0) => State::Program,
+            (State::Unlocked2, 0x5555, 0x80) => State::Erase,
+            (State::Unlocked2, 0x5555, 0x90) => {
+                self.id_mode = true;
+                State::Ready
+            }
+            (State::Program, _, _) => {
+                memory[addr] &= data;
+                self.modified = true;
+                State::Ready
+            }
+            (State::Erase, 0x5555, 0xAA) => State::EraseUnlocked1,
+            (State::EraseUnlocked1, 0x2AAA, 0x55) => State::EraseUnlocked2,
+            (State::EraseUnlocked2, _, 0x30) => {
+                let sector = addr & !(SECTOR_SIZE - 1);
+                // the last sector of a PRG that isn't whole sectors is cut short
+                let end = (sector + SECTOR_SIZE).min(memory.len());
+                memory[sector..end].fill(0xFF);
+                self.modified = true;
+                State::Ready
+            }
+            (State::EraseUnlocked2, 0x5555, 0x10) => {
+                memory.fill(0xFF);
+                self.modified = true;
+                State::Ready
+            }
+            _ => State::Ready,
+        };
+    }
+}
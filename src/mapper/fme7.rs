@@ -0,0 +1,377 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::chr::Chr;
+use crate::mapper::prg_ram::PrgRam;
+use crate::mapper::{bank_addr, last_bank, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// The 5B's tone, noise and envelope generators all run off the CPU clock
+/// divided by 16.
+const AUDIO_DIVIDER: u8 = 16;
+
+/// Output of one channel at full volume, in the same units as the APU mixer.
+/// The 5B is noticeably louder than the APU pulses.
+const CHANNEL_LEVEL: f32 = 0.12;
+
+/// Amplitude of each of the 32 envelope levels. The 5B steps in 1.5 dB, with
+/// level 0 silent.
+fn level_amplitude(level: u8) -> f32 {
+    if level == 0 {
+        0.0
+    } else {
+        10f32.powf((level as f32 - 31.0) * 1.5 / 20.0)
+    }
+}
+
+struct Tone {
+    period: u16,
+    counter: u16,
+    high: bool,
+}
+
+impl Tone {
+    fn new() -> Self {
+        Tone {
+            period: 0,
+            counter: 0,
+            high: false,
+        }
+    }
+
+    fn clock(&mut self) {
+        self.counter += 1;
+        if self.counter >= self.period.max(1) {
+            self.counter = 0;
+            self.high = !self.high;
+        }
+    }
+}
+
+/// YM2149-style envelope: 32 levels ramping up or down, with shape register
+/// $0D deciding what happens after the first ramp.
+struct Envelope {
+    period: u16,
+    counter: u16,
+    shape: u8,
+    step: u8,
+    holding: bool,
+    attack: bool,
+}
+
+impl Envelope {
+    fn restart(&mut self, shape: u8) {
+        self.shape = shape & 0b1111;
+        self.counter = 0;
+        self.step = 0;
+        self.holding = false;
+        self.attack = shape & 0b0100 != 0;
+    }
+
+    fn clock(&mut self) {
+        if self.holding {
+            return;
+        }
+        self.counter += 1;
+        if self.counter < self.period.max(1) {
+            return;
+        }
+        self.counter = 0;
+
+        if self.step < 31 {
+            self.step += 1;
+            return;
+        }
+
+        // end of a ramp: shapes 0-7 fall silent and hold
+        let continuous = self.shape & 0b1000 != 0;
+        let alternate = self.shape & 0b0010 != 0;
+        let hold = self.shape & 0b0001 != 0;
+        if !continuous {
+            self.attack = false;
+            self.holding = true;
+        } else if hold {
+            if alternate {
+                self.attack = !self.attack;
+            }
+            self.holding = true;
+        } else {
+            if alternate {
+                self.attack = !self.attack;
+            }
+            self.step = 0;
+        }
+    }
+
+    fn level(&self) -> u8 {
+        if self.attack {
+            self.step
+        } else if self.holding {
+            0
+        } else {
+            31 - self.step
+        }
+    }
+}
+
+/// Sunsoft 5B audio: a YM2149F with three square channels sharing one noise
+/// generator and one envelope generator.
+struct Sunsoft5b {
+    address: u8,
+    tones: [Tone; 3],
+    volumes: [u8; 3],
+    mixer: u8,
+    noise_period: u8,
+    noise_counter: u8,
+    noise_shift: u32,
+    noise_half: bool,
+    envelope: Envelope,
+    divider: u8,
+}
+
+impl Sunsoft5b {
+    fn new() -> Self {
+        Sunsoft5b {
+            address: 0,
+            tones: [Tone::new(), Tone::new(), Tone::new()],
+            volumes: [0; 3],
+            mixer: 0xFF,
+            noise_period: 0,
+            noise_counter: 0,
+            noise_shift: 1,
+            noise_half: false,
+            envelope: Envelope {
+                period: 0,
+                counter: 0,
+                shape: 0,
+                step: 0,
+                holding: true,
+                attack: false,
+            },
+            divider: 0,
+        }
+    }
+
+    fn write_address(&mut self, data: u8) {
+        self.address = data;
+    }
+
+    /// Writes to the selected register. The upper four bits of the address
+    /// latch have to be zero for the chip to respond.
+    fn write_data(&mut self, data: u8) {
+        match self.address {
+            0x00 | 0x02 | 0x04 => {
+                let tone = &mut self.tones[self.address as usize / 2];
+                tone.period = (tone.period & 0xF00) | data as u16;
+            }
+            0x01 | 0x03 | 0x05 => {
+                let tone = &mut self.tones[self.address as usize / 2];
+                tone.period = (tone.period & 0xFF) | ((data as u16 & 0x0F) << 8);
+            }
+            0x06 => self.noise_period = data & 0b1_1111,
+            0x07 => self.mixer = data,
+            0x08..=0x0A => self.volumes[self.address as usize - 8] = data & 0b1_1111,
+            0x0B => self.envelope.period = (self.envelope.period & 0xFF00) | data as u16,
+            0x0C => self.envelope.period = (self.envelope.period & 0x00FF) | (data as u16) << 8,
+            0x0D => self.envelope.restart(data),
+            _ => {}
+        }
+    }
+
+    /// Called once per CPU cycle.
+    fn clock(&mut self) {
+        self.divider += 1;
+        if self.divider < AUDIO_DIVIDER {
+            return;
+        }
+        self.divider = 0;
+
+        for tone in self.tones.iter_mut() {
+            tone.clock();
+        }
+        self.envelope.clock();
+
+        // the noise generator runs at half the tone rate
+        self.noise_half = !self.noise_half;
+        if self.noise_half {
+            self.noise_counter += 1;
+            if self.noise_counter >= self.noise_period.max(1) {
+                self.noise_counter = 0;
+                let feedback = (self.noise_shift ^ (self.noise_shift >> 3)) & 1;
+                self.noise_shift = (self.noise_shift >> 1) | (feedback << 16);
+            }
+        }
+    }
+
+    fn output(&self) -> f32 {
+        let noise = self.noise_shift & 1 != 0;
+        let mut output = 0.0;
+        for (channel, tone) in self.tones.iter().enumerate() {
+            let tone_off = self.mixer & (1 << channel) != 0;
+            let noise_off = self.mixer & (1 << (channel + 3)) != 0;
+            if !((tone.high || tone_off) && (noise || noise_off)) {
+                continue;
+            }
+            let volume = self.volumes[channel];
+            let level = if volume & 0b1_0000 != 0 {
+                self.envelope.level()
+            } else if volume == 0 {
+                0
+            } else {
+                // fixed volumes line up with the odd envelope levels
+                (volume & 0b1111) * 2 + 1
+            };
+            output += level_amplitude(level) * CHANNEL_LEVEL;
+        }
+        output
+    }
+}
+
+/// Mapper 69: Sunsoft FME-7, 5A and 5B, as used by Gimmick! and Batman: Return of the Joker.
+///
+/// Registers are written through a command/parameter pair at $8000/$A000:
+/// eight 1K CHR banks, three switchable 8K PRG banks plus a fourth at $6000 that
+/// can select PRG-RAM, mirroring, and a 16-bit IRQ counter clocked by the CPU.
+/// The 5B adds a YM2149-style sound chip at $C000/$E000. FME-7 and 5A boards
+/// simply never write there, so it is always present.
+pub struct Fme7 {
+    rom: Rom,
+    chr: Chr,
+    prg_ram: PrgRam,
+    command: u8,
+    chr_banks: [u8; 8],
+    prg_banks: [u8; 3],
+    // $6000-$7FFF: bits 0-5 bank, bit 6 selects RAM, bit 7 enables the RAM
+    low_bank: u8,
+    mirroring: Mirroring,
+
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_counter: u16,
+    irq_pending: bool,
+
+    audio: Sunsoft5b,
+}
+
+impl Fme7 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::new(&mut rom);
+        let prg_ram = PrgRam::new(&rom);
+        let mirroring = rom.screen_mirroring;
+        Fme7 {
+            rom,
+            chr,
+            prg_ram,
+            command: 0,
+            chr_banks: [0; 8],
+            prg_banks: [0; 3],
+            low_bank: 0,
+            mirroring,
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_counter: 0,
+            irq_pending: false,
+            audio: Sunsoft5b::new(),
+        }
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_banks[self.command as usize] = data,
+            0x8 => self.low_bank = data,
+            0x9..=0xB => self.prg_banks[self.command as usize - 0x9] = data & 0b11_1111,
+            0xC => {
+                self.mirroring = match data & 0b11 {
+                    0 => Mirroring::VERTICAL,
+                    1 => Mirroring::HORIZONTAL,
+                    2 => Mirroring::SINGLE_SCREEN_LOWER,
+                    _ => Mirroring::SINGLE_SCREEN_UPPER,
+                }
+            }
+            0xD => {
+                self.irq_enabled = data & 0b0000_0001 != 0;
+                self.irq_counter_enabled = data & 0b1000_0000 != 0;
+                self.irq_pending = false;
+            }
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | data as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00FF) | (data as u16) << 8,
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        let len = self.rom.prg_rom.len();
+        let bank = match addr {
+            0x6000..=0x7FFF if self.low_bank & 0b0100_0000 != 0 => {
+                let bank = (self.low_bank & 0b11_1111) as usize;
+                return if self.low_bank & 0b1000_0000 != 0 {
+                    self.prg_ram.read_bank(bank, addr)
+                } else {
+                    0
+                };
+            }
+            0x6000..=0x7FFF => (self.low_bank & 0b11_1111) as usize,
+            0x8000..=0x9FFF => self.prg_banks[0] as usize,
+            0xA000..=0xBFFF => self.prg_banks[1] as usize,
+            0xC000..=0xDFFF => self.prg_banks[2] as usize,
+            0xE000..=0xFFFF => last_bank(len, PRG_BANK_SIZE),
+            _ => return 0,
+        };
+        self.rom.prg_rom[bank_addr(len, PRG_BANK_SIZE, bank, addr)]
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.low_bank & 0b1100_0000 == 0b1100_0000 => {
+                let bank = (self.low_bank & 0b11_1111) as usize;
+                self.prg_ram.write_bank(bank, addr, data)
+            }
+            0x8000..=0x9FFF => self.command = data & 0b1111,
+            0xA000..=0xBFFF => self.write_parameter(data),
+            0xC000..=0xDFFF => self.audio.write_address(data),
+            0xE000..=0xFFFF => self.audio.write_data(data),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize;
+        self.chr.read_bank(CHR_BANK_SIZE, bank, addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize;
+        self.chr.write_bank(CHR_BANK_SIZE, bank, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock_cpu(&mut self) {
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.battery_ram()
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        self.prg_ram.load(data);
+    }
+}
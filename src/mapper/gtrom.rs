@@ -0,0 +1,104 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::chr::Chr;
+use crate::mapper::flash::Flash;
+use crate::mapper::{Mapper, Nametable};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x2000;
+const CHR_RAM_SIZE: usize = 0x4000;
+const NAMETABLE_PAGE_SIZE: usize = 0x2000;
+const NAMETABLE_RAM_SIZE: usize = 0x4000;
+
+/// Mapper 111: GTROM (Cheapocabra), a homebrew board by Membler Industries.
+///
+/// 32K PRG banks from an SST39SF040 the game can rewrite to save, two 8K banks
+/// of CHR-RAM, and two 8K pages of cartridge RAM used as four-screen nametables.
+/// The single register sits at $5000-$5FFF and is mirrored at $7000-$7FFF;
+/// its top two bits drive the board's LEDs.
+pub struct Gtrom {
+    rom: Rom,
+    chr: Chr,
+    nametable_ram: [u8; NAMETABLE_RAM_SIZE],
+    register: u8,
+    flash: Flash,
+}
+
+impl Gtrom {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::with_min_ram_size(&mut rom, CHR_RAM_SIZE);
+        Gtrom {
+            rom,
+            chr,
+            nametable_ram: [0; NAMETABLE_RAM_SIZE],
+            register: 0,
+            flash: Flash::new(),
+        }
+    }
+
+    fn flash_addr(&self, addr: u16) -> usize {
+        let bank = (self.register & 0b1111) as usize;
+        bank * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1))
+    }
+
+    fn chr_bank(&self) -> usize {
+        ((self.register >> 4) & 1) as usize
+    }
+
+    fn nametable_addr(&self, addr: u16) -> usize {
+        let page = ((self.register >> 5) & 1) as usize;
+        page * NAMETABLE_PAGE_SIZE + (addr as usize & 0x0FFF)
+    }
+}
+
+impl Mapper for Gtrom {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => self.flash.read(&self.rom.prg_rom, self.flash_addr(addr)),
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5000..=0x5FFF | 0x7000..=0x7FFF => self.register = data,
+            0x8000..=0xFFFF => {
+                let flash_addr = self.flash_addr(addr);
+                self.flash.write(&mut self.rom.prg_rom, flash_addr, data);
+            }
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read_bank(CHR_BANK_SIZE, self.chr_bank(), addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let bank = self.chr_bank();
+        self.chr.write_bank(CHR_BANK_SIZE, bank, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::FOUR_SCREEN
+    }
+
+    fn read_nametable(&mut self, addr: u16) -> Nametable {
+        Nametable::Data(self.nametable_ram[self.nametable_addr(addr)])
+    }
+
+    fn write_nametable(&mut self, addr: u16, data: u8) -> Option<u8> {
+        self.nametable_ram[self.nametable_addr(addr)] = data;
+        None
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.flash.modified().then_some(&self.rom.prg_rom[..])
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        if data.len() == self.rom.prg_rom.len() {
+            self.rom.prg_rom.copy_from_slice(data);
+            self.flash.mark_modified();
+        }
+    }
+}
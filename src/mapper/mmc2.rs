@@ -0,0 +1,207 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::chr::Chr;
+use crate::mapper::prg_ram::PrgRam;
+use crate::mapper::{bank_addr, last_bank, Mapper};
+
+const CHR_BANK_SIZE: usize = 0x1000;
+
+/// The CHR half of MMC2/MMC4. Each 4K pattern table has an $FD and an $FE bank,
+/// and the one in use flips when the PPU fetches tile $FD or $FE from that table.
+struct ChrLatches {
+    banks: [[u8; 2]; 2],
+    latches: [usize; 2],
+    // MMC2 only watches $0FD8/$0FE8 on the left table, MMC4 the whole tile row
+    exact_left_trigger: bool,
+}
+
+impl ChrLatches {
+    fn new(exact_left_trigger: bool) -> Self {
+        ChrLatches {
+            banks: [[0; 2]; 2],
+            latches: [1, 1],
+            exact_left_trigger,
+        }
+    }
+
+    fn bank(&self, addr: u16) -> usize {
+        let table = (addr >> 12) as usize & 1;
+        self.banks[table][self.latches[table]] as usize
+    }
+
+    fn read(&mut self, chr: &Chr, addr: u16) -> u8 {
+        let table = (addr >> 12) as usize & 1;
+        let data = chr.read_bank(CHR_BANK_SIZE, self.bank(addr), addr);
+
+        // the latch flips after the fetch, so this read still used the old bank
+        match (addr & 0x1FF8, addr) {
+            (_, 0x0FD8) | (0x1FD8, _) => self.latches[table] = 0,
+            (_, 0x0FE8) | (0x1FE8, _) => self.latches[table] = 1,
+            (0x0FD8, _) if !self.exact_left_trigger => self.latches[table] = 0,
+            (0x0FE8, _) if !self.exact_left_trigger => self.latches[table] = 1,
+            _ => {}
+        }
+        data
+    }
+
+    /// Writes go to the selected bank without touching the latches.
+    fn write(&self, chr: &mut Chr, addr: u16, data: u8) {
+        chr.write_bank(CHR_BANK_SIZE, self.bank(addr), addr, data);
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        let data = data & 0b1_1111;
+        match addr {
+            0xB000..=0xBFFF => self.banks[0][0] = data,
+            0xC000..=0xCFFF => self.banks[0][1] = data,
+            0xD000..=0xDFFF => self.banks[1][0] = data,
+            0xE000..=0xEFFF => self.banks[1][1] = data,
+            _ => {}
+        }
+    }
+}
+
+fn mirroring_register(data: u8) -> Mirroring {
+    if data & 1 == 0 {
+        Mirroring::VERTICAL
+    } else {
+        Mirroring::HORIZONTAL
+    }
+}
+
+/// Mapper 9: MMC2/PxROM, as used by Punch-Out!!.
+///
+/// One switchable 8K PRG bank at $8000 with the last three banks fixed behind it.
+pub struct Mmc2 {
+    rom: Rom,
+    chr: Chr,
+    prg_bank: u8,
+    chr_latches: ChrLatches,
+    mirroring: Mirroring,
+}
+
+impl Mmc2 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::new(&mut rom);
+        let mirroring = rom.screen_mirroring;
+        Mmc2 {
+            rom,
+            chr,
+            prg_bank: 0,
+            chr_latches: ChrLatches::new(true),
+            mirroring,
+        }
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        const PRG_BANK_SIZE: usize = 0x2000;
+        let len = self.rom.prg_rom.len();
+        let bank = match addr {
+            0x8000..=0x9FFF => self.prg_bank as usize,
+            0xA000..=0xFFFF => {
+                let fixed = (0xFFFF - addr as usize) / PRG_BANK_SIZE;
+                last_bank(len, PRG_BANK_SIZE).saturating_sub(fixed)
+            }
+            _ => return 0,
+        };
+        self.rom.prg_rom[bank_addr(len, PRG_BANK_SIZE, bank, addr)]
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xA000..=0xAFFF => self.prg_bank = data & 0b1111,
+            0xB000..=0xEFFF => self.chr_latches.write_register(addr, data),
+            0xF000..=0xFFFF => self.mirroring = mirroring_register(data),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr_latches.read(&self.chr, addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr_latches.write(&mut self.chr, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+/// Mapper 10: MMC4/FxROM, as used by Fire Emblem.
+///
+/// A switchable 16K PRG bank at $8000, the last bank fixed at $C000 and
+/// PRG-RAM at $6000.
+pub struct Mmc4 {
+    rom: Rom,
+    chr: Chr,
+    prg_ram: PrgRam,
+    prg_bank: u8,
+    chr_latches: ChrLatches,
+    mirroring: Mirroring,
+}
+
+impl Mmc4 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::new(&mut rom);
+        let prg_ram = PrgRam::new(&rom);
+        let mirroring = rom.screen_mirroring;
+        Mmc4 {
+            rom,
+            chr,
+            prg_ram,
+            prg_bank: 0,
+            chr_latches: ChrLatches::new(false),
+            mirroring,
+        }
+    }
+}
+
+impl Mapper for Mmc4 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        const PRG_BANK_SIZE: usize = 0x4000;
+        let len = self.rom.prg_rom.len();
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram.read(addr),
+            0x8000..=0xBFFF => {
+                self.rom.prg_rom[bank_addr(len, PRG_BANK_SIZE, self.prg_bank as usize, addr)]
+            }
+            0xC000..=0xFFFF => {
+                self.rom.prg_rom[bank_addr(len, PRG_BANK_SIZE, last_bank(len, PRG_BANK_SIZE), addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram.write(addr, data),
+            0xA000..=0xAFFF => self.prg_bank = data & 0b1111,
+            0xB000..=0xEFFF => self.chr_latches.write_register(addr, data),
+            0xF000..=0xFFFF => self.mirroring = mirroring_register(data),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr_latches.read(&self.chr, addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr_latches.write(&mut self.chr, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.battery_ram()
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        self.prg_ram.load(data);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::chr::Chr;
+use crate::mapper::prg_ram::PrgRam;
+use crate::mapper::{bank_addr, last_bank, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// A12 has to stay low for this many M2 cycles before a rising edge clocks the
+/// IRQ counter. This filters out the edges from the interleaved sprite fetches.
+const A12_LOW_CYCLES: u8 = 3;
+
+/// Mapper 4: MMC3/TxROM.
+///
+/// Two switchable 8K PRG banks plus two fixed ones, two 2K and four 1K CHR banks,
+/// PRG-RAM at $6000 and a scanline counter clocked by rising edges on PPU A12.
+pub struct Mmc3 {
+    rom: Rom,
+    chr: Chr,
+    prg_ram: PrgRam,
+    bank_select: u8,
+    registers: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    prg_ram_write_protect: bool,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    a12: bool,
+    a12_low_cycles: u8,
+}
+
+impl Mmc3 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::new(&mut rom);
+        let prg_ram = PrgRam::new(&rom);
+        let mirroring = rom.screen_mirroring;
+        Mmc3 {
+            rom,
+            chr,
+            prg_ram,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring,
+            prg_ram_enabled: true,
+            prg_ram_write_protect: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12: false,
+            a12_low_cycles: 0,
+        }
+    }
+
+    fn prg_bank(&self, addr: u16) -> usize {
+        let last = last_bank(self.rom.prg_rom.len(), PRG_BANK_SIZE);
+        let second_last = last.saturating_sub(1);
+        let swap_mode = self.bank_select & 0b0100_0000 != 0;
+        match (addr, swap_mode) {
+            (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => self.registers[6] as usize,
+            (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => second_last,
+            (0xA000..=0xBFFF, _) => self.registers[7] as usize,
+            _ => last,
+        }
+    }
+
+    fn chr_bank(&self, addr: u16) -> usize {
+        // CHR inversion swaps the 2K and 1K halves of the pattern tables
+        let addr = if self.bank_select & 0b1000_0000 != 0 {
+            addr ^ 0x1000
+        } else {
+            addr
+        };
+        match addr {
+            0x0000..=0x07FF => (self.registers[0] & 0xFE) as usize + ((addr as usize >> 10) & 1),
+            0x0800..=0x0FFF => (self.registers[1] & 0xFE) as usize + ((addr as usize >> 10) & 1),
+            _ => self.registers[2 + (addr as usize - 0x1000) / CHR_BANK_SIZE] as usize,
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled => self.prg_ram.read(addr),
+            0x8000..=0xFFFF => {
+                let len = self.rom.prg_rom.len();
+                self.rom.prg_rom[bank_addr(len, PRG_BANK_SIZE, self.prg_bank(addr), addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        let even = addr & 1 == 0;
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled && !self.prg_ram_write_protect => {
+                self.prg_ram.write(addr, data);
+            }
+            0x8000..=0x9FFF if even => self.bank_select = data,
+            0x8000..=0x9FFF => self.registers[(self.bank_select & 0b111) as usize] = data,
+            0xA000..=0xBFFF if even => {
+                // boards wired for four-screen VRAM ignore the mirroring register
+                if self.rom.screen_mirroring == Mirroring::FOUR_SCREEN {
+                    return;
+                }
+                self.mirroring = if data & 1 == 0 {
+                    Mirroring::VERTICAL
+                } else {
+                    Mirroring::HORIZONTAL
+                };
+            }
+            0xA000..=0xBFFF => {
+                self.prg_ram_enabled = data & 0b1000_0000 != 0;
+                self.prg_ram_write_protect = data & 0b0100_0000 != 0;
+            }
+            0xC000..=0xDFFF if even => self.irq_latch = data,
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000..=0xFFFF if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.notify_ppu_address(addr);
+        self.chr.read_bank(CHR_BANK_SIZE, self.chr_bank(addr), addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.notify_ppu_address(addr);
+        let bank = self.chr_bank(addr);
+        self.chr.write_bank(CHR_BANK_SIZE, bank, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock_cpu(&mut self) {
+        if !self.a12 {
+            self.a12_low_cycles = self.a12_low_cycles.saturating_add(1);
+        }
+    }
+
+    fn notify_ppu_address(&mut self, addr: u16) {
+        let a12 = addr & 0x1000 != 0;
+        if a12 {
+            if !self.a12 && self.a12_low_cycles >= A12_LOW_CYCLES {
+                self.clock_irq_counter();
+            }
+            self.a12_low_cycles = 0;
+        }
+        self.a12 = a12;
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.battery_ram()
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        self.prg_ram.load(data);
+    }
+}
@@ -0,0 +1,599 @@
+use std::cell::Cell;
+
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::chr::Chr;
+use crate::mapper::prg_ram::PrgRam;
+use crate::mapper::{bank_addr, Mapper, Nametable};
+
+const PRG_PAGE_SIZE: usize = 0x2000;
+/// Enough for the largest ExROM board, for iNES headers that leave the size at
+/// its 8K default.
+const PRG_RAM_SIZE: usize = 0x10000;
+const EXRAM_SIZE: usize = 0x400;
+
+/// Pattern fetches the PPU makes per scanline before the sprite fetches start,
+/// and after them. Sprite fetches sit in between.
+const BACKGROUND_FETCHES: u8 = 64;
+const SPRITE_FETCHES_END: u8 = BACKGROUND_FETCHES + 16;
+
+/// CPU cycles without PPU reads after which the MMC5 decides rendering stopped.
+const IDLE_CYCLES: u8 = 3;
+
+/// The audio frame sequencer runs at a fixed 240 Hz.
+const AUDIO_FRAME_CYCLES: u16 = 7457;
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+const PULSE_OUTPUT_STEP: f32 = 0.00752;
+const PCM_OUTPUT_STEP: f32 = 0.0017;
+
+/// An APU-style pulse channel without the sweep unit.
+struct Pulse {
+    duty: u8,
+    halt: bool,
+    constant_volume: bool,
+    volume: u8,
+    period: u16,
+    timer: u16,
+    step: u8,
+    enabled: bool,
+    length: u8,
+    envelope_start: bool,
+    envelope_divider: u8,
+    envelope_decay: u8,
+}
+
+impl Pulse {
+    fn new() -> Self {
+        Pulse {
+            duty: 0,
+            halt: false,
+            constant_volume: false,
+            volume: 0,
+            period: 0,
+            timer: 0,
+            step: 0,
+            enabled: false,
+            length: 0,
+            envelope_start: false,
+            envelope_divider: 0,
+            envelope_decay: 0,
+        }
+    }
+
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.duty = data >> 6;
+                self.halt = data & 0b10_0000 != 0;
+                self.constant_volume = data & 0b1_0000 != 0;
+                self.volume = data & 0b1111;
+            }
+            2 => self.period = (self.period & 0x700) | data as u16,
+            3 => {
+                self.period = (self.period & 0xFF) | ((data as u16 & 0b111) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.step = 0;
+                self.envelope_start = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 0b111;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_frame(&mut self) {
+        if self.envelope_start {
+            self.envelope_start = false;
+            self.envelope_decay = 15;
+            self.envelope_divider = self.volume;
+        } else if self.envelope_divider == 0 {
+            self.envelope_divider = self.volume;
+            if self.envelope_decay > 0 {
+                self.envelope_decay -= 1;
+            } else if self.halt {
+                self.envelope_decay = 15;
+            }
+        } else {
+            self.envelope_divider -= 1;
+        }
+
+        if !self.halt && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || DUTY_TABLE[self.duty as usize][self.step as usize] == 0 {
+            0
+        } else if self.constant_volume {
+            self.volume
+        } else {
+            self.envelope_decay
+        }
+    }
+}
+
+/// Mapper 5: MMC5/ExROM, as used by Castlevania III and the Koei strategy games.
+///
+/// The MMC5 has no PPU signals beyond the address bus, so like the real chip it
+/// works out where the PPU is by watching fetches: three reads of the same
+/// nametable address mark the start of a scanline, and counting pattern fetches
+/// from there tells background fetches from sprite fetches.
+pub struct Mmc5 {
+    rom: Rom,
+    chr: Chr,
+    prg_ram: PrgRam,
+    exram: [u8; EXRAM_SIZE],
+
+    prg_mode: u8,
+    prg_banks: [u8; 5],
+    prg_ram_protect: [u8; 2],
+    chr_mode: u8,
+    sprite_chr_banks: [u16; 8],
+    background_chr_banks: [u16; 4],
+    chr_upper_bits: u16,
+    last_chr_write_background: bool,
+    exram_mode: u8,
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    // snooped from $2000/$2001
+    tall_sprites: bool,
+    rendering_enabled: bool,
+
+    last_ppu_addr: u16,
+    nametable_matches: u8,
+    idle_cycles: u8,
+    in_frame: bool,
+    scanline: u8,
+    pattern_fetches: u8,
+    // latched on a tile's nametable fetch for its attribute and pattern fetches
+    tile_exram: u8,
+    tile_split_y: Option<u8>,
+
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: Cell<bool>,
+
+    pulses: [Pulse; 2],
+    pcm: u8,
+    audio_frame_divider: u16,
+    odd_cycle: bool,
+}
+
+impl Mmc5 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::new(&mut rom);
+        let prg_ram = if rom.nes2 || rom.prg_ram_size + rom.prg_nvram_size > 0x2000 {
+            PrgRam::new(&rom)
+        } else {
+            PrgRam::with_min_size(&rom, PRG_RAM_SIZE)
+        };
+        Mmc5 {
+            rom,
+            chr,
+            prg_ram,
+            exram: [0; EXRAM_SIZE],
+            prg_mode: 3,
+            prg_banks: [0, 0, 0, 0, 0xFF],
+            prg_ram_protect: [0; 2],
+            chr_mode: 0,
+            sprite_chr_banks: [0; 8],
+            background_chr_banks: [0; 4],
+            chr_upper_bits: 0,
+            last_chr_write_background: false,
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            tall_sprites: false,
+            rendering_enabled: false,
+            last_ppu_addr: 0,
+            nametable_matches: 0,
+            idle_cycles: 0,
+            in_frame: false,
+            scanline: 0,
+            pattern_fetches: 0,
+            tile_exram: 0,
+            tile_split_y: None,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: Cell::new(false),
+            pulses: [Pulse::new(), Pulse::new()],
+            pcm: 0,
+            audio_frame_divider: 0,
+            odd_cycle: false,
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0b10, 0b01]
+    }
+
+    /// The bank register covering `addr` in the current PRG mode and the size of
+    /// its window in 8K pages. $5117 always selects ROM.
+    fn prg_window(&self, addr: u16) -> (u8, usize) {
+        let quarter = ((addr - 0x8000) as usize) / PRG_PAGE_SIZE;
+        let last = self.prg_banks[4] | 0x80;
+        match (self.prg_mode, quarter) {
+            (0, _) => (last, 4),
+            (1, 0..=1) => (self.prg_banks[2], 2),
+            (1, _) => (last, 2),
+            (2, 0..=1) => (self.prg_banks[2], 2),
+            (2, 2) => (self.prg_banks[3], 1),
+            (2, _) => (last, 1),
+            (_, 3) => (last, 1),
+            (_, _) => (self.prg_banks[1 + quarter], 1),
+        }
+    }
+
+    /// Resolves `addr` to a page of PRG-ROM (true) or PRG-RAM (false).
+    fn prg_page(&self, addr: u16) -> (bool, usize) {
+        if addr < 0x8000 {
+            return (false, (self.prg_banks[0] & 0b111) as usize);
+        }
+        let (register, pages) = self.prg_window(addr);
+        let page =
+            ((register & 0x7F) as usize & !(pages - 1)) + ((addr as usize >> 13) & (pages - 1));
+        if register & 0x80 != 0 {
+            (true, page)
+        } else {
+            (false, page & 0b111)
+        }
+    }
+
+    fn read_prg_page(&self, addr: u16) -> u8 {
+        match self.prg_page(addr) {
+            (true, page) => {
+                let len = self.rom.prg_rom.len();
+                self.rom.prg_rom[bank_addr(len, PRG_PAGE_SIZE, page, addr)]
+            }
+            (false, page) => self.prg_ram.read_bank(page, addr),
+        }
+    }
+
+    fn write_prg_page(&mut self, addr: u16, data: u8) {
+        if let (false, page) = self.prg_page(addr) {
+            if self.prg_ram_writable() {
+                self.prg_ram.write_bank(page, addr, data);
+            }
+        }
+    }
+
+    fn write_chr_bank(&mut self, register: usize, data: u8) {
+        let bank = data as u16 | self.chr_upper_bits;
+        if register < 8 {
+            self.sprite_chr_banks[register] = bank;
+            self.last_chr_write_background = false;
+        } else {
+            self.background_chr_banks[register - 8] = bank;
+            self.last_chr_write_background = true;
+        }
+    }
+
+    /// Bank number and size for a pattern fetch through one of the two register sets.
+    /// The background set only has four registers and repeats them for $1000-$1FFF.
+    fn chr_bank(&self, background: bool, addr: u16) -> (usize, usize) {
+        let register = |i: usize| {
+            if background {
+                self.background_chr_banks[i % 4]
+            } else {
+                self.sprite_chr_banks[i]
+            }
+        };
+        let addr = addr as usize;
+        let (bank, size) = match self.chr_mode {
+            0 => (register(7), 0x2000),
+            1 => (register((addr / 0x1000) * 4 + 3), 0x1000),
+            2 => (register((addr / 0x0800) * 2 + 1), 0x0800),
+            _ => (register(addr / 0x0400), 0x0400),
+        };
+        (bank as usize, size)
+    }
+
+    fn fetching_sprites(&self) -> bool {
+        self.in_frame && (BACKGROUND_FETCHES..SPRITE_FETCHES_END).contains(&self.pattern_fetches)
+    }
+
+    fn detect_scanline(&mut self) {
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_compare && self.irq_compare != 0 {
+                self.irq_pending.set(true);
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+        }
+        self.pattern_fetches = 0;
+    }
+
+    /// Tile column and scanline of a background nametable fetch. The two tiles
+    /// fetched after the sprites belong to the next scanline.
+    fn background_tile(&self) -> (u8, u8) {
+        if self.pattern_fetches < BACKGROUND_FETCHES {
+            (2 + self.pattern_fetches / 2, self.scanline)
+        } else {
+            (
+                (self.pattern_fetches - SPRITE_FETCHES_END) / 2,
+                self.scanline.wrapping_add(1),
+            )
+        }
+    }
+
+    fn in_split(&self, tile: u8) -> bool {
+        if self.split_control & 0b1000_0000 == 0 || self.exram_mode > 1 {
+            return false;
+        }
+        let threshold = self.split_control & 0b1_1111;
+        if self.split_control & 0b0100_0000 == 0 {
+            tile < threshold
+        } else {
+            tile >= threshold
+        }
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x5015 => (self.pulses[0].length > 0) as u8 | ((self.pulses[1].length > 0) as u8) << 1,
+            0x5204 => {
+                let status = (self.irq_pending.get() as u8) << 7 | (self.in_frame as u8) << 6;
+                self.irq_pending.set(false);
+                status
+            }
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => self.exram[(addr - 0x5C00) as usize],
+            0x6000..=0xFFFF => self.read_prg_page(addr),
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5000..=0x5003 => self.pulses[0].write(addr & 0b11, data),
+            0x5004..=0x5007 => self.pulses[1].write(addr & 0b11, data),
+            0x5011 if data != 0 => self.pcm = data,
+            0x5015 => {
+                self.pulses[0].set_enabled(data & 0b01 != 0);
+                self.pulses[1].set_enabled(data & 0b10 != 0);
+            }
+            0x5100 => self.prg_mode = data & 0b11,
+            0x5101 => self.chr_mode = data & 0b11,
+            0x5102 => self.prg_ram_protect[0] = data & 0b11,
+            0x5103 => self.prg_ram_protect[1] = data & 0b11,
+            0x5104 => self.exram_mode = data & 0b11,
+            0x5105 => self.nametable_mapping = data,
+            0x5106 => self.fill_tile = data,
+            0x5107 => self.fill_attribute = data & 0b11,
+            0x5113..=0x5117 => self.prg_banks[(addr - 0x5113) as usize] = data,
+            0x5120..=0x512B => self.write_chr_bank((addr - 0x5120) as usize, data),
+            0x5130 => self.chr_upper_bits = (data as u16 & 0b11) << 8,
+            0x5200 => self.split_control = data,
+            0x5201 => self.split_scroll = data,
+            0x5202 => self.split_bank = data,
+            0x5203 => self.irq_compare = data,
+            0x5204 => self.irq_enabled = data & 0b1000_0000 != 0,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            0x5C00..=0x5FFF => {
+                let addr = (addr - 0x5C00) as usize;
+                match self.exram_mode {
+                    // while used for nametables, writes outside rendering store 0
+                    0 | 1 => self.exram[addr] = if self.in_frame { data } else { 0 },
+                    2 => self.exram[addr] = data,
+                    _ => {}
+                }
+            }
+            0x6000..=0xDFFF => self.write_prg_page(addr, data),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.idle_cycles = 0;
+        self.nametable_matches = 0;
+        self.last_ppu_addr = addr;
+
+        let sprites = self.fetching_sprites();
+        let background = self.in_frame && !sprites;
+        if self.in_frame {
+            self.pattern_fetches = self.pattern_fetches.saturating_add(1);
+        }
+
+        if background {
+            if let Some(y) = self.tile_split_y {
+                // the split region has its own 4K bank and vertical scroll
+                let addr = (addr & 0x0FF8) | (y as u16 & 0b111);
+                return self.chr.read_bank(0x1000, self.split_bank as usize, addr);
+            }
+            if self.exram_mode == 1 {
+                let bank =
+                    (self.tile_exram & 0b11_1111) as usize | (self.chr_upper_bits as usize >> 2);
+                return self.chr.read_bank(0x1000, bank, addr);
+            }
+        }
+
+        let use_background_set = if self.tall_sprites && self.rendering_enabled && self.in_frame {
+            !sprites
+        } else {
+            self.last_chr_write_background
+        };
+        let (bank, size) = self.chr_bank(use_background_set, addr);
+        self.chr.read_bank(size, bank, addr)
+    }
+
+    /// CHR-RAM is written outside rendering, through the set of banks written
+    /// last.
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let (bank, size) = self.chr_bank(self.last_chr_write_background, addr);
+        self.chr.write_bank(size, bank, addr, data);
+    }
+
+    /// The standard arrangement closest to $5105. `read_nametable` is what really
+    /// decides where each quadrant goes.
+    fn mirroring(&self) -> Mirroring {
+        match self.nametable_mapping {
+            0x00 => Mirroring::SINGLE_SCREEN_LOWER,
+            0x55 => Mirroring::SINGLE_SCREEN_UPPER,
+            0x50 => Mirroring::HORIZONTAL,
+            _ => Mirroring::VERTICAL,
+        }
+    }
+
+    fn notify_cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x2000 => self.tall_sprites = data & 0b10_0000 != 0,
+            0x2001 => self.rendering_enabled = data & 0b1_1000 != 0,
+            _ => {}
+        }
+    }
+
+    fn read_nametable(&mut self, addr: u16) -> Nametable {
+        self.idle_cycles = 0;
+        if addr == self.last_ppu_addr {
+            self.nametable_matches += 1;
+            if self.nametable_matches == 2 {
+                self.detect_scanline();
+            }
+        } else {
+            self.nametable_matches = 0;
+        }
+        self.last_ppu_addr = addr;
+
+        let offset = (addr & 0x3FF) as usize;
+        let attribute = offset >= 0x3C0;
+        let background = self.in_frame && !self.fetching_sprites();
+
+        if background && !attribute {
+            let (tile, line) = self.background_tile();
+            self.tile_split_y = if self.in_split(tile) {
+                Some(((self.split_scroll as u16 + line as u16) % 240) as u8)
+            } else {
+                None
+            };
+            self.tile_exram = self.exram[offset];
+        }
+
+        if background {
+            if let Some(y) = self.tile_split_y {
+                let (tile, _) = self.background_tile();
+                let tile = tile as usize % 32;
+                let y = y as usize;
+                return if attribute {
+                    let shift = ((y >> 2) & 0b100) | (tile & 0b10);
+                    let palette = (self.exram[0x3C0 + (y / 32) * 8 + tile / 4] >> shift) & 0b11;
+                    Nametable::Data(palette * 0x55)
+                } else {
+                    Nametable::Data(self.exram[(y / 8) * 32 + tile])
+                };
+            }
+            if attribute && self.exram_mode == 1 {
+                return Nametable::Data((self.tile_exram >> 6) * 0x55);
+            }
+        }
+
+        let quadrant = (addr >> 10) & 0b11;
+        match (self.nametable_mapping >> (quadrant * 2)) & 0b11 {
+            page @ (0 | 1) => Nametable::Vram(page),
+            2 if self.exram_mode <= 1 => Nametable::Data(self.exram[offset]),
+            2 => Nametable::Data(0),
+            _ if attribute => Nametable::Data(self.fill_attribute * 0x55),
+            _ => Nametable::Data(self.fill_tile),
+        }
+    }
+
+    fn write_nametable(&mut self, addr: u16, data: u8) -> Option<u8> {
+        let quadrant = (addr >> 10) & 0b11;
+        match (self.nametable_mapping >> (quadrant * 2)) & 0b11 {
+            page @ (0 | 1) => Some(page),
+            2 => {
+                if self.exram_mode <= 1 {
+                    self.exram[(addr & 0x3FF) as usize] = data;
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn clock_cpu(&mut self) {
+        self.idle_cycles = self.idle_cycles.saturating_add(1);
+        if self.idle_cycles >= IDLE_CYCLES {
+            self.in_frame = false;
+        }
+
+        self.odd_cycle = !self.odd_cycle;
+        if self.odd_cycle {
+            for pulse in self.pulses.iter_mut() {
+                pulse.clock_timer();
+            }
+        }
+        self.audio_frame_divider += 1;
+        if self.audio_frame_divider == AUDIO_FRAME_CYCLES {
+            self.audio_frame_divider = 0;
+            for pulse in self.pulses.iter_mut() {
+                pulse.clock_frame();
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_enabled && self.irq_pending.get()
+    }
+
+    fn audio_output(&self) -> f32 {
+        let pulses = self.pulses[0].output() + self.pulses[1].output();
+        pulses as f32 * PULSE_OUTPUT_STEP + self.pcm as f32 * PCM_OUTPUT_STEP
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.battery_ram()
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        self.prg_ram.load(data);
+    }
+}
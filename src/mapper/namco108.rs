@@ -0,0 +1,131 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::chr::Chr;
+use crate::mapper::prg_ram::PrgRam;
+use crate::mapper::{bank_addr, last_bank, vram_page, Mapper, Nametable};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Mappers 206, 88, 95 and 154: Namco 108 and its relatives, as used by
+/// Gauntlet, Quinty and Dragon Buster.
+///
+/// The MMC3's predecessor: the same eight bank registers behind a select/data
+/// pair at $8000/$8001, but with no PRG or CHR swap modes, no mirroring control,
+/// no PRG-RAM (beyond what a trainer needs) and no IRQ. The variants differ in how the CHR lines are wired:
+/// - 88 puts the 2K banks in the first 64K of CHR and the 1K banks in the second.
+/// - 154 is 88 plus one-screen mirroring from bit 6 of any write.
+/// - 95 feeds bit 5 of the 2K bank registers to the CIRAM page select.
+pub struct Namco108 {
+    rom: Rom,
+    chr: Chr,
+    prg_ram: PrgRam,
+    bank_select: u8,
+    registers: [u8; 8],
+    mirroring: Mirroring,
+}
+
+impl Namco108 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::new(&mut rom);
+        let prg_ram = PrgRam::for_trainer(&rom);
+        let mirroring = rom.screen_mirroring;
+        Namco108 {
+            rom,
+            chr,
+            prg_ram,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring,
+        }
+    }
+
+    fn chr_bank(&self, addr: u16) -> usize {
+        let odd = (addr as usize >> 10) & 1;
+        let (bank, high_half) = match addr {
+            0x0000..=0x07FF => ((self.registers[0] & 0xFE) as usize + odd, false),
+            0x0800..=0x0FFF => ((self.registers[1] & 0xFE) as usize + odd, false),
+            _ => (
+                self.registers[2 + (addr as usize - 0x1000) / CHR_BANK_SIZE] as usize,
+                true,
+            ),
+        };
+        match self.rom.mapper {
+            88 | 154 => (bank & 0x3F) | (high_half as usize) << 6,
+            95 => bank & 0x1F,
+            _ => bank & 0x3F,
+        }
+    }
+
+    /// Mapper 95 picks the CIRAM page for the top half of the nametables with
+    /// R0 and for the bottom half with R1.
+    fn nametable_page(&self, addr: u16) -> u8 {
+        if self.rom.mapper != 95 {
+            return vram_page(self.mirroring, addr);
+        }
+        let register = if addr & 0x0800 == 0 { 0 } else { 1 };
+        (self.registers[register] >> 5) & 1
+    }
+}
+
+impl Mapper for Namco108 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        let len = self.rom.prg_rom.len();
+        let bank = match addr {
+            0x6000..=0x7FFF => return self.prg_ram.read(addr),
+            0x8000..=0x9FFF => (self.registers[6] & 0x0F) as usize,
+            0xA000..=0xBFFF => (self.registers[7] & 0x0F) as usize,
+            0xC000..=0xFFFF => {
+                let last = last_bank(len, PRG_BANK_SIZE);
+                if addr < 0xE000 {
+                    last.saturating_sub(1)
+                } else {
+                    last
+                }
+            }
+            _ => return 0,
+        };
+        self.rom.prg_rom[bank_addr(len, PRG_BANK_SIZE, bank, addr)]
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            if addr >= 0x6000 {
+                self.prg_ram.write(addr, data);
+            }
+            return;
+        }
+        if self.rom.mapper == 154 {
+            self.mirroring = if data & 0b0100_0000 == 0 {
+                Mirroring::SINGLE_SCREEN_LOWER
+            } else {
+                Mirroring::SINGLE_SCREEN_UPPER
+            };
+        }
+        match addr {
+            0x8000..=0x9FFF if addr & 1 == 0 => self.bank_select = data & 0b111,
+            0x8000..=0x9FFF => self.registers[self.bank_select as usize] = data,
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read_bank(CHR_BANK_SIZE, self.chr_bank(addr), addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let bank = self.chr_bank(addr);
+        self.chr.write_bank(CHR_BANK_SIZE, bank, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn read_nametable(&mut self, addr: u16) -> Nametable {
+        Nametable::Vram(self.nametable_page(addr))
+    }
+
+    fn write_nametable(&mut self, addr: u16, _data: u8) -> Option<u8> {
+        Some(self.nametable_page(addr))
+    }
+}
@@ -0,0 +1,286 @@
+use std::cell::Cell;
+
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::chr::Chr;
+use crate::mapper::prg_ram::PrgRam;
+use crate::mapper::{bank_addr, last_bank, Mapper, Nametable};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const INTERNAL_RAM_SIZE: usize = 0x80;
+
+/// CHR bank values from here up select a CIRAM page instead of CHR-ROM.
+const CIRAM_BANKS: u8 = 0xE0;
+
+/// The 15-bit IRQ counter stops, and asserts /IRQ, when it reaches this.
+const IRQ_COUNTER_MAX: u16 = 0x7FFF;
+
+/// The sound unit updates one channel every 15 CPU cycles.
+const CYCLES_PER_CHANNEL: u8 = 15;
+
+/// Channel registers sit at the top of internal RAM, 8 bytes per channel,
+/// channel 7 last.
+const CHANNEL_REGISTERS: usize = 0x40;
+
+/// Scale of one output step, putting a full volume channel a little above an
+/// APU pulse channel, where the loudest boards sit.
+const OUTPUT_STEP: f32 = 0.0012;
+
+/// Mapper 19: Namco 129 and 163, as used by Megami Tensei II and King of Kings.
+///
+/// Three switchable 8K PRG banks, eight 1K CHR banks, four nametable banks that
+/// can point at CHR-ROM or CIRAM, a 15-bit CPU-cycle IRQ counter and PRG-RAM.
+/// The chip also has 128 bytes of internal RAM, shared between save data and a
+/// wavetable sound unit of up to eight channels that are played one at a time.
+/// With a battery both the PRG-RAM and the internal RAM are saved.
+pub struct Namco163 {
+    rom: Rom,
+    chr: Chr,
+    // the internal RAM is chip RAM after the PRG-RAM
+    prg_ram: PrgRam,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    nametable_banks: [u8; 4],
+    // $F800: write protection, and the internal RAM address port
+    write_protect: u8,
+    // a Cell since reads through $4800 advance it too
+    ram_address: Cell<u8>,
+    ram_auto_increment: bool,
+
+    irq_counter: u16,
+    irq_enabled: bool,
+
+    sound_disabled: bool,
+    current_channel: u8,
+    channel_divider: u8,
+    output: f32,
+}
+
+impl Namco163 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::new(&mut rom);
+        let prg_ram = PrgRam::with_chip_ram(&rom, INTERNAL_RAM_SIZE);
+        Namco163 {
+            rom,
+            chr,
+            prg_ram,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            nametable_banks: [0; 4],
+            write_protect: 0,
+            ram_address: Cell::new(0),
+            ram_auto_increment: false,
+            irq_counter: 0,
+            irq_enabled: false,
+            sound_disabled: false,
+            current_channel: 7,
+            channel_divider: 0,
+            output: 0.0,
+        }
+    }
+
+    fn internal_ram(&self) -> &[u8] {
+        self.prg_ram.chip_ram()
+    }
+
+    fn internal_ram_mut(&mut self) -> &mut [u8] {
+        self.prg_ram.chip_ram_mut()
+    }
+
+    fn prg_ram_writable(&self, addr: u16) -> bool {
+        let window = (addr - 0x6000) / 0x800;
+        self.write_protect & 0xF0 == 0x40 && self.write_protect & (1 << window) == 0
+    }
+
+    fn advance_ram_address(&self) {
+        if self.ram_auto_increment {
+            self.ram_address.set((self.ram_address.get() + 1) & 0x7F);
+        }
+    }
+
+    fn read_chr_bank(&self, bank: u8, addr: u16) -> u8 {
+        self.chr.read_bank(CHR_BANK_SIZE, bank as usize, addr)
+    }
+
+    fn write_chr_bank(&mut self, bank: u8, addr: u16, data: u8) {
+        self.chr
+            .write_bank(CHR_BANK_SIZE, bank as usize, addr, data)
+    }
+
+    /// Number of channels being played, counting down from channel 7.
+    fn enabled_channels(&self) -> u8 {
+        ((self.internal_ram()[0x7F] >> 4) & 0b111) + 1
+    }
+
+    /// Steps `channel`'s phase and returns its sample, centered around zero.
+    fn update_channel(&mut self, channel: u8) -> i16 {
+        let base = CHANNEL_REGISTERS + channel as usize * 8;
+        let ram = self.internal_ram_mut();
+        let frequency =
+            ram[base] as u32 | (ram[base + 2] as u32) << 8 | (ram[base + 4] as u32 & 0b11) << 16;
+        let phase =
+            ram[base + 1] as u32 | (ram[base + 3] as u32) << 8 | (ram[base + 5] as u32) << 16;
+        let length = 256 - (ram[base + 4] & 0xFC) as u32;
+        let phase = (phase + frequency) % (length << 16);
+        ram[base + 1] = phase as u8;
+        ram[base + 3] = (phase >> 8) as u8;
+        ram[base + 5] = (phase >> 16) as u8;
+
+        // 4-bit samples, packed low nibble first
+        let sample_addr = (ram[base + 6] as u32 + (phase >> 16)) as usize & 0xFF;
+        let byte = ram[sample_addr >> 1];
+        let sample = if sample_addr & 1 == 0 {
+            byte & 0x0F
+        } else {
+            byte >> 4
+        };
+        let volume = ram[base + 7] & 0x0F;
+        (sample as i16 - 8) * volume as i16
+    }
+}
+
+impl Mapper for Namco163 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        let len = self.rom.prg_rom.len();
+        let bank = match addr {
+            0x4800..=0x4FFF => {
+                let data = self.internal_ram()[self.ram_address.get() as usize];
+                self.advance_ram_address();
+                return data;
+            }
+            0x5000..=0x57FF => return self.irq_counter as u8,
+            0x5800..=0x5FFF => {
+                return (self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7
+            }
+            0x6000..=0x7FFF => return self.prg_ram.read(addr),
+            0x8000..=0x9FFF => self.prg_banks[0] as usize,
+            0xA000..=0xBFFF => self.prg_banks[1] as usize,
+            0xC000..=0xDFFF => self.prg_banks[2] as usize,
+            0xE000..=0xFFFF => last_bank(len, PRG_BANK_SIZE),
+            _ => return 0,
+        };
+        self.rom.prg_rom[bank_addr(len, PRG_BANK_SIZE, bank, addr)]
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4800..=0x4FFF => {
+                let ram_address = self.ram_address.get() as usize;
+                self.internal_ram_mut()[ram_address] = data;
+                self.advance_ram_address();
+            }
+            0x5000..=0x57FF => self.irq_counter = (self.irq_counter & 0x7F00) | data as u16,
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | ((data as u16 & 0x7F) << 8);
+                self.irq_enabled = data & 0b1000_0000 != 0;
+            }
+            0x6000..=0x7FFF if self.prg_ram_writable(addr) => self.prg_ram.write(addr, data),
+            0x8000..=0xBFFF => self.chr_banks[(addr as usize - 0x8000) / 0x800] = data,
+            0xC000..=0xDFFF => self.nametable_banks[(addr as usize - 0xC000) / 0x800] = data,
+            0xE000..=0xE7FF => {
+                self.prg_banks[0] = data & 0b11_1111;
+                self.sound_disabled = data & 0b0100_0000 != 0;
+            }
+            0xE800..=0xEFFF => {
+                // bits 6-7 only matter for CIRAM in the pattern tables, see read_chr
+                self.prg_banks[1] = data & 0b11_1111;
+            }
+            0xF000..=0xF7FF => self.prg_banks[2] = data & 0b11_1111,
+            0xF800..=0xFFFF => {
+                self.write_protect = data;
+                self.ram_address.set(data & 0x7F);
+                self.ram_auto_increment = data & 0b1000_0000 != 0;
+            }
+            _ => {}
+        }
+    }
+
+    /// Pattern table banks of $E0 and up select CIRAM unless $E800 disables it.
+    /// CIRAM belongs to the PPU and isn't reachable from here, so those read as
+    /// the matching CHR bank instead.
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE];
+        self.read_chr_bank(bank, addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE];
+        self.write_chr_bank(bank, addr, data);
+    }
+
+    /// Only meaningful when the nametable banks are all CIRAM, `read_nametable`
+    /// does the real work.
+    fn mirroring(&self) -> Mirroring {
+        match self.nametable_banks.map(|bank| bank & 1) {
+            [0, 1, 0, 1] => Mirroring::VERTICAL,
+            [0, 0, 1, 1] => Mirroring::HORIZONTAL,
+            [0, 0, 0, 0] => Mirroring::SINGLE_SCREEN_LOWER,
+            [1, 1, 1, 1] => Mirroring::SINGLE_SCREEN_UPPER,
+            _ => self.rom.screen_mirroring,
+        }
+    }
+
+    fn read_nametable(&mut self, addr: u16) -> Nametable {
+        let bank = self.nametable_banks[((addr >> 10) & 0b11) as usize];
+        if bank >= CIRAM_BANKS {
+            Nametable::Vram(bank & 1)
+        } else {
+            Nametable::Data(self.read_chr_bank(bank, addr))
+        }
+    }
+
+    fn write_nametable(&mut self, addr: u16, data: u8) -> Option<u8> {
+        let bank = self.nametable_banks[((addr >> 10) & 0b11) as usize];
+        if bank >= CIRAM_BANKS {
+            Some(bank & 1)
+        } else {
+            self.write_chr_bank(bank, addr, data);
+            None
+        }
+    }
+
+    fn clock_cpu(&mut self) {
+        if self.irq_enabled && self.irq_counter < IRQ_COUNTER_MAX {
+            self.irq_counter += 1;
+        }
+
+        if self.sound_disabled {
+            return;
+        }
+        self.channel_divider += 1;
+        if self.channel_divider < CYCLES_PER_CHANNEL {
+            return;
+        }
+        self.channel_divider = 0;
+
+        // channels take turns driving the output, from 7 down
+        let first = 8 - self.enabled_channels();
+        self.current_channel = if self.current_channel <= first {
+            7
+        } else {
+            self.current_channel - 1
+        };
+        let sample = self.update_channel(self.current_channel);
+        self.output = sample as f32 * OUTPUT_STEP;
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_enabled && self.irq_counter == IRQ_COUNTER_MAX
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.sound_disabled {
+            0.0
+        } else {
+            self.output
+        }
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.battery_ram()
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        self.prg_ram.load(data);
+    }
+}
@@ -0,0 +1,62 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::chr::Chr;
+use crate::mapper::prg_ram::PrgRam;
+use crate::mapper::Mapper;
+
+/// Mapper 0: 16K or 32K of PRG, 8K of CHR-ROM or CHR-RAM, no banking. Family
+/// BASIC adds PRG-RAM at $6000.
+pub struct NRom {
+    rom: Rom,
+    chr: Chr,
+    prg_ram: PrgRam,
+}
+
+impl NRom {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::new(&mut rom);
+        let prg_ram = PrgRam::new(&rom);
+        NRom { rom, chr, prg_ram }
+    }
+}
+
+impl Mapper for NRom {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram.read(addr),
+            0x8000..=0xFFFF => {
+                let mut addr = addr - 0x8000;
+                if self.rom.prg_rom.len() == 0x4000 && addr >= 0x4000 {
+                    addr %= 0x4000;
+                }
+                self.rom.prg_rom[addr as usize]
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            self.prg_ram.write(addr, data);
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.rom.screen_mirroring
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.battery_ram()
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        self.prg_ram.load(data);
+    }
+}
@@ -0,0 +1,212 @@
+use crate::cartridge::{ConsoleType, Mirroring, Rom, Timing};
+use crate::mapper::{Fds, Fme7, Mapper, MapperConstructor, Mmc5, Namco163, Vrc6, Vrc7};
+use crate::nsf::{self, Nsf};
+
+const BANK_SIZE: usize = 0x1000;
+const RAM_SIZE: usize = 0x2000;
+/// FDS tunes have RAM over all of $6000-$FFFF.
+const FDS_RAM_SIZE: usize = 0xA000;
+
+/// The expansion chips an NSF can ask for, and the mappers that carry them. Only
+/// their sound registers are passed on. These are the built-in mappers, not
+/// whatever is registered for the numbers.
+const CHIPS: [(u8, u16, MapperConstructor); 6] = [
+    (nsf::VRC6, 24, |rom| Box::new(Vrc6::new(rom))),
+    (nsf::VRC7, 85, |rom| Box::new(Vrc7::new(rom))),
+    (nsf::FDS, 20, |rom| Box::new(Fds::new(rom))),
+    (nsf::MMC5, 5, |rom| Box::new(Mmc5::new(rom))),
+    (nsf::NAMCO163, 19, |rom| Box::new(Namco163::new(rom))),
+    (nsf::SUNSOFT_5B, 69, |rom| Box::new(Fme7::new(rom))),
+];
+
+fn chip_reads(chip: u8, addr: u16) -> bool {
+    match chip {
+        nsf::FDS => matches!(addr, 0x4040..=0x4092),
+        nsf::MMC5 => matches!(addr, 0x5205..=0x5206 | 0x5C00..=0x5FF5),
+        nsf::NAMCO163 => matches!(addr, 0x4800..=0x4FFF),
+        _ => false,
+    }
+}
+
+fn chip_writes(chip: u8, addr: u16) -> bool {
+    match chip {
+        nsf::VRC6 => matches!(addr, 0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002),
+        nsf::VRC7 => matches!(addr, 0x9010 | 0x9030),
+        nsf::FDS => matches!(addr, 0x4040..=0x408A),
+        nsf::MMC5 => matches!(addr, 0x5000..=0x5015 | 0x5205..=0x5206 | 0x5C00..=0x5FF5),
+        nsf::NAMCO163 => matches!(addr, 0x4800..=0x4FFF | 0xF800..=0xFFFF),
+        nsf::SUNSOFT_5B => matches!(addr, 0xC000..=0xFFFF),
+        _ => false,
+    }
+}
+
+/// A bare board for `mapper`, only used for its sound.
+fn chip_rom(mapper: u16) -> Rom {
+    Rom {
+        prg_rom: vec![0; 0x8000],
+        chr_rom: Vec::new(),
+        trainer: None,
+        mapper,
+        submapper: 0,
+        screen_mirroring: Mirroring::HORIZONTAL,
+        vertical_mirroring_bit: false,
+        prg_ram_size: 0,
+        prg_nvram_size: 0,
+        chr_ram_size: 0x2000,
+        chr_nvram_size: 0,
+        battery: false,
+        bus_conflicts: false,
+        nes2: false,
+        archaic: false,
+        timing: Timing::NTSC,
+        console_type: ConsoleType::NES,
+        misc_roms: 0,
+        expansion_device: 0,
+        header_fixes: Vec::new(),
+        disk_sides: Vec::new(),
+    }
+}
+
+/// The hardware an NSF player provides.
+///
+/// The program is split into 4K banks switched into $8000-$FFFF through
+/// $5FF8-$5FFF, with 8K of RAM at $6000. Tunes that aren't bankswitched are laid
+/// out at their load address instead. FDS tunes get RAM over all of $6000-$FFFF,
+/// and a bank write copies that bank into it, with $5FF6/$5FF7 covering
+/// $6000-$7FFF. The expansion sound chips the header asks for are the mappers'
+/// own, and mixed into `audio_output`.
+pub struct NsfMapper {
+    data: Vec<u8>,
+    banks: [u8; 8],
+    ram: Vec<u8>,
+    fds: bool,
+    chips: Vec<(u8, Box<dyn Mapper>)>,
+}
+
+impl NsfMapper {
+    pub fn new(nsf: &Nsf) -> Self {
+        let bankswitched = nsf.is_bankswitched();
+        let fds = nsf.expansion & nsf::FDS != 0;
+        let padding = if bankswitched {
+            nsf.load_addr as usize % BANK_SIZE
+        } else {
+            (nsf.load_addr as usize).saturating_sub(0x8000)
+        };
+        let mut data = vec![0; padding];
+        data.extend_from_slice(&nsf.data);
+
+        let mut chips: Vec<(u8, Box<dyn Mapper>)> = CHIPS
+            .iter()
+            .filter(|&&(chip, _, _)| nsf.expansion & chip != 0)
+            .map(|&(chip, number, constructor)| (chip, constructor(chip_rom(number))))
+            .collect();
+        for (chip, mapper) in chips.iter_mut() {
+            // ExRAM as plain RAM, the way NSF players provide it
+            if *chip == nsf::MMC5 {
+                mapper.write_prg(0x5104, 2);
+            }
+        }
+
+        let mut nsf_mapper = NsfMapper {
+            data,
+            banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            ram: vec![0; if fds { FDS_RAM_SIZE } else { RAM_SIZE }],
+            fds,
+            chips,
+        };
+        match (bankswitched, fds) {
+            (true, true) => {
+                nsf_mapper.write_prg(0x5FF6, nsf.bankswitch[6]);
+                nsf_mapper.write_prg(0x5FF7, nsf.bankswitch[7]);
+                for (i, &bank) in nsf.bankswitch.iter().enumerate() {
+                    nsf_mapper.write_prg(0x5FF8 + i as u16, bank);
+                }
+            }
+            (true, false) => nsf_mapper.banks = nsf.bankswitch,
+            (false, true) => {
+                let start = (nsf.load_addr as usize).saturating_sub(0x6000);
+                for (byte, &data) in nsf_mapper.ram[start..].iter_mut().zip(&nsf.data) {
+                    *byte = data;
+                }
+            }
+            (false, false) => {}
+        }
+        nsf_mapper
+    }
+
+    /// Copies `bank` of the program into FDS RAM at `offset`.
+    fn copy_bank(&mut self, offset: usize, bank: u8) {
+        let start = bank as usize * BANK_SIZE;
+        for i in 0..BANK_SIZE {
+            self.ram[offset + i] = self.data.get(start + i).copied().unwrap_or(0);
+        }
+    }
+
+    fn write_chips(&mut self, addr: u16, data: u8) {
+        for (chip, mapper) in self.chips.iter_mut() {
+            if chip_writes(*chip, addr) {
+                mapper.write_prg(addr, data);
+            }
+        }
+    }
+}
+
+impl Mapper for NsfMapper {
+    fn read_prg(&self, addr: u16) -> u8 {
+        if let Some((_, mapper)) = self.chips.iter().find(|(chip, _)| chip_reads(*chip, addr)) {
+            return mapper.read_prg(addr);
+        }
+        match addr {
+            0x6000..=0xFFFF if self.fds => self.ram[addr as usize - 0x6000],
+            0x6000..=0x7FFF => self.ram[addr as usize - 0x6000],
+            0x8000..=0xFFFF => {
+                let bank = self.banks[(addr as usize - 0x8000) / BANK_SIZE] as usize;
+                let offset = bank * BANK_SIZE + addr as usize % BANK_SIZE;
+                self.data.get(offset).copied().unwrap_or(0)
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5FF6..=0x5FF7 if self.fds => {
+                self.copy_bank((addr as usize - 0x5FF6) * BANK_SIZE, data);
+            }
+            0x5FF8..=0x5FFF => {
+                let slot = addr as usize - 0x5FF8;
+                self.banks[slot] = data;
+                if self.fds {
+                    self.copy_bank(0x2000 + slot * BANK_SIZE, data);
+                }
+            }
+            0x6000..=0xFFFF if self.fds => self.ram[addr as usize - 0x6000] = data,
+            0x6000..=0x7FFF => self.ram[addr as usize - 0x6000] = data,
+            _ => {}
+        }
+        self.write_chips(addr, data);
+    }
+
+    fn read_chr(&mut self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn write_chr(&mut self, _addr: u16, _data: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::HORIZONTAL
+    }
+
+    fn clock_cpu(&mut self) {
+        for (_, mapper) in self.chips.iter_mut() {
+            mapper.clock_cpu();
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.chips
+            .iter()
+            .map(|(_, mapper)| mapper.audio_output())
+            .sum()
+    }
+}
@@ -0,0 +1,125 @@
+use crate::cartridge::Rom;
+
+const WINDOW_SIZE: usize = 0x2000;
+const TRAINER_ADDR: u16 = 0x7000;
+
+/// Cartridge work RAM, normally mapped at $6000-$7FFF, sized from the header.
+///
+/// RAM smaller than the 8K window is mirrored across it, and a board without
+/// any reads as 0. When the header declares any of it battery-backed its contents
+/// are what the cartridge hands out through `Mapper::battery_ram`. A trainer in
+/// the ROM is copied to $7000 at power-on, so boards with one get at least 8K.
+pub(crate) struct PrgRam {
+    data: Vec<u8>,
+    // bytes at the end of `data` that belong to the mapper chip, not the window
+    chip_ram_size: usize,
+    battery: bool,
+}
+
+impl PrgRam {
+    pub(crate) fn new(rom: &Rom) -> Self {
+        PrgRam::with_min_size(rom, 0)
+    }
+
+    /// For chips whose RAM size the iNES header can't express, like the MMC5's.
+    pub(crate) fn with_min_size(rom: &Rom, min_size: usize) -> Self {
+        let min_size = if rom.trainer.is_some() {
+            min_size.max(WINDOW_SIZE)
+        } else {
+            min_size
+        };
+        let mut prg_ram = PrgRam {
+            data: vec![0; (rom.prg_ram_size + rom.prg_nvram_size).max(min_size)],
+            chip_ram_size: 0,
+            battery: rom.prg_nvram_size > 0,
+        };
+        prg_ram.load_trainer(rom);
+        prg_ram
+    }
+
+    /// For boards without work RAM, which emulators give 8K anyway when there's
+    /// a trainer to run. Without one there is no RAM and $6000-$7FFF reads 0.
+    pub(crate) fn for_trainer(rom: &Rom) -> Self {
+        let size = if rom.trainer.is_some() {
+            WINDOW_SIZE
+        } else {
+            0
+        };
+        let mut prg_ram = PrgRam {
+            data: vec![0; size],
+            chip_ram_size: 0,
+            battery: false,
+        };
+        prg_ram.load_trainer(rom);
+        prg_ram
+    }
+
+    fn load_trainer(&mut self, rom: &Rom) {
+        if let Some(trainer) = &rom.trainer {
+            for (addr, &byte) in (TRAINER_ADDR..).zip(trainer) {
+                self.write(addr, byte);
+            }
+        }
+    }
+
+    /// For mapper chips with RAM of their own that a battery keeps along with
+    /// the work RAM, like the Namco 163's. It follows the work RAM in
+    /// `battery_ram`, and the cartridge battery backs both.
+    pub(crate) fn with_chip_ram(rom: &Rom, chip_ram_size: usize) -> Self {
+        let mut prg_ram = PrgRam::new(rom);
+        prg_ram.data.resize(prg_ram.data.len() + chip_ram_size, 0);
+        prg_ram.chip_ram_size = chip_ram_size;
+        prg_ram.battery |= rom.battery;
+        prg_ram
+    }
+
+    fn offset(&self, bank: usize, addr: u16) -> Option<usize> {
+        let len = self.data.len() - self.chip_ram_size;
+        if len == 0 {
+            None
+        } else {
+            Some((bank * WINDOW_SIZE + (addr as usize % WINDOW_SIZE)) % len)
+        }
+    }
+
+    pub(crate) fn chip_ram(&self) -> &[u8] {
+        &self.data[self.data.len() - self.chip_ram_size..]
+    }
+
+    pub(crate) fn chip_ram_mut(&mut self) -> &mut [u8] {
+        let start = self.data.len() - self.chip_ram_size;
+        &mut self.data[start..]
+    }
+
+    /// Reads `addr` within the 8K window at $6000.
+    pub(crate) fn read(&self, addr: u16) -> u8 {
+        self.read_bank(0, addr)
+    }
+
+    pub(crate) fn write(&mut self, addr: u16, data: u8) {
+        self.write_bank(0, addr, data);
+    }
+
+    /// Reads `addr` within 8K bank `bank`, for mappers that bank their RAM.
+    pub(crate) fn read_bank(&self, bank: usize, addr: u16) -> u8 {
+        match self.offset(bank, addr) {
+            Some(offset) => self.data[offset],
+            None => 0,
+        }
+    }
+
+    pub(crate) fn write_bank(&mut self, bank: usize, addr: u16, data: u8) {
+        if let Some(offset) = self.offset(bank, addr) {
+            self.data[offset] = data;
+        }
+    }
+
+    pub(crate) fn battery_ram(&self) -> Option<&[u8]> {
+        (self.battery && !self.data.is_empty()).then_some(&self.data[..])
+    }
+
+    pub(crate) fn load(&mut self, data: &[u8]) {
+        let len = data.len().min(self.data.len());
+        self.data[..len].copy_from_slice(&data[..len]);
+    }
+}
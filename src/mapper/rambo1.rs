@@ -0,0 +1,199 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::chr::Chr;
+use crate::mapper::prg_ram::PrgRam;
+use crate::mapper::{bank_addr, last_bank, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// A12 has to stay low for this many M2 cycles before a rising edge clocks the
+/// IRQ counter, as on the MMC3.
+const A12_LOW_CYCLES: u8 = 3;
+
+/// In cycle mode the IRQ counter is clocked every 4 CPU cycles.
+const CYCLE_MODE_PRESCALER: u8 = 4;
+
+/// Mapper 64: Tengen RAMBO-1, as used by Klax and Skull & Crossbones.
+///
+/// An MMC3 lookalike with sixteen bank registers: two extra 1K CHR banks for a
+/// full 1K CHR mode, a third switchable PRG bank, and an IRQ counter that can be
+/// clocked either by A12 like the MMC3 or by the CPU through a divide-by-4 prescaler.
+pub struct Rambo1 {
+    rom: Rom,
+    chr: Chr,
+    prg_ram: PrgRam,
+    bank_select: u8,
+    registers: [u8; 16],
+    mirroring: Mirroring,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_cycle_mode: bool,
+    irq_prescaler: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    a12: bool,
+    a12_low_cycles: u8,
+}
+
+impl Rambo1 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::new(&mut rom);
+        let prg_ram = PrgRam::for_trainer(&rom);
+        let mirroring = rom.screen_mirroring;
+        Rambo1 {
+            rom,
+            chr,
+            prg_ram,
+            bank_select: 0,
+            registers: [0; 16],
+            mirroring,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_cycle_mode: false,
+            irq_prescaler: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            a12: false,
+            a12_low_cycles: 0,
+        }
+    }
+
+    fn prg_bank(&self, addr: u16) -> usize {
+        let swap_mode = self.bank_select & 0b0100_0000 != 0;
+        let register = match (addr, swap_mode) {
+            (0x8000..=0x9FFF, false) | (0xA000..=0xBFFF, true) => 6,
+            (0xA000..=0xBFFF, false) | (0xC000..=0xDFFF, true) => 7,
+            (0xC000..=0xDFFF, false) | (0x8000..=0x9FFF, true) => 0xF,
+            _ => return last_bank(self.rom.prg_rom.len(), PRG_BANK_SIZE),
+        };
+        self.registers[register] as usize
+    }
+
+    fn chr_bank(&self, addr: u16) -> usize {
+        let addr = if self.bank_select & 0b1000_0000 != 0 {
+            addr ^ 0x1000
+        } else {
+            addr
+        };
+        let full_1k_mode = self.bank_select & 0b0010_0000 != 0;
+        let odd = (addr as usize >> 10) & 1;
+        match (addr, full_1k_mode) {
+            (0x0000..=0x03FF, true) => self.registers[0] as usize,
+            (0x0400..=0x07FF, true) => self.registers[8] as usize,
+            (0x0800..=0x0BFF, true) => self.registers[1] as usize,
+            (0x0C00..=0x0FFF, true) => self.registers[9] as usize,
+            (0x0000..=0x07FF, false) => (self.registers[0] & 0xFE) as usize + odd,
+            (0x0800..=0x0FFF, false) => (self.registers[1] & 0xFE) as usize + odd,
+            _ => self.registers[2 + (addr as usize - 0x1000) / CHR_BANK_SIZE] as usize,
+        }
+    }
+
+    /// Unlike the MMC3, a reload sets the counter one or two above the latch,
+    /// which delays the first IRQ after a $C001 write. Hard Drivin' depends on it.
+    fn clock_irq_counter(&mut self) {
+        if self.irq_reload {
+            self.irq_counter = if self.irq_latch <= 1 {
+                self.irq_latch + 1
+            } else {
+                self.irq_latch.saturating_add(2)
+            };
+            self.irq_reload = false;
+        } else if self.irq_counter == 0 {
+            self.irq_counter = self.irq_latch.wrapping_add(1);
+        }
+
+        self.irq_counter = self.irq_counter.wrapping_sub(1);
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Rambo1 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram.read(addr),
+            0x8000..=0xFFFF => {
+                let len = self.rom.prg_rom.len();
+                self.rom.prg_rom[bank_addr(len, PRG_BANK_SIZE, self.prg_bank(addr), addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        let even = addr & 1 == 0;
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram.write(addr, data),
+            0x8000..=0x9FFF if even => self.bank_select = data,
+            0x8000..=0x9FFF => self.registers[(self.bank_select & 0b1111) as usize] = data,
+            0xA000..=0xBFFF if even => {
+                self.mirroring = if data & 1 == 0 {
+                    Mirroring::VERTICAL
+                } else {
+                    Mirroring::HORIZONTAL
+                };
+            }
+            0xC000..=0xDFFF if even => self.irq_latch = data,
+            0xC000..=0xDFFF => {
+                self.irq_cycle_mode = data & 1 != 0;
+                self.irq_prescaler = 0;
+                self.irq_reload = true;
+            }
+            0xE000..=0xFFFF if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.notify_ppu_address(addr);
+        self.chr.read_bank(CHR_BANK_SIZE, self.chr_bank(addr), addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.notify_ppu_address(addr);
+        let bank = self.chr_bank(addr);
+        self.chr.write_bank(CHR_BANK_SIZE, bank, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock_cpu(&mut self) {
+        if !self.a12 {
+            self.a12_low_cycles = self.a12_low_cycles.saturating_add(1);
+        }
+
+        if self.irq_cycle_mode {
+            self.irq_prescaler += 1;
+            if self.irq_prescaler == CYCLE_MODE_PRESCALER {
+                self.irq_prescaler = 0;
+                self.clock_irq_counter();
+            }
+        }
+    }
+
+    fn notify_ppu_address(&mut self, addr: u16) {
+        let a12 = addr & 0x1000 != 0;
+        if a12 {
+            if !self.a12 && self.a12_low_cycles >= A12_LOW_CYCLES && !self.irq_cycle_mode {
+                self.clock_irq_counter();
+            }
+            self.a12_low_cycles = 0;
+        }
+        self.a12 = a12;
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+}
@@ -0,0 +1,132 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::chr::Chr;
+use crate::mapper::flash::Flash;
+use crate::mapper::prg_ram::PrgRam;
+use crate::mapper::{bank_addr, last_bank, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x2000;
+const CHR_RAM_SIZE: usize = 0x8000;
+
+/// Mapper 30: UNROM 512, the RetroUSB homebrew board.
+///
+/// Like UNROM with up to 512K of PRG and four 8K banks of CHR-RAM selected by the
+/// same register. Boards whose header has the four-screen bit set instead have
+/// one-screen mirroring switched by bit 7 of that register, unless the vertical
+/// bit is set too, which marks true four-screen boards.
+///
+/// With the battery bit set the PRG is an SST39SF040 the game can rewrite to save:
+/// the bank register then only answers at $C000-$FFFF, and writes to $8000-$BFFF
+/// go to the flash chip at the address selected by the current bank.
+pub struct Unrom512 {
+    rom: Rom,
+    chr: Chr,
+    prg_ram: PrgRam,
+    prg_bank: u8,
+    chr_bank: u8,
+    one_screen_upper: bool,
+    flash: Option<Flash>,
+}
+
+impl Unrom512 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::with_min_ram_size(&mut rom, CHR_RAM_SIZE);
+        let flash = rom.battery.then(Flash::new);
+        let prg_ram = PrgRam::for_trainer(&rom);
+        Unrom512 {
+            rom,
+            chr,
+            prg_ram,
+            prg_bank: 0,
+            chr_bank: 0,
+            one_screen_upper: false,
+            flash,
+        }
+    }
+
+    fn flash_addr(&self, addr: u16) -> usize {
+        bank_addr(
+            self.rom.prg_rom.len(),
+            PRG_BANK_SIZE,
+            self.prg_bank as usize,
+            addr,
+        )
+    }
+}
+
+impl Mapper for Unrom512 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        let len = self.rom.prg_rom.len();
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram.read(addr),
+            0x8000..=0xBFFF => match &self.flash {
+                Some(flash) => flash.read(&self.rom.prg_rom, self.flash_addr(addr)),
+                None => self.rom.prg_rom[self.flash_addr(addr)],
+            },
+            0xC000..=0xFFFF => {
+                self.rom.prg_rom[bank_addr(len, PRG_BANK_SIZE, last_bank(len, PRG_BANK_SIZE), addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            if addr >= 0x6000 {
+                self.prg_ram.write(addr, data);
+            }
+            return;
+        }
+        if addr < 0xC000 {
+            let flash_addr = self.flash_addr(addr);
+            if let Some(flash) = &mut self.flash {
+                flash.write(&mut self.rom.prg_rom, flash_addr, data);
+                return;
+            }
+        }
+
+        let data = if self.rom.bus_conflicts && self.flash.is_none() {
+            data & self.read_prg(addr)
+        } else {
+            data
+        };
+        self.prg_bank = data & 0b1_1111;
+        self.chr_bank = (data >> 5) & 0b11;
+        self.one_screen_upper = data & 0b1000_0000 != 0;
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        let bank = self.chr_bank as usize;
+        self.chr.read_bank(CHR_BANK_SIZE, bank, addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let bank = self.chr_bank as usize;
+        self.chr.write_bank(CHR_BANK_SIZE, bank, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.rom.screen_mirroring {
+            Mirroring::FOUR_SCREEN if self.rom.vertical_mirroring_bit => Mirroring::FOUR_SCREEN,
+            Mirroring::FOUR_SCREEN if self.one_screen_upper => Mirroring::SINGLE_SCREEN_UPPER,
+            Mirroring::FOUR_SCREEN => Mirroring::SINGLE_SCREEN_LOWER,
+            mirroring => mirroring,
+        }
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        match &self.flash {
+            Some(flash) if flash.modified() => Some(&self.rom.prg_rom),
+            _ => None,
+        }
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        if let Some(flash) = &mut self.flash {
+            if data.len() == self.rom.prg_rom.len() {
+                self.rom.prg_rom.copy_from_slice(data);
+                flash.mark_modified();
+            }
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::chr::Chr;
+use crate::mapper::prg_ram::PrgRam;
+use crate::mapper::{bank_addr, last_bank, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+/// Mapper 2: switchable 16K PRG bank at $8000, last bank fixed at $C000.
+pub struct UxRom {
+    rom: Rom,
+    chr: Chr,
+    prg_ram: PrgRam,
+    prg_bank: u8,
+}
+
+impl UxRom {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::new(&mut rom);
+        let prg_ram = PrgRam::for_trainer(&rom);
+        UxRom {
+            rom,
+            chr,
+            prg_ram,
+            prg_bank: 0,
+        }
+    }
+}
+
+impl Mapper for UxRom {
+    fn read_prg(&self, addr: u16) -> u8 {
+        let len = self.rom.prg_rom.len();
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram.read(addr),
+            0x8000..=0xBFFF => {
+                self.rom.prg_rom[bank_addr(len, PRG_BANK_SIZE, self.prg_bank as usize, addr)]
+            }
+            0xC000..=0xFFFF => {
+                self.rom.prg_rom[bank_addr(len, PRG_BANK_SIZE, last_bank(len, PRG_BANK_SIZE), addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, mut data: u8) {
+        if addr < 0x8000 {
+            if addr >= 0x6000 {
+                self.prg_ram.write(addr, data);
+            }
+            return;
+        }
+        if self.rom.bus_conflicts {
+            data &= self.read_prg(addr);
+        }
+        self.prg_bank = data;
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.rom.screen_mirroring
+    }
+}
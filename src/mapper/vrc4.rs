@@ -0,0 +1,181 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::chr::Chr;
+use crate::mapper::prg_ram::PrgRam;
+use crate::mapper::vrc_irq::VrcIrq;
+use crate::mapper::{bank_addr, last_bank, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Which CPU address lines a board feeds into the chip's two register select pins.
+/// Submapper 0 boards are ambiguous, so both candidate lines are ORed together.
+fn register_lines(mapper: u16, submapper: u8) -> (u16, u16) {
+    match (mapper, submapper) {
+        (21, 1) => (0x02, 0x04),           // VRC4a
+        (21, 2) => (0x40, 0x80),           // VRC4c
+        (21, _) => (0x42, 0x84),           // VRC4a/VRC4c
+        (22, _) => (0x02, 0x01),           // VRC2a
+        (23, 1) | (23, 3) => (0x01, 0x02), // VRC4f, VRC2b
+        (23, 2) => (0x04, 0x08),           // VRC4e
+        (23, _) => (0x05, 0x0A),           // VRC2b/VRC4e
+        (25, 1) | (25, 3) => (0x02, 0x01), // VRC4b, VRC2c
+        (25, 2) => (0x08, 0x04),           // VRC4d
+        _ => (0x0A, 0x05),                 // VRC2c/VRC4b/VRC4d
+    }
+}
+
+/// Mappers 21, 22, 23 and 25: Konami VRC2 and VRC4.
+///
+/// Two switchable 8K PRG banks, eight 1K CHR banks and, on VRC4, single-screen
+/// mirroring, a PRG swap mode and the VRC IRQ counter. VRC2 is the subset without
+/// them; VRC2a (mapper 22) also ignores the low bit of its CHR bank numbers.
+pub struct Vrc4 {
+    rom: Rom,
+    chr: Chr,
+    vrc2: bool,
+    register_lines: (u16, u16),
+    prg_ram: PrgRam,
+    prg_banks: [u8; 2],
+    prg_swap_mode: bool,
+    chr_banks: [u16; 8],
+    mirroring: Mirroring,
+    irq: VrcIrq,
+}
+
+impl Vrc4 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::new(&mut rom);
+        let prg_ram = PrgRam::new(&rom);
+        let vrc2 = rom.mapper == 22 || (rom.submapper == 3 && rom.mapper != 21);
+        let register_lines = register_lines(rom.mapper, rom.submapper);
+        let mirroring = rom.screen_mirroring;
+        Vrc4 {
+            rom,
+            chr,
+            vrc2,
+            register_lines,
+            prg_ram,
+            prg_banks: [0; 2],
+            prg_swap_mode: false,
+            chr_banks: [0; 8],
+            mirroring,
+            irq: VrcIrq::new(),
+        }
+    }
+
+    /// Folds the board's address wiring down to $x000-$x003.
+    fn register(&self, addr: u16) -> u16 {
+        let (a0, a1) = self.register_lines;
+        let a0 = (addr & a0 != 0) as u16;
+        let a1 = (addr & a1 != 0) as u16;
+        (addr & 0xF000) | (a1 << 1) | a0
+    }
+
+    fn prg_bank(&self, addr: u16) -> usize {
+        let last = last_bank(self.rom.prg_rom.len(), PRG_BANK_SIZE);
+        let second_last = last.saturating_sub(1);
+        match (addr, self.prg_swap_mode) {
+            (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => self.prg_banks[0] as usize,
+            (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => second_last,
+            (0xA000..=0xBFFF, _) => self.prg_banks[1] as usize,
+            _ => last,
+        }
+    }
+
+    fn chr_bank(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize;
+        if self.rom.mapper == 22 {
+            bank >> 1
+        } else {
+            bank
+        }
+    }
+
+    fn write_chr_bank(&mut self, register: u16, data: u8) {
+        // $B000/$B001 are the low/high halves of bank 0, $B002/$B003 bank 1, ...
+        let slot = ((register >> 12) as usize - 0xB) * 2 + ((register as usize >> 1) & 1);
+        let data = data as u16;
+        self.chr_banks[slot] = if register & 1 == 0 {
+            (self.chr_banks[slot] & 0x1F0) | (data & 0x0F)
+        } else {
+            (self.chr_banks[slot] & 0x00F) | ((data & 0x1F) << 4)
+        };
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram.read(addr),
+            0x8000..=0xFFFF => {
+                let len = self.rom.prg_rom.len();
+                self.rom.prg_rom[bank_addr(len, PRG_BANK_SIZE, self.prg_bank(addr), addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            self.prg_ram.write(addr, data);
+            return;
+        }
+
+        let register = self.register(addr);
+        match register {
+            0x8000..=0x8003 => self.prg_banks[0] = data & 0b1_1111,
+            0x9000..=0x9003 if self.vrc2 => {
+                self.mirroring = if data & 1 == 0 {
+                    Mirroring::VERTICAL
+                } else {
+                    Mirroring::HORIZONTAL
+                }
+            }
+            0x9000 => {
+                self.mirroring = match data & 0b11 {
+                    0 => Mirroring::VERTICAL,
+                    1 => Mirroring::HORIZONTAL,
+                    2 => Mirroring::SINGLE_SCREEN_LOWER,
+                    _ => Mirroring::SINGLE_SCREEN_UPPER,
+                }
+            }
+            0x9002 if !self.vrc2 => self.prg_swap_mode = data & 0b10 != 0,
+            0xA000..=0xA003 => self.prg_banks[1] = data & 0b1_1111,
+            0xB000..=0xEFFF => self.write_chr_bank(register, data),
+            0xF000 if !self.vrc2 => self.irq.write_latch_low(data),
+            0xF001 if !self.vrc2 => self.irq.write_latch_high(data),
+            0xF002 if !self.vrc2 => self.irq.write_control(data),
+            0xF003 if !self.vrc2 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read_bank(CHR_BANK_SIZE, self.chr_bank(addr), addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let bank = self.chr_bank(addr);
+        self.chr.write_bank(CHR_BANK_SIZE, bank, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock_cpu(&mut self) {
+        self.irq.clock_cpu();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.battery_ram()
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        self.prg_ram.load(data);
+    }
+}
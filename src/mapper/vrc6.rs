@@ -0,0 +1,308 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::chr::Chr;
+use crate::mapper::prg_ram::PrgRam;
+use crate::mapper::vrc_irq::VrcIrq;
+use crate::mapper::{bank_addr, last_bank, Mapper};
+
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Scale of one output step, matching the linear approximation of an APU pulse
+/// channel so a VRC6 pulse at full volume is as loud as an APU one.
+const OUTPUT_STEP: f32 = 0.00752;
+
+/// Divider for the three channels, shared so $9003 can slow them all down.
+#[derive(Clone, Copy)]
+struct FrequencyControl {
+    halt: bool,
+    shift: u8,
+}
+
+impl FrequencyControl {
+    fn period(&self, period: u16) -> u16 {
+        period >> self.shift
+    }
+}
+
+struct Pulse {
+    volume: u8,
+    duty: u8,
+    ignore_duty: bool,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+}
+
+impl Pulse {
+    fn new() -> Self {
+        Pulse {
+            volume: 0,
+            duty: 0,
+            ignore_duty: false,
+            period: 0,
+            enabled: false,
+            timer: 0,
+            step: 15,
+        }
+    }
+
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.ignore_duty = data & 0b1000_0000 != 0;
+                self.duty = (data >> 4) & 0b111;
+                self.volume = data & 0b1111;
+            }
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data as u16 & 0x0F) << 8);
+                self.enabled = data & 0b1000_0000 != 0;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, frequency: FrequencyControl) {
+        if !self.enabled || frequency.halt {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = frequency.period(self.period);
+            self.step = if self.step == 0 { 15 } else { self.step - 1 };
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.ignore_duty || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+struct Sawtooth {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn new() -> Self {
+        Sawtooth {
+            rate: 0,
+            period: 0,
+            enabled: false,
+            timer: 0,
+            step: 0,
+            accumulator: 0,
+        }
+    }
+
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => self.rate = data & 0b11_1111,
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data as u16 & 0x0F) << 8);
+                self.enabled = data & 0b1000_0000 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, frequency: FrequencyControl) {
+        if !self.enabled || frequency.halt {
+            return;
+        }
+        if self.timer != 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = frequency.period(self.period);
+
+        // the accumulator takes the rate on every other step, six times, and resets on
+        // the 14th step
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step.is_multiple_of(2) {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled {
+            self.accumulator >> 3
+        } else {
+            0
+        }
+    }
+}
+
+/// Mappers 24 and 26: Konami VRC6a and VRC6b, as used by Akumajou Densetsu.
+///
+/// A switchable 16K PRG bank at $8000, a switchable 8K bank at $C000, eight 1K CHR
+/// banks, the VRC IRQ counter, and two pulse channels plus a sawtooth channel of
+/// expansion audio. VRC6b swaps the A0 and A1 register lines.
+pub struct Vrc6 {
+    rom: Rom,
+    chr: Chr,
+    swap_register_lines: bool,
+    prg_ram: PrgRam,
+    prg_ram_enabled: bool,
+    prg_banks: [u8; 2],
+    chr_banks: [u8; 8],
+    mirroring: Mirroring,
+    irq: VrcIrq,
+
+    frequency: FrequencyControl,
+    pulses: [Pulse; 2],
+    sawtooth: Sawtooth,
+}
+
+impl Vrc6 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::new(&mut rom);
+        let prg_ram = PrgRam::new(&rom);
+        let swap_register_lines = rom.mapper == 26;
+        let mirroring = rom.screen_mirroring;
+        Vrc6 {
+            rom,
+            chr,
+            swap_register_lines,
+            prg_ram,
+            prg_ram_enabled: false,
+            prg_banks: [0; 2],
+            chr_banks: [0; 8],
+            mirroring,
+            irq: VrcIrq::new(),
+            frequency: FrequencyControl {
+                halt: false,
+                shift: 0,
+            },
+            pulses: [Pulse::new(), Pulse::new()],
+            sawtooth: Sawtooth::new(),
+        }
+    }
+
+    fn register(&self, addr: u16) -> u16 {
+        let lines = addr & 0b11;
+        let lines = if self.swap_register_lines {
+            ((lines & 1) << 1) | (lines >> 1)
+        } else {
+            lines
+        };
+        (addr & 0xF000) | lines
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        let len = self.rom.prg_rom.len();
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled => self.prg_ram.read(addr),
+            0x8000..=0xBFFF => {
+                self.rom.prg_rom[bank_addr(len, 0x4000, self.prg_banks[0] as usize, addr)]
+            }
+            0xC000..=0xDFFF => {
+                self.rom.prg_rom[bank_addr(len, 0x2000, self.prg_banks[1] as usize, addr)]
+            }
+            0xE000..=0xFFFF => {
+                self.rom.prg_rom[bank_addr(len, 0x2000, last_bank(len, 0x2000), addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            if self.prg_ram_enabled {
+                self.prg_ram.write(addr, data);
+            }
+            return;
+        }
+
+        let register = self.register(addr);
+        match register {
+            0x8000..=0x8003 => self.prg_banks[0] = data & 0b1111,
+            0x9000..=0x9002 => self.pulses[0].write(register & 0b11, data),
+            0x9003 => {
+                self.frequency.halt = data & 0b001 != 0;
+                self.frequency.shift = match data & 0b110 {
+                    0b000 => 0,
+                    0b010 => 4,
+                    _ => 8,
+                };
+            }
+            0xA000..=0xA002 => self.pulses[1].write(register & 0b11, data),
+            0xB000..=0xB002 => self.sawtooth.write(register & 0b11, data),
+            0xB003 => {
+                self.prg_ram_enabled = data & 0b1000_0000 != 0;
+                self.mirroring = match (data >> 2) & 0b11 {
+                    0 => Mirroring::VERTICAL,
+                    1 => Mirroring::HORIZONTAL,
+                    2 => Mirroring::SINGLE_SCREEN_LOWER,
+                    _ => Mirroring::SINGLE_SCREEN_UPPER,
+                };
+            }
+            0xC000..=0xC003 => self.prg_banks[1] = data & 0b1_1111,
+            0xD000..=0xD003 => self.chr_banks[(register & 0b11) as usize] = data,
+            0xE000..=0xE003 => self.chr_banks[4 + (register & 0b11) as usize] = data,
+            0xF000 => self.irq.write_latch(data),
+            0xF001 => self.irq.write_control(data),
+            0xF002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize;
+        self.chr.read_bank(CHR_BANK_SIZE, bank, addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize;
+        self.chr.write_bank(CHR_BANK_SIZE, bank, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock_cpu(&mut self) {
+        self.irq.clock_cpu();
+        for pulse in self.pulses.iter_mut() {
+            pulse.clock(self.frequency);
+        }
+        self.sawtooth.clock(self.frequency);
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn audio_output(&self) -> f32 {
+        let level = self.pulses[0].output() + self.pulses[1].output() + self.sawtooth.output();
+        level as f32 * OUTPUT_STEP
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.battery_ram()
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        self.prg_ram.load(data);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::chr::Chr;
+use crate::mapper::prg_ram::PrgRam;
+use crate::mapper::vrc_irq::VrcIrq;
+use crate::mapper::{bank_addr, last_bank, Mapper};
+use crate::opll::Opll;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// The OPLL runs off a 3.58 MHz crystal, twice the CPU clock, and produces a
+/// sample every 72 of its clocks.
+const CPU_CYCLES_PER_SAMPLE: u8 = 36;
+
+/// Mapper 85: Konami VRC7, as used by Lagrange Point.
+///
+/// Three switchable 8K PRG banks, eight 1K CHR banks, the VRC IRQ counter and a
+/// six channel FM synthesizer. VRC7a selects its odd registers with A4, VRC7b with A3.
+pub struct Vrc7 {
+    rom: Rom,
+    chr: Chr,
+    register_line: u16,
+    prg_ram: PrgRam,
+    prg_ram_enabled: bool,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    mirroring: Mirroring,
+    irq: VrcIrq,
+
+    opll: Opll,
+    audio_silenced: bool,
+    audio_divider: u8,
+}
+
+impl Vrc7 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = Chr::new(&mut rom);
+        let prg_ram = PrgRam::new(&rom);
+        let register_line = match rom.submapper {
+            1 => 0x08,
+            2 => 0x10,
+            _ => 0x18,
+        };
+        let mirroring = rom.screen_mirroring;
+        Vrc7 {
+            rom,
+            chr,
+            register_line,
+            prg_ram,
+            prg_ram_enabled: false,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            mirroring,
+            irq: VrcIrq::new(),
+            opll: Opll::new(),
+            audio_silenced: false,
+            audio_divider: 0,
+        }
+    }
+
+    fn register(&self, addr: u16) -> u16 {
+        let odd = (addr & self.register_line != 0) as u16;
+        (addr & 0xF000) | (odd << 4)
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        let len = self.rom.prg_rom.len();
+        let bank = match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled => return self.prg_ram.read(addr),
+            0x8000..=0x9FFF => self.prg_banks[0] as usize,
+            0xA000..=0xBFFF => self.prg_banks[1] as usize,
+            0xC000..=0xDFFF => self.prg_banks[2] as usize,
+            0xE000..=0xFFFF => last_bank(len, PRG_BANK_SIZE),
+            _ => return 0,
+        };
+        self.rom.prg_rom[bank_addr(len, PRG_BANK_SIZE, bank, addr)]
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            if self.prg_ram_enabled {
+                self.prg_ram.write(addr, data);
+            }
+            return;
+        }
+
+        // the audio ports are decoded on A4 and A5 regardless of the board's wiring
+        match addr & 0xF030 {
+            0x9010 => {
+                self.opll.write_address(data);
+                return;
+            }
+            0x9030 => {
+                self.opll.write_data(data);
+                return;
+            }
+            _ => {}
+        }
+
+        let register = self.register(addr);
+        match register {
+            0x8000 => self.prg_banks[0] = data & 0b11_1111,
+            0x8010 => self.prg_banks[1] = data & 0b11_1111,
+            0x9000 => self.prg_banks[2] = data & 0b11_1111,
+            0xA000..=0xDFFF => {
+                let slot = ((register >> 12) as usize - 0xA) * 2 + ((register as usize >> 4) & 1);
+                self.chr_banks[slot] = data;
+            }
+            0xE000 => {
+                self.mirroring = match data & 0b11 {
+                    0 => Mirroring::VERTICAL,
+                    1 => Mirroring::HORIZONTAL,
+                    2 => Mirroring::SINGLE_SCREEN_LOWER,
+                    _ => Mirroring::SINGLE_SCREEN_UPPER,
+                };
+                self.audio_silenced = data & 0b0100_0000 != 0;
+                if self.audio_silenced {
+                    self.opll.reset();
+                }
+                self.prg_ram_enabled = data & 0b1000_0000 != 0;
+            }
+            0xE010 => self.irq.write_latch(data),
+            0xF000 => self.irq.write_control(data),
+            0xF010 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize;
+        self.chr.read_bank(CHR_BANK_SIZE, bank, addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize;
+        self.chr.write_bank(CHR_BANK_SIZE, bank, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock_cpu(&mut self) {
+        self.irq.clock_cpu();
+
+        self.audio_divider += 1;
+        if self.audio_divider == CPU_CYCLES_PER_SAMPLE {
+            self.audio_divider = 0;
+            if !self.audio_silenced {
+                self.opll.clock();
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.audio_silenced {
+            0.0
+        } else {
+            self.opll.output()
+        }
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.battery_ram()
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        self.prg_ram.load(data);
+    }
+}
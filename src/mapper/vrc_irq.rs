@@ -0,0 +1,84 @@
+/// Konami's IRQ counter, shared by VRC4, VRC6 and VRC7.
+///
+/// An 8-bit up-counter that reloads from the latch and fires when it overflows.
+/// In scanline mode a prescaler divides CPU cycles by 113.667 (341 PPU dots / 3),
+/// in cycle mode the counter is clocked by every CPU cycle.
+pub(crate) struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    pub(crate) fn new() -> Self {
+        VrcIrq {
+            latch: 0,
+            counter: 0,
+            prescaler: 341,
+            enabled: false,
+            enable_after_ack: false,
+            cycle_mode: false,
+            pending: false,
+        }
+    }
+
+    pub(crate) fn write_latch(&mut self, data: u8) {
+        self.latch = data;
+    }
+
+    pub(crate) fn write_latch_low(&mut self, data: u8) {
+        self.latch = (self.latch & 0xF0) | (data & 0x0F);
+    }
+
+    pub(crate) fn write_latch_high(&mut self, data: u8) {
+        self.latch = (self.latch & 0x0F) | (data << 4);
+    }
+
+    pub(crate) fn write_control(&mut self, data: u8) {
+        self.enable_after_ack = data & 0b001 != 0;
+        self.enabled = data & 0b010 != 0;
+        self.cycle_mode = data & 0b100 != 0;
+        self.pending = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+    }
+
+    pub(crate) fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    pub(crate) fn clock_cpu(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock_counter();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += 341;
+                self.clock_counter();
+            }
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+
+    pub(crate) fn pending(&self) -> bool {
+        self.pending
+    }
+}
@@ -0,0 +1,219 @@
+//! NSF music files.
+//!
+//! A 128-byte header ("NESM\x1A") followed by the tune's program, which is either
+//! loaded at the header's load address or, if any initial bank is set, split
+//! into 4K banks. Playing a song means calling INIT once with the song number
+//! in A and the region in X, then PLAY at the header's rate, the way a game's
+//! NMI handler would.
+//!
+//! There is no 2A03 APU yet, so only the expansion chips are heard: a tune with
+//! no expansion chips renders as silence, and [`Nsf::is_audible`] says so up
+//! front.
+
+use std::collections::VecDeque;
+
+use crate::bus::Bus;
+use crate::cartridge::{RomError, Timing};
+use crate::cpu::{Mem, CPU};
+use crate::mapper::NsfMapper;
+
+pub const MAGIC: &[u8] = b"NESM\x1a";
+pub const HEADER_SIZE: usize = 0x80;
+
+// expansion sound chips, header byte $7B
+pub const VRC6: u8 = 0b0000_0001;
+pub const VRC7: u8 = 0b0000_0010;
+pub const FDS: u8 = 0b0000_0100;
+pub const MMC5: u8 = 0b0000_1000;
+pub const NAMCO163: u8 = 0b0001_0000;
+pub const SUNSOFT_5B: u8 = 0b0010_0000;
+
+pub const NTSC_CPU_HZ: f64 = 1_789_773.0;
+pub const PAL_CPU_HZ: f64 = 1_662_607.0;
+
+/// PLAY periods in microseconds for headers that leave theirs at 0.
+const NTSC_PLAY_PERIOD: u16 = 16639;
+const PAL_PLAY_PERIOD: u16 = 19997;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Nsf {
+    pub version: u8,
+    pub songs: u8,
+    /// The song to start with, counting from 1 like the header does.
+    pub starting_song: u8,
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    pub name: String,
+    pub artist: String,
+    pub copyright: String,
+    /// Microseconds between PLAY calls on each region.
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    pub bankswitch: [u8; 8],
+    pub timing: Timing,
+    /// Which expansion sound chips the tune uses, as `VRC6 | FDS` etc.
+    pub expansion: u8,
+    pub data: Vec<u8>,
+}
+
+fn text(field: &[u8]) -> String {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..len]).into_owned()
+}
+
+impl Nsf {
+    pub fn parse(raw: &[u8]) -> Result<Nsf, RomError> {
+        if raw.len() < HEADER_SIZE {
+            return Err(RomError::TruncatedHeader { len: raw.len() });
+        }
+        if &raw[0..5] != MAGIC {
+            return Err(RomError::BadMagic {
+                found: raw[0..5].to_vec(),
+            });
+        }
+
+        let word = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        let timing = match raw[0x7A] & 0b11 {
+            0 => Timing::NTSC,
+            1 => Timing::PAL,
+            _ => Timing::MULTI_REGION,
+        };
+        let mut bankswitch = [0; 8];
+        bankswitch.copy_from_slice(&raw[0x70..0x78]);
+
+        Ok(Nsf {
+            version: raw[5],
+            songs: raw[6],
+            starting_song: raw[7],
+            load_addr: word(0x08),
+            init_addr: word(0x0A),
+            play_addr: word(0x0C),
+            name: text(&raw[0x0E..0x2E]),
+            artist: text(&raw[0x2E..0x4E]),
+            copyright: text(&raw[0x4E..0x6E]),
+            ntsc_speed: word(0x6E),
+            pal_speed: word(0x78),
+            bankswitch,
+            timing,
+            expansion: raw[0x7B],
+            data: raw[HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    pub fn is_bankswitched(&self) -> bool {
+        self.bankswitch.iter().any(|&bank| bank != 0)
+    }
+
+    /// Whether any of the tune can be heard, which without a 2A03 APU means it
+    /// uses at least one expansion chip.
+    pub fn is_audible(&self) -> bool {
+        self.expansion != 0
+    }
+
+    /// Whether the tune plays at PAL rates. Tunes for both regions play as NTSC.
+    pub fn is_pal(&self) -> bool {
+        self.timing == Timing::PAL
+    }
+
+    /// CPU cycles between PLAY calls.
+    pub fn play_cycles(&self) -> f64 {
+        let (speed, default, hz) = if self.is_pal() {
+            (self.pal_speed, PAL_PLAY_PERIOD, PAL_CPU_HZ)
+        } else {
+            (self.ntsc_speed, NTSC_PLAY_PERIOD, NTSC_CPU_HZ)
+        };
+        let speed = if speed == 0 { default } else { speed };
+        speed as f64 * hz / 1_000_000.0
+    }
+}
+
+/// Plays one song of an NSF, producing samples at a fixed rate.
+pub struct Player {
+    pub cpu: CPU,
+    play_addr: u16,
+    play_cycles: f64,
+    next_play: f64,
+    cycles_per_sample: f64,
+    next_sample: f64,
+    samples: VecDeque<f32>,
+}
+
+impl Player {
+    /// Sets up the NSF hardware and runs INIT for `song`, counting from 0.
+    pub fn new(nsf: &Nsf, song: u8, sample_rate: u32) -> Self {
+        let hz = if nsf.is_pal() {
+            PAL_CPU_HZ
+        } else {
+            NTSC_CPU_HZ
+        };
+        let mut cpu = CPU::new(Bus::with_mapper(Box::new(NsfMapper::new(nsf))));
+        cpu.reset();
+
+        // silence the APU, with the frame counter IRQ off
+        for addr in 0x4000..=0x4013 {
+            cpu.mem_write(addr, 0);
+        }
+        cpu.mem_write(0x4015, 0x0F);
+        cpu.mem_write(0x4017, 0x40);
+
+        cpu.accumulator = song;
+        cpu.register_x = nsf.is_pal() as u8;
+        cpu.call(nsf.init_addr);
+
+        let play_cycles = nsf.play_cycles();
+        Player {
+            play_addr: nsf.play_addr,
+            play_cycles,
+            next_play: cpu.bus.cycles() as f64,
+            cycles_per_sample: hz / sample_rate as f64,
+            next_sample: cpu.bus.cycles() as f64,
+            samples: VecDeque::new(),
+            cpu,
+        }
+    }
+
+    /// Runs the tune for another `count` samples and returns them. Only the
+    /// expansion chips are mixed in, so these are all zero unless the tune
+    /// [is audible](Nsf::is_audible).
+    pub fn render(&mut self, count: usize) -> Vec<f32> {
+        while self.samples.len() < count {
+            self.run_frame();
+        }
+        self.samples.drain(..count).collect()
+    }
+
+    /// Calls PLAY, then lets the chips run until the next call is due.
+    fn run_frame(&mut self) {
+        let mut sampler = Sampler {
+            cycles_per_sample: self.cycles_per_sample,
+            next_sample: self.next_sample,
+            samples: &mut self.samples,
+        };
+        self.cpu
+            .call_with_callback(self.play_addr, |cpu| sampler.sample(&cpu.bus));
+
+        self.next_play += self.play_cycles;
+        while (self.cpu.bus.cycles() as f64) < self.next_play {
+            self.cpu.bus.tick(1);
+            sampler.sample(&self.cpu.bus);
+        }
+        self.next_sample = sampler.next_sample;
+    }
+}
+
+struct Sampler<'a> {
+    cycles_per_sample: f64,
+    next_sample: f64,
+    samples: &'a mut VecDeque<f32>,
+}
+
+impl Sampler<'_> {
+    /// Takes however many samples are due by now.
+    fn sample(&mut self, bus: &Bus) {
+        while bus.cycles() as f64 >= self.next_sample {
+            self.samples.push_back(bus.expansion_audio());
+            self.next_sample += self.cycles_per_sample;
+        }
+    }
+}
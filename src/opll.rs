@@ -0,0 +1,433 @@
+//! FM synthesizer of the VRC7, a trimmed YM2413 (OPLL).
+//!
+//! Six two-operator channels, each playing one of 15 fixed instrument patches or
+//! the single user-defined one in registers $00-$07. The VRC7 has no rhythm mode.
+//! This is a floating point model of the chip rather than a bit-exact one: the
+//! envelope, key scaling, vibrato and tremolo follow the datasheet's curves but
+//! not its internal log/exp tables.
+use std::f32::consts::PI;
+
+/// One output sample per 72 clocks of the 3.58 MHz crystal on the VRC7.
+pub const SAMPLE_RATE: f32 = 49_716.0;
+
+pub const CHANNELS: usize = 6;
+
+/// Built-in patches of the VRC7, as dumped by Nuke.YKT. Patch 0 is the user patch.
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27], // buzzy bell
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12], // guitar
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12], // wurly
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27], // flute
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28], // clarinet
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4], // synth
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07], // trumpet
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17], // organ
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01], // bells
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02], // vibes
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12], // vibraphone
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16], // tutti
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02], // fretless
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6], // synth bass
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06], // sweep
+];
+
+/// Frequency multipliers selected by the MULTI field.
+const MULTIPLIERS: [f32; 16] = [
+    0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0,
+];
+
+/// Key scale attenuation in dB at 6 dB/octave, by the top four bits of F-Number.
+const KEY_SCALE_LEVELS: [f32; 16] = [
+    0.0, 9.0, 12.0, 13.875, 15.0, 16.125, 16.875, 17.625, 18.0, 18.75, 19.125, 19.5, 19.875, 20.25,
+    20.625, 21.0,
+];
+
+/// The envelope generator runs from 0 dB down to this before the operator is silent.
+const MAX_ATTENUATION: f32 = 48.0;
+
+/// Time in seconds for a decay at the fastest rate to cover the whole envelope range.
+/// Every step down in effective rate (0-63) slows it by a quarter octave.
+const FASTEST_DECAY: f32 = 0.0024;
+
+/// Release rate used instead of RR when the channel's sustain bit is set.
+const SUSTAIN_RELEASE_RATE: u8 = 5;
+
+/// Release rate of percussive patches, which already fade at RR while the key is held.
+const PERCUSSIVE_RELEASE_RATE: u8 = 7;
+
+const VIBRATO_HZ: f32 = 6.4;
+const VIBRATO_DEPTH: f32 = 0.0045;
+const TREMOLO_HZ: f32 = 3.7;
+const TREMOLO_DEPTH_DB: f32 = 4.8;
+
+/// Peak phase deviation a full scale modulator puts on its carrier.
+const MODULATION_INDEX: f32 = 4.0 * PI;
+
+/// Scale of a single channel at full volume, in APU mixer units.
+const CHANNEL_LEVEL: f32 = 0.1;
+
+#[derive(Clone, Copy, PartialEq)]
+enum EnvelopeStage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+/// Operator parameters unpacked from a patch.
+#[derive(Clone, Copy)]
+struct OperatorPatch {
+    tremolo: bool,
+    vibrato: bool,
+    sustained: bool,
+    key_scale_rate: bool,
+    multiplier: f32,
+    key_scale_level: u8,
+    rectified: bool,
+    attack_rate: u8,
+    decay_rate: u8,
+    sustain_level: u8,
+    release_rate: u8,
+}
+
+impl OperatorPatch {
+    fn unpack(patch: &[u8; 8], carrier: bool) -> Self {
+        let i = carrier as usize;
+        OperatorPatch {
+            tremolo: patch[i] & 0b1000_0000 != 0,
+            vibrato: patch[i] & 0b0100_0000 != 0,
+            sustained: patch[i] & 0b0010_0000 != 0,
+            key_scale_rate: patch[i] & 0b0001_0000 != 0,
+            multiplier: MULTIPLIERS[(patch[i] & 0b1111) as usize],
+            key_scale_level: patch[2 + i] >> 6,
+            rectified: patch[3] & (0b1000 << i) != 0,
+            attack_rate: patch[4 + i] >> 4,
+            decay_rate: patch[4 + i] & 0b1111,
+            sustain_level: patch[6 + i] >> 4,
+            release_rate: patch[6 + i] & 0b1111,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Operator {
+    phase: f32,
+    stage: EnvelopeStage,
+    attenuation: f32,
+}
+
+impl Operator {
+    fn new() -> Self {
+        Operator {
+            phase: 0.0,
+            stage: EnvelopeStage::Off,
+            attenuation: MAX_ATTENUATION,
+        }
+    }
+
+    fn key_on(&mut self) {
+        self.phase = 0.0;
+        self.stage = EnvelopeStage::Attack;
+    }
+
+    fn key_off(&mut self) {
+        if self.stage != EnvelopeStage::Off {
+            self.stage = EnvelopeStage::Release;
+        }
+    }
+
+    /// dB the envelope moves per sample at `rate` (0-15), after key scaling.
+    fn envelope_step(rate: u8, key_scale: u8, attack: bool) -> f32 {
+        if rate == 0 {
+            return 0.0;
+        }
+        let effective = (rate * 4 + key_scale).min(63) as f32;
+        let seconds = FASTEST_DECAY * 2f32.powf((60.0 - effective) / 4.0);
+        // attack is roughly an order of magnitude faster than decay at the same rate
+        let seconds = if attack { seconds / 8.0 } else { seconds };
+        MAX_ATTENUATION / (seconds * SAMPLE_RATE)
+    }
+
+    fn clock_envelope(&mut self, patch: &OperatorPatch, key_scale: u8, sustain_bit: bool) {
+        match self.stage {
+            EnvelopeStage::Attack => {
+                if patch.attack_rate == 15 {
+                    self.attenuation = 0.0;
+                } else {
+                    // attack follows an exponential curve, fast at first and then settling
+                    let step = Self::envelope_step(patch.attack_rate, key_scale, true);
+                    self.attenuation -= step * (1.0 + self.attenuation / 6.0);
+                }
+                if self.attenuation <= 0.0 {
+                    self.attenuation = 0.0;
+                    self.stage = EnvelopeStage::Decay;
+                }
+            }
+            EnvelopeStage::Decay => {
+                self.attenuation += Self::envelope_step(patch.decay_rate, key_scale, false);
+                let sustain = patch.sustain_level as f32 * 3.0;
+                if self.attenuation >= sustain {
+                    self.attenuation = sustain;
+                    self.stage = EnvelopeStage::Sustain;
+                }
+            }
+            EnvelopeStage::Sustain => {
+                // percussive patches keep fading at the release rate while the key is held
+                if !patch.sustained {
+                    self.attenuation += Self::envelope_step(patch.release_rate, key_scale, false);
+                }
+            }
+            EnvelopeStage::Release => {
+                let rate = if sustain_bit {
+                    SUSTAIN_RELEASE_RATE
+                } else if patch.sustained {
+                    patch.release_rate
+                } else {
+                    PERCUSSIVE_RELEASE_RATE
+                };
+                self.attenuation += Self::envelope_step(rate, key_scale, false);
+            }
+            EnvelopeStage::Off => {}
+        }
+
+        if self.attenuation >= MAX_ATTENUATION {
+            self.attenuation = MAX_ATTENUATION;
+            if self.stage != EnvelopeStage::Attack {
+                self.stage = EnvelopeStage::Off;
+            }
+        }
+    }
+
+    /// Advances the phase by `frequency` Hz and returns the operator's output for
+    /// `modulation` radians of phase offset, attenuated by `level` dB.
+    fn output(&mut self, frequency: f32, modulation: f32, level: f32, rectified: bool) -> f32 {
+        self.phase = (self.phase + frequency / SAMPLE_RATE).fract();
+        if self.stage == EnvelopeStage::Off {
+            return 0.0;
+        }
+
+        let wave = (2.0 * PI * self.phase + modulation).sin();
+        let wave = if rectified { wave.max(0.0) } else { wave };
+        let attenuation = self.attenuation + level;
+        if attenuation >= MAX_ATTENUATION * 2.0 {
+            0.0
+        } else {
+            wave * 10f32.powf(-attenuation / 20.0)
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Channel {
+    f_number: u16,
+    block: u8,
+    key: bool,
+    sustain: bool,
+    instrument: u8,
+    volume: u8,
+    modulator: Operator,
+    carrier: Operator,
+    feedback: [f32; 2],
+}
+
+impl Channel {
+    fn new() -> Self {
+        Channel {
+            f_number: 0,
+            block: 0,
+            key: false,
+            sustain: false,
+            instrument: 0,
+            volume: 0,
+            modulator: Operator::new(),
+            carrier: Operator::new(),
+            feedback: [0.0; 2],
+        }
+    }
+
+    fn frequency(&self) -> f32 {
+        self.f_number as f32 * SAMPLE_RATE / (1 << (19 - self.block)) as f32
+    }
+
+    /// Rate key scaling, added to the effective envelope rate.
+    fn key_scale_rate(&self, patch: &OperatorPatch) -> u8 {
+        let rks = (self.block << 1) | (self.f_number >> 8) as u8;
+        if patch.key_scale_rate {
+            rks
+        } else {
+            rks >> 2
+        }
+    }
+
+    /// Level key scaling in dB.
+    fn key_scale_level(&self, patch: &OperatorPatch) -> f32 {
+        if patch.key_scale_level == 0 {
+            return 0.0;
+        }
+        let base = KEY_SCALE_LEVELS[(self.f_number >> 5) as usize & 0b1111];
+        let level = (base - 6.0 * (7 - self.block) as f32).max(0.0);
+        // KSL 1-3 select 1.5, 3 and 6 dB/octave
+        level * [0.0, 0.25, 0.5, 1.0][patch.key_scale_level as usize]
+    }
+}
+
+pub struct Opll {
+    address: u8,
+    user_patch: [u8; 8],
+    channels: [Channel; CHANNELS],
+    // vibrato and tremolo LFO phases, in cycles
+    vibrato_phase: f32,
+    tremolo_phase: f32,
+    output: f32,
+}
+
+impl Default for Opll {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Opll {
+    pub fn new() -> Self {
+        Opll {
+            address: 0,
+            user_patch: [0; 8],
+            channels: [Channel::new(); CHANNELS],
+            vibrato_phase: 0.0,
+            tremolo_phase: 0.0,
+            output: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Opll::new();
+    }
+
+    pub fn write_address(&mut self, data: u8) {
+        self.address = data;
+    }
+
+    pub fn write_data(&mut self, data: u8) {
+        self.write_register(self.address, data);
+    }
+
+    pub fn write_register(&mut self, register: u8, data: u8) {
+        let index = (register & 0x0F) as usize;
+        match register {
+            0x00..=0x07 => self.user_patch[index] = data,
+            0x10..=0x15 => {
+                let channel = &mut self.channels[index];
+                channel.f_number = (channel.f_number & 0x100) | data as u16;
+            }
+            0x20..=0x25 => {
+                let channel = &mut self.channels[index];
+                channel.f_number = (channel.f_number & 0xFF) | ((data as u16 & 1) << 8);
+                channel.block = (data >> 1) & 0b111;
+                channel.sustain = data & 0b10_0000 != 0;
+
+                let key = data & 0b1_0000 != 0;
+                if key && !channel.key {
+                    channel.modulator.key_on();
+                    channel.carrier.key_on();
+                    channel.feedback = [0.0; 2];
+                } else if !key && channel.key {
+                    channel.modulator.key_off();
+                    channel.carrier.key_off();
+                }
+                channel.key = key;
+            }
+            0x30..=0x35 => {
+                let channel = &mut self.channels[index];
+                channel.instrument = data >> 4;
+                channel.volume = data & 0b1111;
+            }
+            _ => {}
+        }
+    }
+
+    fn patch(&self, instrument: u8) -> &[u8; 8] {
+        if instrument == 0 {
+            &self.user_patch
+        } else {
+            &PATCHES[instrument as usize - 1]
+        }
+    }
+
+    /// Generates the next sample, advancing the chip by 1 / `SAMPLE_RATE` seconds.
+    pub fn clock(&mut self) -> f32 {
+        // wrapped every sample, a running time would lose precision within minutes
+        self.vibrato_phase = (self.vibrato_phase + VIBRATO_HZ / SAMPLE_RATE).fract();
+        self.tremolo_phase = (self.tremolo_phase + TREMOLO_HZ / SAMPLE_RATE).fract();
+        let vibrato = 1.0 + VIBRATO_DEPTH * (2.0 * PI * self.vibrato_phase).sin();
+        let tremolo = TREMOLO_DEPTH_DB * 0.5 * (1.0 + (2.0 * PI * self.tremolo_phase).sin());
+
+        let mut mix = 0.0;
+        for i in 0..CHANNELS {
+            let mut channel = self.channels[i];
+            let patch = *self.patch(channel.instrument);
+            let modulator_patch = OperatorPatch::unpack(&patch, false);
+            let carrier_patch = OperatorPatch::unpack(&patch, true);
+            let frequency = channel.frequency();
+
+            let rks = channel.key_scale_rate(&modulator_patch);
+            channel
+                .modulator
+                .clock_envelope(&modulator_patch, rks, channel.sustain);
+            let rks = channel.key_scale_rate(&carrier_patch);
+            channel
+                .carrier
+                .clock_envelope(&carrier_patch, rks, channel.sustain);
+
+            let feedback_level = patch[3] & 0b111;
+            let feedback = if feedback_level == 0 {
+                0.0
+            } else {
+                (channel.feedback[0] + channel.feedback[1]) / 2.0 * PI / 16.0
+                    * (1 << (feedback_level - 1)) as f32
+            };
+
+            let mut level =
+                (patch[2] & 0b11_1111) as f32 * 0.75 + channel.key_scale_level(&modulator_patch);
+            if modulator_patch.tremolo {
+                level += tremolo;
+            }
+            let mut modulator_frequency = frequency * modulator_patch.multiplier;
+            if modulator_patch.vibrato {
+                modulator_frequency *= vibrato;
+            }
+            let modulation = channel.modulator.output(
+                modulator_frequency,
+                feedback,
+                level,
+                modulator_patch.rectified,
+            );
+            channel.feedback = [channel.feedback[1], modulation];
+
+            let mut level = channel.volume as f32 * 3.0 + channel.key_scale_level(&carrier_patch);
+            if carrier_patch.tremolo {
+                level += tremolo;
+            }
+            let mut carrier_frequency = frequency * carrier_patch.multiplier;
+            if carrier_patch.vibrato {
+                carrier_frequency *= vibrato;
+            }
+            mix += channel.carrier.output(
+                carrier_frequency,
+                modulation * MODULATION_INDEX,
+                level,
+                carrier_patch.rectified,
+            );
+
+            self.channels[i] = channel;
+        }
+
+        self.output = mix * CHANNEL_LEVEL;
+        self.output
+    }
+
+    /// The most recent sample produced by `clock`.
+    pub fn output(&self) -> f32 {
+        self.output
+    }
+}
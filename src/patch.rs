@@ -0,0 +1,355 @@
+//! Soft-patching ROMs with IPS, UPS and BPS patches.
+//!
+//! Patches apply to the raw file, header included, before it is parsed. IPS
+//! patches are lists of records that overwrite bytes at an offset. UPS patches
+//! XOR the ROM with a difference, and BPS patches rebuild it from copies of the
+//! original, of themselves and of what they have already written. Both of the
+//! latter end in CRC-32s of the original, the result and the patch itself,
+//! which are checked so a patch meant for another dump fails instead of
+//! producing garbage.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::hash::crc32;
+
+pub const IPS_MAGIC: &[u8] = b"PATCH";
+pub const UPS_MAGIC: &[u8] = b"UPS1";
+pub const BPS_MAGIC: &[u8] = b"BPS1";
+
+const IPS_EOF: &[u8] = b"EOF";
+/// Source, target and patch CRC-32 at the end of UPS and BPS patches.
+const FOOTER_SIZE: usize = 12;
+/// Largest ROM a UPS or BPS patch may produce, well above any NES game. The size
+/// comes from the patch, so without a limit a forged one asks for any allocation.
+pub const MAX_TARGET_SIZE: usize = 16 << 20;
+
+/// Which checksum of a UPS or BPS patch failed.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Checksum {
+    /// The ROM isn't the one the patch was made for.
+    Source,
+    /// Patching produced something else than the patch promised.
+    Target,
+    /// The patch file itself is damaged.
+    Patch,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PatchError {
+    /// The file isn't an IPS, UPS or BPS patch.
+    UnknownFormat { found: Vec<u8> },
+    /// The patch ends in the middle of a record, or without its footer.
+    Truncated { offset: usize },
+    /// A BPS copy reaches outside the data it copies from, or a number or
+    /// position in the patch is out of range.
+    OutOfBounds { offset: usize },
+    /// The patch claims a result larger than `MAX_TARGET_SIZE`.
+    TargetTooLarge { size: usize },
+    ChecksumMismatch {
+        checksum: Checksum,
+        expected: u32,
+        found: u32,
+    },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat { found } => {
+                write!(f, "Not an IPS, UPS or BPS patch (magic {:02X?})", found)
+            }
+            PatchError::Truncated { offset } => {
+                write!(f, "Patch is truncated at offset {}", offset)
+            }
+            PatchError::OutOfBounds { offset } => {
+                write!(f, "Patch reaches outside the ROM at offset {}", offset)
+            }
+            PatchError::TargetTooLarge { size } => write!(
+                f,
+                "Patch would make a {} byte ROM, more than the {} allowed",
+                size, MAX_TARGET_SIZE
+            ),
+            PatchError::ChecksumMismatch {
+                checksum,
+                expected,
+                found,
+            } => {
+                let what = match checksum {
+                    Checksum::Source => "Patch is for a different ROM",
+                    Checksum::Target => "Patched ROM is not what the patch expects",
+                    Checksum::Patch => "Patch is damaged",
+                };
+                write!(
+                    f,
+                    "{}: CRC32 is {:08X}, expected {:08X}",
+                    what, found, expected
+                )
+            }
+        }
+    }
+}
+
+impl Error for PatchError {}
+
+/// Reads through a patch, keeping track of the offset for errors.
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], PatchError> {
+        let truncated = PatchError::Truncated {
+            offset: self.offset,
+        };
+        let end = self.offset.checked_add(len).ok_or(truncated.clone())?;
+        let bytes = self.data.get(self.offset..end).ok_or(truncated)?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn big_endian(&mut self, len: usize) -> Result<usize, PatchError> {
+        Ok(self
+            .bytes(len)?
+            .iter()
+            .fold(0, |value, &byte| value << 8 | byte as usize))
+    }
+
+    /// The variable-length numbers of UPS and BPS: 7 bits per byte, low bits
+    /// first, with the top bit marking the last byte.
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let out_of_bounds = PatchError::OutOfBounds {
+            offset: self.offset,
+        };
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.byte()?;
+            value = ((byte & 0x7F) as usize)
+                .checked_mul(shift)
+                .and_then(|bits| value.checked_add(bits))
+                .ok_or(out_of_bounds.clone())?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_mul(0x80).ok_or(out_of_bounds.clone())?;
+            value = value.checked_add(shift).ok_or(out_of_bounds.clone())?;
+        }
+    }
+
+    /// The target size of a UPS or BPS patch.
+    fn target_size(&mut self) -> Result<usize, PatchError> {
+        let size = self.number()?;
+        if size > MAX_TARGET_SIZE {
+            return Err(PatchError::TargetTooLarge { size });
+        }
+        Ok(size)
+    }
+}
+
+/// Applies `patch` to `rom`, telling the format from its magic.
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(UPS_MAGIC) {
+        apply_ups(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err(unknown_format(patch))
+    }
+}
+
+fn unknown_format(patch: &[u8]) -> PatchError {
+    PatchError::UnknownFormat {
+        found: patch.iter().take(5).copied().collect(),
+    }
+}
+
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if !patch.starts_with(IPS_MAGIC) {
+        return Err(unknown_format(patch));
+    }
+    let mut out = rom.to_vec();
+    let mut reader = Reader {
+        data: patch,
+        offset: IPS_MAGIC.len(),
+    };
+    loop {
+        let record = reader.bytes(3)?;
+        if record == IPS_EOF {
+            break;
+        }
+        let offset = record
+            .iter()
+            .fold(0, |value, &byte| value << 8 | byte as usize);
+        let (len, data) = match reader.big_endian(2)? {
+            // run-length encoded
+            0 => {
+                let len = reader.big_endian(2)?;
+                (len, None)
+            }
+            len => (len, Some(reader.bytes(len)?)),
+        };
+        if out.len() < offset + len {
+            out.resize(offset + len, 0);
+        }
+        match data {
+            Some(data) => out[offset..offset + len].copy_from_slice(data),
+            None => out[offset..offset + len].fill(reader.byte()?),
+        }
+    }
+    // an extension some patches use to cut the file down
+    if let Ok(len) = reader.big_endian(3) {
+        out.truncate(len);
+    }
+    Ok(out)
+}
+
+/// Splits off and checks the footer of a UPS or BPS patch, and checks the
+/// source checksum against `rom`. Returns the body and the target checksum.
+fn checked_body<'a>(rom: &[u8], patch: &'a [u8]) -> Result<(&'a [u8], u32), PatchError> {
+    if patch.len() < FOOTER_SIZE + 4 {
+        return Err(PatchError::Truncated {
+            offset: patch.len(),
+        });
+    }
+    let (body, footer) = patch.split_at(patch.len() - FOOTER_SIZE);
+    let word = |i: usize| u32::from_le_bytes(footer[i..i + 4].try_into().unwrap());
+    verify(Checksum::Patch, word(8), &patch[..patch.len() - 4])?;
+    verify(Checksum::Source, word(0), rom)?;
+    Ok((body, word(4)))
+}
+
+fn verify(checksum: Checksum, expected: u32, data: &[u8]) -> Result<(), PatchError> {
+    let found = crc32(data);
+    if found != expected {
+        return Err(PatchError::ChecksumMismatch {
+            checksum,
+            expected,
+            found,
+        });
+    }
+    Ok(())
+}
+
+pub fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if !patch.starts_with(UPS_MAGIC) {
+        return Err(unknown_format(patch));
+    }
+    let (body, target_crc) = checked_body(rom, patch)?;
+    let mut reader = Reader {
+        data: body,
+        offset: UPS_MAGIC.len(),
+    };
+    let _source_size = reader.number()?;
+    let target_size = reader.target_size()?;
+
+    let mut out = rom.to_vec();
+    out.resize(target_size, 0);
+    let mut position: usize = 0;
+    while reader.offset < body.len() {
+        let offset = reader.offset;
+        position = position
+            .checked_add(reader.number()?)
+            .filter(|&position| position <= target_size)
+            .ok_or(PatchError::OutOfBounds { offset })?;
+        loop {
+            let xor = reader.byte()?;
+            // the terminating zero stands for an unchanged byte
+            if xor == 0 {
+                position += 1;
+                break;
+            }
+            if let Some(byte) = out.get_mut(position) {
+                *byte ^= xor;
+            }
+            position += 1;
+        }
+    }
+
+    verify(Checksum::Target, target_crc, &out)?;
+    Ok(out)
+}
+
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if !patch.starts_with(BPS_MAGIC) {
+        return Err(unknown_format(patch));
+    }
+    let (body, target_crc) = checked_body(rom, patch)?;
+    let mut reader = Reader {
+        data: body,
+        offset: BPS_MAGIC.len(),
+    };
+    let _source_size = reader.number()?;
+    let target_size = reader.target_size()?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+
+    let mut out = Vec::with_capacity(target_size);
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+    while reader.offset < body.len() {
+        let action_offset = reader.offset;
+        let action = reader.number()?;
+        let len = (action >> 2) + 1;
+        let out_of_bounds = PatchError::OutOfBounds {
+            offset: action_offset,
+        };
+        if out.len() + len > target_size {
+            return Err(out_of_bounds);
+        }
+        match action & 0b11 {
+            // source read: the original's bytes at the same position
+            0 => {
+                let start = out.len();
+                let data = rom.get(start..start + len).ok_or(out_of_bounds)?;
+                out.extend_from_slice(data);
+            }
+            // target read: bytes from the patch
+            1 => out.extend_from_slice(reader.bytes(len)?),
+            // source copy: the original's bytes from anywhere
+            2 => {
+                source_offset =
+                    relative(source_offset, reader.number()?).ok_or(out_of_bounds.clone())?;
+                let end = source_offset
+                    .checked_add(len)
+                    .ok_or(out_of_bounds.clone())?;
+                let data = rom.get(source_offset..end).ok_or(out_of_bounds)?;
+                out.extend_from_slice(data);
+                source_offset += len;
+            }
+            // target copy: what was already written, possibly overlapping
+            _ => {
+                target_offset =
+                    relative(target_offset, reader.number()?).ok_or(out_of_bounds.clone())?;
+                if target_offset >= out.len() {
+                    return Err(out_of_bounds);
+                }
+                for _ in 0..len {
+                    out.push(out[target_offset]);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    verify(Checksum::Target, target_crc, &out)?;
+    Ok(out)
+}
+
+/// Moves `offset` by a BPS relative offset: the magnitude shifted up by one,
+/// with a sign in the low bit.
+fn relative(offset: usize, encoded: usize) -> Option<usize> {
+    let distance = encoded >> 1;
+    if encoded & 1 != 0 {
+        offset.checked_sub(distance)
+    } else {
+        offset.checked_add(distance)
+    }
+}
@@ -0,0 +1,28 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Where the save file for the ROM at `rom_path` lives: next to it, with the
+/// extension replaced by `.sav`.
+pub fn sav_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("sav")
+}
+
+/// Reads a save file, or returns `None` if there isn't one yet.
+pub fn load(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Writes a save file without ever leaving a half-written one behind: the data
+/// goes to a temporary file first, which then replaces the old save in one step.
+pub fn store(path: &Path, data: &[u8]) -> io::Result<()> {
+    let temp_path = path.with_extension("sav.tmp");
+    let mut file = fs::File::create(&temp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)
+}
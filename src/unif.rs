@@ -0,0 +1,210 @@
+//! UNIF (`.unf`) cartridge images.
+//!
+//! A 32-byte header ("UNIF", a revision number and padding) followed by chunks,
+//! each a four-character ID, a little-endian length and that many bytes. The board
+//! name in MAPR stands in for the iNES mapper number.
+
+use crate::cartridge::{section, ConsoleType, Mirroring, Rom, RomError, Timing};
+use crate::mapper;
+
+pub const MAGIC: &[u8] = b"UNIF";
+const HEADER_SIZE: usize = 32;
+const CHUNK_HEADER_SIZE: usize = 8;
+const WORK_RAM_SIZE: usize = 0x2000;
+const CHR_RAM_SIZE: usize = 0x2000;
+
+// Board names, without the NES-/HVC-/UNL-... prefix, and the mapper and
+// submapper the iNES world knows them by.
+const BOARDS: &[(&[&str], u16, u8)] = &[
+    (
+        &[
+            "NROM",
+            "NROM-128",
+            "NROM-256",
+            "RROM",
+            "RROM-128",
+            "FAMILYBASIC",
+        ],
+        0,
+        0,
+    ),
+    (&["UNROM", "UOROM"], 2, 0),
+    (&["CNROM"], 3, 0),
+    (
+        &[
+            "TBROM", "TEROM", "TFROM", "TGROM", "TKROM", "TLROM", "TNROM", "TR1ROM", "TSROM",
+            "TVROM", "B4",
+        ],
+        4,
+        0,
+    ),
+    (&["EKROM", "ELROM", "ETROM", "EWROM"], 5, 0),
+    (&["AMROM", "ANROM", "AN1ROM", "AOROM"], 7, 0),
+    (&["PNROM", "PEEOROM"], 9, 0),
+    (&["FJROM", "FKROM"], 10, 0),
+    (&["UNROM-512-8", "UNROM-512-16", "UNROM-512-32"], 30, 0),
+    (&["JLROM", "JSROM", "BTR"], 69, 0),
+    (&["DEROM", "DE1ROM", "DRROM"], 206, 0),
+];
+
+const BOARD_PREFIXES: &[&str] = &[
+    "NES-", "HVC-", "UNL-", "BMC-", "BTL-", "IREM-", "KONAMI-", "NAMCOT-", "TENGEN-",
+];
+
+/// The mapper and submapper implementing a UNIF board.
+pub fn board_mapper(name: &str) -> Option<(u16, u8)> {
+    let board = BOARD_PREFIXES
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name);
+    BOARDS
+        .iter()
+        .find(|(names, _, _)| names.contains(&board))
+        .map(|&(_, mapper, submapper)| (mapper, submapper))
+}
+
+/// The 0-F in PRG0..PRGF/CHR0..CHRF.
+fn rom_index(id: &[u8], prefix: &[u8]) -> Option<usize> {
+    if !id.starts_with(prefix) {
+        return None;
+    }
+    (id[3] as char).to_digit(16).map(|digit| digit as usize)
+}
+
+/// Loads a UNIF image into a `Rom`, as if it came from the matching iNES header.
+pub fn load(raw: &[u8]) -> Result<Rom, RomError> {
+    if raw.len() < HEADER_SIZE {
+        return Err(RomError::TruncatedHeader { len: raw.len() });
+    }
+    if !raw.starts_with(MAGIC) {
+        return Err(RomError::BadMagic {
+            found: raw[0..4].to_vec(),
+        });
+    }
+
+    let mut board = None;
+    let mut prg_chunks: [Option<&[u8]>; 16] = [None; 16];
+    let mut chr_chunks: [Option<&[u8]>; 16] = [None; 16];
+    let mut mirroring = Mirroring::HORIZONTAL;
+    let mut battery = false;
+    let mut timing = Timing::NTSC;
+    let mut chr_is_ram = false;
+
+    let mut offset = HEADER_SIZE;
+    while offset < raw.len() {
+        let header = section(raw, offset, CHUNK_HEADER_SIZE).map_err(|available| {
+            RomError::TruncatedChunk {
+                id: String::from_utf8_lossy(&raw[offset..raw.len().min(offset + 4)]).into_owned(),
+                offset,
+                expected: CHUNK_HEADER_SIZE,
+                available,
+            }
+        })?;
+        let id = &header[0..4];
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let data_offset = offset + CHUNK_HEADER_SIZE;
+        let data =
+            section(raw, data_offset, len).map_err(|available| RomError::TruncatedChunk {
+                id: String::from_utf8_lossy(id).into_owned(),
+                offset: data_offset,
+                expected: len,
+                available,
+            })?;
+
+        if let Some(index) = rom_index(id, b"PRG") {
+            prg_chunks[index] = Some(data);
+        } else if let Some(index) = rom_index(id, b"CHR") {
+            chr_chunks[index] = Some(data);
+        } else {
+            match id {
+                b"MAPR" => {
+                    let name = data.split(|&byte| byte == 0).next().unwrap_or_default();
+                    board = Some(String::from_utf8_lossy(name).trim().to_string());
+                }
+                b"MIRR" => {
+                    mirroring = match data.first() {
+                        Some(1) => Mirroring::VERTICAL,
+                        Some(2) => Mirroring::SINGLE_SCREEN_LOWER,
+                        Some(3) => Mirroring::SINGLE_SCREEN_UPPER,
+                        Some(4) => Mirroring::FOUR_SCREEN,
+                        // 5 leaves it to the mapper
+                        _ => Mirroring::HORIZONTAL,
+                    }
+                }
+                b"BATR" => battery = data.first().is_none_or(|&flag| flag != 0),
+                b"TVCI" => {
+                    timing = match data.first() {
+                        Some(1) => Timing::PAL,
+                        Some(2) => Timing::MULTI_REGION,
+                        _ => Timing::NTSC,
+                    }
+                }
+                b"VROR" => chr_is_ram = true,
+                // NAME, READ, DINF, CTRL, the checksums and so on don't affect emulation
+                _ => {}
+            }
+        }
+        offset = data_offset + len;
+    }
+
+    let board = board.ok_or(RomError::MissingChunk { id: "MAPR" })?;
+    let (mapper, submapper) = board_mapper(&board)
+        .filter(|&(mapper, submapper)| mapper::is_supported(mapper, submapper))
+        .ok_or(RomError::UnsupportedBoard {
+            name: board.clone(),
+        })?;
+
+    let prg_rom: Vec<u8> = prg_chunks
+        .iter()
+        .flatten()
+        .flat_map(|chunk| chunk.iter())
+        .copied()
+        .collect();
+    if prg_rom.is_empty() {
+        return Err(RomError::MissingChunk { id: "PRG0" });
+    }
+    let mut chr_rom: Vec<u8> = chr_chunks
+        .iter()
+        .flatten()
+        .flat_map(|chunk| chunk.iter())
+        .copied()
+        .collect();
+    let chr_ram_size = if chr_is_ram || chr_rom.is_empty() {
+        chr_rom.len().max(CHR_RAM_SIZE)
+    } else {
+        0
+    };
+    if chr_is_ram {
+        chr_rom.clear();
+    }
+
+    let (prg_ram_size, prg_nvram_size) = if battery {
+        (0, WORK_RAM_SIZE)
+    } else {
+        (WORK_RAM_SIZE, 0)
+    };
+
+    Ok(Rom {
+        prg_rom,
+        chr_rom,
+        trainer: None,
+        mapper,
+        submapper,
+        screen_mirroring: mirroring,
+        vertical_mirroring_bit: mirroring == Mirroring::VERTICAL,
+        prg_ram_size,
+        prg_nvram_size,
+        chr_ram_size,
+        chr_nvram_size: 0,
+        battery,
+        bus_conflicts: false,
+        nes2: false,
+        archaic: false,
+        timing,
+        console_type: ConsoleType::NES,
+        misc_roms: 0,
+        expansion_device: 0,
+        header_fixes: Vec::new(),
+        disk_sides: Vec::new(),
+    })
+}
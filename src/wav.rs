@@ -0,0 +1,34 @@
+//! Writing audio out as 16-bit mono PCM WAV files.
+
+use std::io::{self, Write};
+
+const BITS_PER_SAMPLE: u16 = 16;
+
+/// Writes `samples`, in the mixer's units where 1.0 is full scale, as a WAV file.
+/// Anything louder is clipped.
+pub fn write<W: Write>(out: &mut W, sample_rate: u32, samples: &[f32]) -> io::Result<()> {
+    let block_align = BITS_PER_SAMPLE / 8;
+    let data_len = samples.len() as u32 * block_align as u32;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    // PCM, one channel
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    out.write_all(&block_align.to_le_bytes())?;
+    out.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+    for &sample in samples {
+        let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        out.write_all(&sample.to_le_bytes())?;
+    }
+    Ok(())
+}
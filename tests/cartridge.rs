@@ -0,0 +1,381 @@
+use rust_NES::bus::Bus;
+use rust_NES::cartridge::test::test_rom;
+use rust_NES::cartridge::{ConsoleType, Mirroring, Rom, RomBuilder, RomError, Timing};
+use rust_NES::cpu::Mem;
+use rust_NES::mapper;
+
+#[test]
+fn test_ines_header_defaults() {
+    let mut raw = RomBuilder::new(vec![0; 0x8000], Vec::new())
+        .battery(true)
+        .to_ines();
+    // no work RAM size
+    raw[8] = 0;
+    let rom = Rom::new(&raw).unwrap();
+
+    assert!(!rom.nes2);
+    assert_eq!(rom.prg_rom.len(), 0x8000);
+    assert_eq!(rom.prg_ram_size, 0);
+    assert_eq!(rom.prg_nvram_size, 0x2000);
+    assert_eq!(rom.chr_ram_size, 0x2000);
+    assert_eq!(rom.chr_nvram_size, 0);
+    assert_eq!(rom.timing, Timing::NTSC);
+    assert_eq!(rom.console_type, ConsoleType::NES);
+    assert_eq!(rom.misc_roms, 0);
+    assert_eq!(rom.expansion_device, 0);
+}
+
+#[test]
+fn test_nes2_mapper_and_submapper() {
+    // mapper 341 only fits in the 12 bits of NES 2.0
+    let raw = RomBuilder::new(vec![0; 0x4000], vec![0; 0x2000])
+        .mapper(341)
+        .nes2(true)
+        .to_ines();
+    assert_eq!((raw[6] >> 4, raw[7] >> 4, raw[8]), (5, 5, 1));
+    assert_eq!(
+        Rom::new(&raw).err().unwrap(),
+        RomError::UnsupportedMapper {
+            mapper: 341,
+            submapper: 0
+        }
+    );
+
+    let raw = RomBuilder::new(vec![0; 0x20000], vec![0; 0x20000])
+        .mapper(21)
+        .submapper(3)
+        .nes2(true)
+        .to_ines();
+    let rom = Rom::new(&raw).unwrap();
+    assert!(rom.nes2);
+    assert_eq!(rom.mapper, 21);
+    assert_eq!(rom.submapper, 3);
+}
+
+#[test]
+fn test_nes2_rom_sizes() {
+    // the size MSB nibbles in byte 9 extend the page counts
+    let raw = RomBuilder::new(vec![0; 256 * 0x4000], Vec::new())
+        .nes2(true)
+        .to_ines();
+    assert_eq!((raw[4], raw[9]), (0, 0x01));
+    let rom = Rom::new(&raw).unwrap();
+    assert_eq!(rom.prg_rom.len(), 256 * 0x4000);
+    assert_eq!(rom.chr_rom.len(), 0);
+
+    // exponent-multiplier: 2^E * (MM*2+1), here 2^10 * 3 and 2^9 * 1
+    let raw = RomBuilder::new(vec![0; 3 * 1024], vec![0; 512])
+        .nes2(true)
+        .to_ines();
+    assert_eq!((raw[4], raw[5], raw[9]), (0b0010_1001, 0b0010_0100, 0xFF));
+    let rom = Rom::new(&raw).unwrap();
+    assert_eq!(rom.prg_rom.len(), 3 * 1024);
+    assert_eq!(rom.chr_rom.len(), 512);
+}
+
+#[test]
+fn test_nes2_ram_sizes() {
+    // sizes are 64 << shift
+    let raw = RomBuilder::new(vec![0; 0x8000], Vec::new())
+        .nes2(true)
+        .battery(true)
+        .prg_nvram_size(0x2000)
+        .chr_ram_size(0x8000)
+        .to_ines();
+    assert_eq!((raw[10], raw[11]), (0x70, 0x09));
+    let rom = Rom::new(&raw).unwrap();
+    assert_eq!(rom.prg_ram_size, 0);
+    assert_eq!(rom.prg_nvram_size, 64 << 7);
+    assert_eq!(rom.chr_ram_size, 64 << 9);
+    assert_eq!(rom.chr_nvram_size, 0);
+
+    let raw = RomBuilder::new(vec![0; 0x8000], Vec::new())
+        .nes2(true)
+        .prg_ram_size(0x2000)
+        .chr_ram_size(0x2000)
+        .chr_nvram_size(0x8000)
+        .to_ines();
+    assert_eq!((raw[10], raw[11]), (0x07, 0x97));
+    let rom = Rom::new(&raw).unwrap();
+    assert_eq!(rom.prg_ram_size, 64 << 7);
+    assert_eq!(rom.prg_nvram_size, 0);
+    assert_eq!(rom.chr_ram_size, 64 << 7);
+    assert_eq!(rom.chr_nvram_size, 64 << 9);
+}
+
+#[test]
+fn test_nes2_timing_console_and_devices() {
+    let builder = RomBuilder::new(vec![0; 0x8000], vec![0; 0x2000]).nes2(true);
+    let timings = [
+        Timing::NTSC,
+        Timing::PAL,
+        Timing::MULTI_REGION,
+        Timing::DENDY,
+    ];
+    for (byte, timing) in timings.into_iter().enumerate() {
+        let raw = builder.clone().timing(timing).to_ines();
+        assert_eq!(raw[12], byte as u8);
+        assert_eq!(Rom::new(&raw).unwrap().timing, timing);
+    }
+
+    let mut raw = builder
+        .clone()
+        .console_type(ConsoleType::VS_SYSTEM {
+            ppu: 2,
+            hardware: 5,
+        })
+        .expansion_device(8)
+        .to_ines();
+    assert_eq!((raw[7] & 0b11, raw[13], raw[15]), (1, 0x52, 8));
+    // one misc ROM
+    raw[14] = 1;
+    let rom = Rom::new(&raw).unwrap();
+    assert_eq!(
+        rom.console_type,
+        ConsoleType::VS_SYSTEM {
+            ppu: 2,
+            hardware: 5
+        }
+    );
+    assert_eq!(rom.misc_roms, 1);
+    assert_eq!(rom.expansion_device, 8);
+
+    let raw = builder
+        .clone()
+        .console_type(ConsoleType::PLAYCHOICE_10)
+        .to_ines();
+    assert_eq!(raw[7] & 0b11, 2);
+    let rom = Rom::new(&raw).unwrap();
+    assert_eq!(rom.console_type, ConsoleType::PLAYCHOICE_10);
+
+    let raw = builder.console_type(ConsoleType::EXTENDED(3)).to_ines();
+    assert_eq!((raw[7] & 0b11, raw[13]), (3, 3));
+    let rom = Rom::new(&raw).unwrap();
+    assert_eq!(rom.console_type, ConsoleType::EXTENDED(3));
+}
+
+#[test]
+fn test_short_or_foreign_files_are_errors() {
+    assert_eq!(
+        Rom::new(&vec![0x4e, 0x45, 0x53]).err(),
+        Some(RomError::TruncatedHeader { len: 3 })
+    );
+
+    let mut raw = RomBuilder::new(vec![0; 0x4000], vec![0; 0x2000]).to_ines();
+    raw[3] = 0;
+    assert_eq!(
+        Rom::new(&raw).err(),
+        Some(RomError::BadMagic {
+            found: vec![0x4e, 0x45, 0x53, 0]
+        })
+    );
+}
+
+#[test]
+fn test_truncated_sections_report_offsets() {
+    let nrom = RomBuilder::new(vec![0; 0x4000], vec![0; 0x2000]);
+    let with_trainer = nrom.clone().trainer([0; 512]).to_ines();
+    assert_eq!(
+        Rom::new(&with_trainer[..16 + 100].to_vec()).err(),
+        Some(RomError::TruncatedTrainer {
+            offset: 16,
+            available: 100
+        })
+    );
+    assert_eq!(
+        Rom::new(&nrom.to_ines()[..16 + 0x3000].to_vec()).err(),
+        Some(RomError::TruncatedPrg {
+            offset: 16,
+            expected: 0x4000,
+            available: 0x3000
+        })
+    );
+    assert_eq!(
+        Rom::new(&with_trainer[..16 + 512 + 0x4000 + 0x1000].to_vec()).err(),
+        Some(RomError::TruncatedChr {
+            offset: 16 + 512 + 0x4000,
+            expected: 0x2000,
+            available: 0x1000
+        })
+    );
+
+    // an exponent-multiplier size far beyond any file
+    let mut raw = RomBuilder::new(vec![0; 0x100], Vec::new())
+        .nes2(true)
+        .to_ines();
+    raw[4] = 0xFC;
+    let err = Rom::new(&raw).err();
+    assert!(matches!(
+        err,
+        Some(RomError::TruncatedPrg { offset: 16, .. })
+    ));
+}
+
+#[test]
+fn test_overdumps_are_only_refused_in_strict_mode() {
+    let mut raw = RomBuilder::new(vec![0; 0x4000], vec![0; 0x2000]).to_ines();
+    raw.resize(16 + 0x6000 + 0x80, 0);
+    assert_eq!(
+        Rom::new_strict(&raw).err(),
+        Some(RomError::TrailingData {
+            offset: 16 + 0x6000,
+            len: 0x80
+        })
+    );
+
+    let rom = Rom::new(&raw).unwrap();
+    assert_eq!(rom.prg_rom.len(), 0x4000);
+    assert_eq!(rom.chr_rom.len(), 0x2000);
+}
+
+#[test]
+fn test_nes2_misc_roms_are_not_trailing_data() {
+    let mut raw = RomBuilder::new(vec![0; 0x8000], vec![0; 0x2000])
+        .nes2(true)
+        .to_ines();
+    raw[14] = 1;
+    raw.resize(16 + 0xA000 + 0x100, 0);
+    let rom = Rom::new_strict(&raw).unwrap();
+    assert_eq!(rom.misc_roms, 1);
+}
+
+#[test]
+fn test_trainer_is_kept_and_preloaded_at_7000() {
+    let mut trainer = [0; 512];
+    trainer[0] = 0x4C;
+    trainer[511] = 0x60;
+    let nrom = RomBuilder::new(vec![0xEA; 0x4000], vec![0; 0x2000]);
+    let raw = nrom.clone().trainer(trainer).to_ines();
+    assert_eq!(raw[6] & 0b100, 0b100);
+    let rom = Rom::new(&raw).unwrap();
+
+    let trainer = rom.trainer.as_ref().unwrap();
+    assert_eq!(trainer.len(), 512);
+    assert_eq!(rom.prg_rom[0], 0xEA);
+
+    let cart = mapper::new(rom);
+    assert_eq!(cart.read_prg(0x7000), 0x4C);
+    assert_eq!(cart.read_prg(0x71FF), 0x60);
+    assert_eq!(cart.read_prg(0x6000), 0);
+
+    assert!(Rom::new(&nrom.to_ines()).unwrap().trainer.is_none());
+}
+
+#[test]
+fn test_archaic_header_junk_is_ignored() {
+    let mut raw = RomBuilder::new(vec![0; 0x4000], vec![0; 0x2000])
+        .mapper(2)
+        .mirroring(Mirroring::VERTICAL)
+        .to_ines();
+    raw[7..16].copy_from_slice(b"DiskDude!");
+    let rom = Rom::new(&raw).unwrap();
+
+    assert!(rom.archaic);
+    assert!(!rom.nes2);
+    assert_eq!(rom.mapper, 2);
+    assert_eq!(rom.timing, Timing::NTSC);
+    assert_eq!(rom.console_type, ConsoleType::NES);
+    assert_eq!(rom.prg_ram_size, 0x2000);
+
+    // junk only in bytes 12-15 gives it away too
+    raw[7..16].copy_from_slice(&[0, 0, 0, 0, 0, 0xAA, 0xBB, 0xCC, 0xDD]);
+    assert!(Rom::new(&raw).unwrap().archaic);
+    raw[12..16].fill(0);
+    assert!(!Rom::new(&raw).unwrap().archaic);
+}
+
+#[test]
+fn test_builder_ines_round_trip() {
+    let mut trainer = [0; 512];
+    trainer[0] = 0x4C;
+    let builder = RomBuilder::new(vec![0xEA; 0x8000], vec![0x11; 0x4000])
+        .mapper(4)
+        .mirroring(Mirroring::VERTICAL)
+        .battery(true)
+        .trainer(trainer)
+        .prg_ram_size(0x4000)
+        .timing(Timing::PAL);
+    let raw = builder.to_ines();
+    assert_eq!(
+        raw[..16],
+        [0x4e, 0x45, 0x53, 0x1a, 2, 2, 0x47, 0, 2, 1, 0, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(raw.len(), 16 + 512 + 0x8000 + 0x4000);
+
+    let rom = Rom::new(&raw).unwrap();
+    assert_eq!(rom, builder.build().unwrap());
+    assert_eq!(rom.prg_ram_size, 0);
+    assert_eq!(rom.prg_nvram_size, 0x4000);
+    assert_eq!(rom.trainer.as_ref().unwrap()[0], 0x4C);
+    assert_eq!(Rom::new(&rom.to_ines()).unwrap(), rom);
+}
+
+#[test]
+fn test_builder_nes2_round_trip() {
+    let builder = RomBuilder::new(vec![0; 0x4000], Vec::new())
+        .nes2(true)
+        .mapper(23)
+        .submapper(2)
+        .mirroring(Mirroring::FOUR_SCREEN)
+        .vertical_mirroring_bit(true)
+        .prg_ram_size(0x2000)
+        .prg_nvram_size(0x800)
+        .chr_ram_size(0x4000)
+        .chr_nvram_size(0x2000)
+        .timing(Timing::DENDY)
+        .console_type(ConsoleType::VS_SYSTEM {
+            ppu: 3,
+            hardware: 5,
+        })
+        .expansion_device(8);
+    let raw = builder.to_ines();
+    let rom = Rom::new(&raw).unwrap();
+    assert_eq!(rom, builder.build().unwrap());
+    assert!(rom.nes2);
+    assert_eq!(rom.submapper, 2);
+    assert!(rom.vertical_mirroring_bit);
+    assert_eq!(rom.prg_nvram_size, 0x800);
+    assert_eq!(rom.chr_ram_size, 0x4000);
+    assert_eq!(rom.chr_nvram_size, 0x2000);
+    assert_eq!(rom.expansion_device, 8);
+
+    // sizes that aren't whole pages use the exponent-multiplier form
+    let builder = RomBuilder::new(vec![0; 3 * 0x2000], vec![0; 0x2000]).nes2(true);
+    let raw = builder.to_ines();
+    assert_eq!((raw[4], raw[9] & 0xF), ((13 << 2) | 1, 0xF));
+    assert_eq!(Rom::new(&raw).unwrap(), builder.build().unwrap());
+}
+
+#[test]
+fn test_builder_defaults_match_an_empty_header() {
+    let rom = RomBuilder::new(vec![0; 0x4000], Vec::new())
+        .build()
+        .unwrap();
+    let mut raw = vec![0x4e, 0x45, 0x53, 0x1a, 1, 0];
+    raw.resize(16 + 0x4000, 0);
+    assert_eq!(rom, Rom::new(&raw).unwrap());
+    assert_eq!(Rom::new(&rom.to_ines()).unwrap(), rom);
+}
+
+#[test]
+fn test_builder_refuses_what_rom_new_refuses() {
+    let builder = RomBuilder::new(vec![0; 0x4000], Vec::new()).mapper(252);
+    let err = Rom::new(&builder.to_ines()).err().unwrap();
+    assert_eq!(builder.build().err().unwrap(), err);
+
+    let builder = RomBuilder::new(vec![0; 0x4000], Vec::new())
+        .mapper(16)
+        .trainer([0; 512]);
+    assert_eq!(
+        builder.build().err().unwrap(),
+        RomError::UnsupportedTrainer { mapper: 16 }
+    );
+}
+
+#[test]
+fn test_test_rom() {
+    let mut bus = Bus::new(test_rom());
+    bus.mem_write(0x0010, 0x42);
+    assert_eq!(bus.mem_read(0x0010), 0x42);
+    assert_eq!(bus.mem_read(0x8000), 0);
+}
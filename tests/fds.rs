@@ -0,0 +1,238 @@
+use rust_NES::cartridge::{Mirroring, RomError};
+use rust_NES::fds;
+use rust_NES::mapper::{self, Mapper};
+
+const BYTE_CYCLES: usize = 150;
+const DISK_CHANGE_CYCLES: usize = 1_789_773;
+
+fn bios() -> Vec<u8> {
+    let mut bios = vec![0xEA; fds::BIOS_SIZE];
+    bios[0x1FFC] = 0x24;
+    bios[0x1FFD] = 0xEE;
+    bios
+}
+
+// A side holding one file of `file` bytes, the way it is stored in an .fds image.
+fn side(side_number: u8, file: &[u8]) -> Vec<u8> {
+    let mut side = vec![0x01];
+    side.extend_from_slice(b"*NINTENDO-HVC*");
+    side.resize(56, 0);
+    side[21] = side_number;
+    side.extend_from_slice(&[0x02, 1]);
+    let size = (file.len() as u16).to_le_bytes();
+    side.extend_from_slice(&[0x03, 0, 0]);
+    side.extend_from_slice(b"TESTFILE");
+    side.extend_from_slice(&[0x00, 0x60, size[0], size[1], 0]);
+    side.push(0x04);
+    side.extend_from_slice(file);
+    side.resize(fds::SIDE_SIZE, 0);
+    side
+}
+
+fn image(header: bool) -> Vec<u8> {
+    let mut image = Vec::new();
+    if header {
+        image.extend_from_slice(b"FDS\x1a\x02");
+        image.resize(16, 0);
+    }
+    image.extend(side(0, &[0x11, 0x22, 0x33]));
+    image.extend(side(1, &[0x44]));
+    image
+}
+
+fn disk_system() -> Box<dyn Mapper> {
+    mapper::new(fds::load(&bios(), &image(true)).unwrap())
+}
+
+fn clock_until_irq(cart: &mut Box<dyn Mapper>, limit: usize) -> usize {
+    for cycle in 0..limit {
+        if cart.irq() {
+            return cycle;
+        }
+        cart.clock_cpu();
+    }
+    panic!("no IRQ within {} cycles", limit);
+}
+
+#[test]
+fn test_load_with_and_without_header() {
+    let rom = fds::load(&bios(), &image(true)).unwrap();
+    assert_eq!(rom.mapper, 20);
+    assert_eq!(rom.disk_sides.len(), 2);
+    assert_eq!(rom.disk_sides[1][21], 1);
+
+    let headerless = fds::load(&bios(), &image(false)).unwrap();
+    assert_eq!(headerless.disk_sides, rom.disk_sides);
+
+    assert_eq!(
+        fds::load(&bios()[..0x1000], &image(true)).err(),
+        Some(RomError::BadBios { len: 0x1000 })
+    );
+    let mut truncated = image(false);
+    truncated.truncate(fds::SIDE_SIZE + 100);
+    assert_eq!(
+        fds::load(&bios(), &truncated).err(),
+        Some(RomError::TruncatedDiskSide {
+            side: 1,
+            offset: fds::SIDE_SIZE,
+            available: 100
+        })
+    );
+    assert!(matches!(
+        fds::load(&bios(), &[0; 100]),
+        Err(RomError::BadMagic { .. })
+    ));
+}
+
+#[test]
+fn test_ram_adapter_memory() {
+    let mut cart = disk_system();
+    assert_eq!(cart.read_prg(0xFFFC), 0x24);
+    assert_eq!(cart.read_prg(0xE000), 0xEA);
+
+    cart.write_prg(0x6000, 1);
+    cart.write_prg(0xDFFF, 2);
+    assert_eq!(cart.read_prg(0x6000), 1);
+    assert_eq!(cart.read_prg(0xDFFF), 2);
+    assert_eq!(cart.read_prg(0x8000), 0);
+
+    cart.write_chr(0x1234, 0x56);
+    assert_eq!(cart.read_chr(0x1234), 0x56);
+
+    cart.write_prg(0x4025, 0b0010_1000);
+    assert_eq!(cart.mirroring(), Mirroring::HORIZONTAL);
+    cart.write_prg(0x4025, 0b0010_0000);
+    assert_eq!(cart.mirroring(), Mirroring::VERTICAL);
+}
+
+#[test]
+fn test_timer_irq() {
+    let mut cart = disk_system();
+    cart.write_prg(0x4020, 100);
+    cart.write_prg(0x4021, 0);
+    cart.write_prg(0x4022, 0b11);
+
+    assert_eq!(clock_until_irq(&mut cart, 1000), 101);
+    assert_eq!(cart.read_prg(0x4030) & 1, 1);
+    assert!(!cart.irq());
+
+    // repeat mode reloads
+    assert_eq!(clock_until_irq(&mut cart, 1000), 101);
+    cart.read_prg(0x4030);
+
+    // disabling the disk registers stops it
+    cart.write_prg(0x4023, 0);
+    for _ in 0..1000 {
+        cart.clock_cpu();
+    }
+    assert!(!cart.irq());
+}
+
+#[test]
+fn test_reading_a_block() {
+    let mut cart = disk_system();
+    assert_eq!(cart.read_prg(0x4032) & 0b111, 0b010);
+
+    // motor on, read mode, look for a block, IRQ per byte
+    cart.write_prg(0x4025, 0b1110_0101);
+    clock_until_irq(&mut cart, 1_000_000);
+    assert_eq!(cart.read_prg(0x4032) & 0b111, 0b000);
+    assert_eq!(cart.read_prg(0x4031), 0x01);
+    assert!(!cart.irq());
+
+    let mut header = Vec::new();
+    for _ in 0..14 {
+        let cycles = clock_until_irq(&mut cart, 1000);
+        assert!(cycles <= BYTE_CYCLES + 1, "{}", cycles);
+        assert_eq!(cart.read_prg(0x4030) & 0b10, 0b10);
+        header.push(cart.read_prg(0x4031));
+    }
+    assert_eq!(header, b"*NINTENDO-HVC*");
+}
+
+#[test]
+fn test_writes_are_saved_with_the_disk() {
+    let mut cart = disk_system();
+    assert!(cart.battery_ram().is_none());
+
+    // motor on, write mode, data instead of gap, IRQ per byte
+    cart.write_prg(0x4024, 0xAB);
+    cart.write_prg(0x4025, 0b1110_0001);
+    clock_until_irq(&mut cart, 1_000_000);
+    cart.write_prg(0x4024, 0xCD);
+    clock_until_irq(&mut cart, 1000);
+
+    let saved = cart.battery_ram().unwrap().to_vec();
+    assert!(saved.windows(2).any(|bytes| bytes == [0xAB, 0xCD]));
+
+    let mut cart = disk_system();
+    cart.load_battery_ram(&saved);
+    assert_eq!(cart.battery_ram(), Some(&saved[..]));
+
+    // a save for a differently sized disk is ignored
+    let mut cart = disk_system();
+    cart.load_battery_ram(&saved[..100]);
+    assert!(cart.battery_ram().is_none());
+}
+
+#[test]
+fn test_switching_sides() {
+    let mut cart = disk_system();
+    assert_eq!(cart.disk_sides(), 2);
+
+    cart.insert_disk(None);
+    assert_eq!(cart.read_prg(0x4032) & 0b101, 0b101);
+
+    cart.insert_disk(Some(1));
+    for _ in 0..DISK_CHANGE_CYCLES - 1 {
+        cart.clock_cpu();
+    }
+    assert_eq!(cart.read_prg(0x4032) & 1, 1);
+    cart.clock_cpu();
+    assert_eq!(cart.read_prg(0x4032) & 1, 0);
+
+    // side 1's disk info block says so
+    cart.write_prg(0x4025, 0b0110_0101);
+    let mut bytes = Vec::new();
+    while bytes.len() < 23 {
+        cart.clock_cpu();
+        if cart.read_prg(0x4030) & 0b10 != 0 {
+            bytes.push(cart.read_prg(0x4031));
+        }
+    }
+    // without IRQs the start mark shows up too
+    assert_eq!(bytes[..2], [0x80, 0x01]);
+    assert_eq!(bytes[22], 1);
+}
+
+#[test]
+fn test_wavetable_audio() {
+    let mut cart = disk_system();
+    cart.write_prg(0x4089, 0x80);
+    for i in 0..64 {
+        cart.write_prg(0x4040 + i, if i < 32 { 63 } else { 0 });
+    }
+    assert_eq!(cart.read_prg(0x4040) & 0x3F, 63);
+    cart.write_prg(0x4089, 0);
+    // envelope off with a gain of 32, modulation halted
+    cart.write_prg(0x4080, 0x80 | 32);
+    cart.write_prg(0x4087, 0x80);
+    assert_eq!(cart.read_prg(0x4090) & 0x3F, 32);
+
+    cart.write_prg(0x4082, 0x00);
+    cart.write_prg(0x4083, 0x04);
+
+    let mut levels = Vec::new();
+    for _ in 0..0x2000 {
+        cart.clock_cpu();
+        levels.push(cart.audio_output());
+    }
+    let high = levels.iter().cloned().fold(0.0, f32::max);
+    assert!(high > 0.2, "{}", high);
+    assert!(levels.contains(&0.0));
+
+    // the sound registers can be switched off
+    cart.write_prg(0x4023, 0b01);
+    cart.write_prg(0x4080, 0x80);
+    assert_eq!(cart.read_prg(0x4090), 0);
+}
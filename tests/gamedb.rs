@@ -0,0 +1,162 @@
+use rust_NES::cartridge::{Mirroring, Rom, RomBuilder, Timing};
+use rust_NES::gamedb::{self, Entry, HeaderFix};
+use rust_NES::hash;
+
+fn crc32_of(raw: &[u8]) -> u32 {
+    hash::crc32(&raw[16..])
+}
+
+#[test]
+fn test_hashes() {
+    assert_eq!(hash::crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(
+        hash::crc32_update(hash::crc32(b"1234"), b"56789"),
+        0xCBF4_3926
+    );
+    assert_eq!(
+        hash::sha1(b"abc"),
+        [
+            0xa9, 0x99, 0x3e, 0x36, 0x47, 0x06, 0x81, 0x6a, 0xba, 0x3e, 0x25, 0x71, 0x78, 0x50,
+            0xc2, 0x6c, 0x9c, 0xd0, 0xd8, 0x9d
+        ]
+    );
+    assert_eq!(hash::sha1(&[b'a'; 1000])[..4], [0x29, 0x1e, 0x9a, 0x6c]);
+}
+
+#[test]
+fn test_parse_entries() {
+    let entries = gamedb::parse(
+        "# comment\n\
+         0123abcd - mapper=4 submapper=1 mirroring=vertical battery=yes timing=pal bus_conflicts=yes # name\n\
+         \n\
+         89abcdef a9993e364706816aba3e25717850c26c9cd0d89d mirroring=four\n",
+    )
+    .unwrap();
+    assert_eq!(
+        entries[0],
+        Entry {
+            crc32: 0x0123_ABCD,
+            sha1: None,
+            mapper: Some(4),
+            submapper: Some(1),
+            mirroring: Some(Mirroring::VERTICAL),
+            battery: Some(true),
+            timing: Some(Timing::PAL),
+            bus_conflicts: Some(true),
+        }
+    );
+    assert_eq!(entries[1].sha1, Some(hash::sha1(b"abc")));
+    assert_eq!(entries[1].mirroring, Some(Mirroring::FOUR_SCREEN));
+
+    assert!(gamedb::parse("0123abcd - mapper=four\n").is_err());
+    assert!(gamedb::parse("0123abcd\n").is_err());
+}
+
+#[test]
+fn test_shipped_database_parses() {
+    let entries = gamedb::parse(include_str!("../data/gamedb.txt")).unwrap();
+    for (i, entry) in entries.iter().enumerate() {
+        assert!(
+            entries[..i]
+                .iter()
+                .all(|other| (other.crc32, other.sha1) != (entry.crc32, entry.sha1)),
+            "duplicate entry for {:08x}",
+            entry.crc32
+        );
+    }
+}
+
+#[test]
+fn test_database_corrects_ines_header() {
+    // claims mapper 0, horizontal, no battery; really an MMC3 game with a battery
+    // each test fills PRG with its own byte to get its own hash
+    let raw = RomBuilder::new(vec![1; 0x4000], vec![0; 0x2000]).to_ines();
+    gamedb::register(Entry {
+        crc32: crc32_of(&raw),
+        mapper: Some(4),
+        mirroring: Some(Mirroring::HORIZONTAL),
+        battery: Some(true),
+        timing: Some(Timing::PAL),
+        ..Entry::default()
+    });
+
+    let rom = Rom::new(&raw).unwrap();
+    assert_eq!(rom.mapper, 4);
+    assert!(rom.battery);
+    assert_eq!(rom.prg_nvram_size, 0x2000);
+    assert_eq!(rom.prg_ram_size, 0);
+    assert_eq!(rom.timing, Timing::PAL);
+    // mirroring already matched, so it isn't reported
+    assert_eq!(
+        rom.header_fixes,
+        vec![
+            HeaderFix::Mapper {
+                header: 0,
+                database: 4
+            },
+            HeaderFix::Battery {
+                header: false,
+                database: true
+            },
+            HeaderFix::Timing {
+                header: Timing::NTSC,
+                database: Timing::PAL
+            },
+        ]
+    );
+    assert_eq!(rom.header_fixes[0].to_string(), "mapper 0 corrected to 4");
+}
+
+#[test]
+fn test_sha1_must_match_when_given() {
+    let raw = RomBuilder::new(vec![2; 0x4000], vec![0; 0x2000]).to_ines();
+    gamedb::register(Entry {
+        crc32: crc32_of(&raw),
+        sha1: Some([0; 20]),
+        mapper: Some(4),
+        ..Entry::default()
+    });
+    assert!(Rom::new(&raw).unwrap().header_fixes.is_empty());
+
+    gamedb::register(Entry {
+        crc32: crc32_of(&raw),
+        sha1: Some(hash::sha1(&raw[16..])),
+        mirroring: Some(Mirroring::VERTICAL),
+        ..Entry::default()
+    });
+    let rom = Rom::new(&raw).unwrap();
+    assert_eq!(rom.mapper, 0);
+    assert_eq!(rom.screen_mirroring, Mirroring::VERTICAL);
+}
+
+#[test]
+fn test_nes2_headers_are_left_alone() {
+    let raw = RomBuilder::new(vec![3; 0x4000], vec![0; 0x2000])
+        .nes2(true)
+        .to_ines();
+    gamedb::register(Entry {
+        crc32: crc32_of(&raw),
+        mapper: Some(4),
+        ..Entry::default()
+    });
+    let rom = Rom::new(&raw).unwrap();
+    assert_eq!(rom.mapper, 0);
+    assert!(rom.header_fixes.is_empty());
+}
+
+#[test]
+fn test_database_sets_bus_conflicts() {
+    let raw = RomBuilder::new(vec![4; 0x4000], vec![0; 0x2000])
+        .mapper(2)
+        .to_ines();
+    assert!(!Rom::new(&raw).unwrap().bus_conflicts);
+    gamedb::register(Entry {
+        crc32: crc32_of(&raw),
+        bus_conflicts: Some(true),
+        ..Entry::default()
+    });
+    let rom = Rom::new(&raw).unwrap();
+    assert_eq!(rom.mapper, 2);
+    assert!(rom.bus_conflicts);
+    assert!(rom.header_fixes.is_empty());
+}
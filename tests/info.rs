@@ -0,0 +1,82 @@
+use rust_NES::cartridge::{Mirroring, RomBuilder, RomError};
+use rust_NES::hash;
+use rust_NES::info::{json_string, Format, RomInfo};
+
+#[test]
+fn test_formats() {
+    let mmc3 = RomBuilder::new(vec![0x5A; 0x4000], vec![0; 0x2000]).mapper(4);
+    assert_eq!(RomInfo::new(&mmc3.to_ines()).unwrap().format, Format::INES);
+    assert_eq!(
+        RomInfo::new(&mmc3.clone().nes2(true).to_ines())
+            .unwrap()
+            .format,
+        Format::NES2
+    );
+
+    let mut archaic = mmc3.to_ines();
+    archaic[7..16].copy_from_slice(b"DiskDude!");
+    let info = RomInfo::new(&archaic).unwrap();
+    assert_eq!(info.format, Format::ARCHAIC_INES);
+    assert_eq!(info.rom.mapper, 4);
+    assert_eq!(info.format.to_string(), "archaic iNES");
+}
+
+#[test]
+fn test_text_output() {
+    let raw = RomBuilder::new(vec![0x5A; 0x4000], vec![0; 0x2000])
+        .mapper(4)
+        .battery(true)
+        .to_ines();
+    let info = RomInfo::new(&raw).unwrap();
+    assert_eq!(info.file_crc32, hash::crc32(&raw));
+    assert_eq!(info.crc32, hash::crc32(&raw[16..]));
+    assert_eq!(info.sha1, hash::sha1(&raw[16..]));
+
+    let text = info.to_string();
+    assert!(text.contains("Format:           iNES\n"), "{}", text);
+    assert!(
+        text.contains("Mapper:           4 (MMC3/TxROM)\n"),
+        "{}",
+        text
+    );
+    assert!(text.contains("PRG-ROM:          16 KiB\n"), "{}", text);
+    assert!(text.contains("CHR-RAM:          none\n"), "{}", text);
+    assert!(text.contains("Battery:          yes\n"), "{}", text);
+    assert!(text.contains(&format!("ROM CRC32:        {:08x}\n", info.crc32)));
+    assert!(
+        text.ends_with("Database:         no corrections"),
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn test_json_output() {
+    let raw = RomBuilder::new(vec![0x5A; 0x4000], vec![0; 0x2000])
+        .mirroring(Mirroring::VERTICAL)
+        .to_ines();
+    let info = RomInfo::new(&raw).unwrap();
+    let json = info.to_json();
+    assert!(json.starts_with("{\"format\":\"iNES\",\"mapper\":0,\"mapper_name\":\"NROM\","));
+    assert!(json.contains("\"prg_rom_size\":16384,"), "{}", json);
+    assert!(json.contains("\"mirroring\":\"VERTICAL\","), "{}", json);
+    assert!(json.contains("\"battery\":false,"), "{}", json);
+    assert!(json.contains(&format!("\"crc32\":\"{:08x}\",", info.crc32)));
+    assert!(json.ends_with("\"header_fixes\":[]}"), "{}", json);
+    assert!(!json.contains('\n'));
+
+    // the same errors as loading the ROM
+    assert!(matches!(
+        RomInfo::new(
+            &RomBuilder::new(vec![0x5A; 0x4000], vec![0; 0x2000])
+                .mapper(255)
+                .to_ines()
+        ),
+        Err(RomError::UnsupportedMapper { mapper: 255, .. })
+    ));
+}
+
+#[test]
+fn test_json_string() {
+    assert_eq!(json_string("a\"b\\c\n\u{1}é"), "\"a\\\"b\\\\c\\n\\u0001é\"");
+}
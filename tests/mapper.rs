@@ -0,0 +1,1139 @@
+use rust_NES::bus::Bus;
+use rust_NES::cartridge::{Mirroring, Rom, RomBuilder, RomError};
+use rust_NES::cpu::Mem;
+use rust_NES::mapper::{self, Mapper, Nametable};
+use rust_NES::opll::{self, Opll};
+
+const A12_FILTER_CYCLES: usize = 3;
+
+// A cartridge with `prg_banks` 16K and `chr_banks` 8K banks, where every byte of
+// PRG holds the number of the 8K bank it sits in and every byte of CHR holds the
+// number of its 1K bank with the high bit set.
+fn builder(mapper: u16, prg_banks: u8, chr_banks: u8) -> RomBuilder {
+    let prg_rom = (0..prg_banks * 2)
+        .flat_map(|bank| std::iter::repeat_n(bank, 0x2000))
+        .collect();
+    let chr_rom = (0..chr_banks * 8)
+        .flat_map(|bank| std::iter::repeat_n(0x80 | bank, 0x0400))
+        .collect();
+    RomBuilder::new(prg_rom, chr_rom).mapper(mapper)
+}
+
+#[test]
+fn test_uxrom_switches_low_bank_and_fixes_last() {
+    let mut cart = mapper::new(Rom::new(&builder(2, 8, 0).to_ines()).unwrap());
+    assert_eq!(cart.read_prg(0x8000), 0);
+    assert_eq!(cart.read_prg(0xC000), 14);
+
+    cart.write_prg(0x8000, 5);
+    assert_eq!(cart.read_prg(0x8000), 10);
+    assert_eq!(cart.read_prg(0xBFFF), 11);
+    assert_eq!(cart.read_prg(0xFFFF), 15);
+}
+
+#[test]
+fn test_uxrom_bus_conflicts() {
+    // NES 2.0 submapper 2 declares them
+    let raw = builder(2, 8, 0).nes2(true).submapper(2).to_ines();
+    let rom = Rom::new(&raw).unwrap();
+    assert!(rom.bus_conflicts);
+    let mut cart = mapper::new(rom);
+
+    // ROM at $C000 reads back 14, so 0b0111 & 0b1110 lands on bank 6
+    cart.write_prg(0xC000, 0b0111);
+    assert_eq!(cart.read_prg(0x8000), 12);
+}
+
+#[test]
+fn test_cnrom_switches_chr() {
+    let mut cart = mapper::new(Rom::new(&builder(3, 2, 4).to_ines()).unwrap());
+    assert_eq!(cart.read_chr(0x0000), 0x80);
+
+    cart.write_prg(0x8000, 3);
+    assert_eq!(cart.read_chr(0x0000), 0x80 | 24);
+    assert_eq!(cart.read_chr(0x1FFF), 0x80 | 31);
+    assert_eq!(cart.read_prg(0xC000), 2);
+}
+
+#[test]
+fn test_chr_ram_is_writable() {
+    let mut cart = mapper::new(Rom::new(&builder(2, 8, 0).to_ines()).unwrap());
+    cart.write_chr(0x0000, 0x12);
+    cart.write_chr(0x1FFF, 0x34);
+    assert_eq!(cart.read_chr(0x0000), 0x12);
+    assert_eq!(cart.read_chr(0x1FFF), 0x34);
+
+    // CHR-ROM stays as it is
+    let mut cart = mapper::new(Rom::new(&builder(3, 2, 4).to_ines()).unwrap());
+    cart.write_chr(0x0000, 0x12);
+    assert_eq!(cart.read_chr(0x0000), 0x80);
+}
+
+#[test]
+fn test_chr_ram_size_from_nes2_header_is_bankable() {
+    // CNROM with 32K of CHR-RAM
+    let raw = builder(3, 2, 0).nes2(true).chr_ram_size(0x8000).to_ines();
+    let mut cart = mapper::new(Rom::new(&raw).unwrap());
+
+    for bank in 0..4 {
+        cart.write_prg(0x8000, bank);
+        cart.write_chr(0x0010, 0x40 | bank);
+    }
+    for bank in 0..4 {
+        cart.write_prg(0x8000, bank);
+        assert_eq!(cart.read_chr(0x0010), 0x40 | bank);
+    }
+}
+
+#[test]
+fn test_axrom_switches_prg_and_single_screen() {
+    let mut cart = mapper::new(Rom::new(&builder(7, 8, 0).to_ines()).unwrap());
+    assert_eq!(cart.mirroring(), Mirroring::SINGLE_SCREEN_LOWER);
+
+    cart.write_prg(0x8000, 0b1_0010);
+    assert_eq!(cart.read_prg(0x8000), 8);
+    assert_eq!(cart.read_prg(0xE000), 11);
+    assert_eq!(cart.mirroring(), Mirroring::SINGLE_SCREEN_UPPER);
+}
+
+#[test]
+fn test_mmc3_prg_modes() {
+    let mut cart = mapper::new(Rom::new(&builder(4, 8, 8).to_ines()).unwrap());
+    cart.write_prg(0x8000, 6);
+    cart.write_prg(0x8001, 3);
+    cart.write_prg(0x8000, 7);
+    cart.write_prg(0x8001, 5);
+    assert_eq!(cart.read_prg(0x8000), 3);
+    assert_eq!(cart.read_prg(0xA000), 5);
+    assert_eq!(cart.read_prg(0xC000), 14);
+    assert_eq!(cart.read_prg(0xE000), 15);
+
+    cart.write_prg(0x8000, 0b0100_0000);
+    assert_eq!(cart.read_prg(0x8000), 14);
+    assert_eq!(cart.read_prg(0xC000), 3);
+}
+
+#[test]
+fn test_mmc3_chr_inversion() {
+    let mut cart = mapper::new(Rom::new(&builder(4, 8, 8).to_ines()).unwrap());
+    cart.write_prg(0x8000, 0);
+    cart.write_prg(0x8001, 9);
+    cart.write_prg(0x8000, 2);
+    cart.write_prg(0x8001, 20);
+    assert_eq!(cart.read_chr(0x0000), 0x80 | 8);
+    assert_eq!(cart.read_chr(0x0400), 0x80 | 9);
+    assert_eq!(cart.read_chr(0x1000), 0x80 | 20);
+
+    cart.write_prg(0x8000, 0b1000_0000);
+    assert_eq!(cart.read_chr(0x1000), 0x80 | 8);
+    assert_eq!(cart.read_chr(0x0000), 0x80 | 20);
+}
+
+#[test]
+fn test_mmc3_scanline_irq() {
+    let mut cart = mapper::new(Rom::new(&builder(4, 8, 8).to_ines()).unwrap());
+    cart.write_prg(0xC000, 2);
+    cart.write_prg(0xC001, 0);
+    cart.write_prg(0xE001, 0);
+
+    let scanline = |cart: &mut Box<dyn Mapper>| {
+        cart.read_chr(0x0000);
+        for _ in 0..A12_FILTER_CYCLES {
+            cart.clock_cpu();
+        }
+        cart.read_chr(0x1000);
+    };
+
+    scanline(&mut cart); // reload to 2
+    scanline(&mut cart); // 1
+    assert!(!cart.irq());
+    scanline(&mut cart); // 0
+    assert!(cart.irq());
+
+    cart.write_prg(0xE000, 0);
+    assert!(!cart.irq());
+}
+
+#[test]
+fn test_mmc3_scanline_irq_through_the_bus() {
+    let mut bus = Bus::new(Rom::new(&builder(4, 8, 8).to_ines()).unwrap());
+    bus.mem_write(0xC000, 1);
+    bus.mem_write(0xC001, 0);
+    bus.mem_write(0xE001, 0);
+
+    for _ in 0..2 {
+        bus.ppu_read(0x0000);
+        bus.tick(A12_FILTER_CYCLES as u8);
+        bus.ppu_address(0x1000);
+    }
+    assert!(bus.poll_irq());
+}
+
+#[test]
+fn test_mmc3_a12_filter_ignores_short_pulses() {
+    let mut cart = mapper::new(Rom::new(&builder(4, 8, 8).to_ines()).unwrap());
+    cart.write_prg(0xC000, 0);
+    cart.write_prg(0xE001, 0);
+
+    cart.read_chr(0x0000);
+    cart.read_chr(0x1000);
+    assert!(!cart.irq());
+}
+
+#[test]
+fn test_mmc2_latches_switch_on_tile_fetch() {
+    let mut cart = mapper::new(Rom::new(&builder(9, 8, 16).to_ines()).unwrap());
+    cart.write_prg(0xB000, 1); // left table, $FD
+    cart.write_prg(0xC000, 2); // left table, $FE
+    cart.write_prg(0xD000, 3); // right table, $FD
+    cart.write_prg(0xE000, 4); // right table, $FE
+    assert_eq!(cart.read_chr(0x0000), 0x80 | 8);
+
+    // the fetch that trips the latch still comes from the old bank
+    assert_eq!(cart.read_chr(0x0FD8), 0x80 | 11);
+    assert_eq!(cart.read_chr(0x0000), 0x80 | 4);
+    // MMC2 only reacts to the first row of the tile on the left table
+    cart.read_chr(0x0FE9);
+    assert_eq!(cart.read_chr(0x0000), 0x80 | 4);
+
+    cart.read_chr(0x1FDB);
+    assert_eq!(cart.read_chr(0x1000), 0x80 | 12);
+    assert_eq!(cart.read_chr(0x0000), 0x80 | 4);
+}
+
+#[test]
+fn test_mmc2_and_mmc4_latches_through_the_bus() {
+    for number in [9, 10] {
+        let mut bus = Bus::new(Rom::new(&builder(number, 8, 16).to_ines()).unwrap());
+        bus.mem_write(0xB000, 1);
+        bus.mem_write(0xC000, 2);
+        assert_eq!(bus.ppu_read(0x0000), 0x80 | 8, "mapper {}", number);
+        bus.ppu_read(0x0FD8);
+        assert_eq!(bus.ppu_read(0x0000), 0x80 | 4, "mapper {}", number);
+    }
+}
+
+#[test]
+fn test_mmc2_and_mmc4_chr_ram() {
+    for number in [9, 10] {
+        let mut cart = mapper::new(Rom::new(&builder(number, 8, 0).to_ines()).unwrap());
+        cart.write_prg(0xB000, 1);
+        cart.write_prg(0xC000, 1);
+        cart.write_chr(0x0010, 0x5A);
+        assert_eq!(cart.read_chr(0x0010), 0x5A);
+        // the left table starts on its $FE bank, bank 1
+        cart.write_prg(0xC000, 0);
+        assert_eq!(cart.read_chr(0x0010), 0);
+    }
+}
+
+#[test]
+fn test_mmc2_prg_fixes_last_three_banks() {
+    let mut cart = mapper::new(Rom::new(&builder(9, 8, 16).to_ines()).unwrap());
+    cart.write_prg(0xA000, 5);
+    assert_eq!(cart.read_prg(0x8000), 5);
+    assert_eq!(cart.read_prg(0xA000), 13);
+    assert_eq!(cart.read_prg(0xC000), 14);
+    assert_eq!(cart.read_prg(0xE000), 15);
+}
+
+#[test]
+fn test_vrc4_address_wiring_by_submapper() {
+    // VRC4c selects registers with A6/A7 instead of A1/A2
+    let mut rom = Rom::new(&builder(21, 8, 16).to_ines()).unwrap();
+    rom.submapper = 2;
+    let mut cart = mapper::new(rom);
+    cart.write_prg(0xB000, 0x05);
+    cart.write_prg(0xB040, 0x01);
+    assert_eq!(cart.read_chr(0x0000), 0x80 | 0x15);
+
+    cart.write_prg(0x9080, 0b10);
+    cart.write_prg(0x8000, 3);
+    assert_eq!(cart.read_prg(0xC000), 3);
+    assert_eq!(cart.read_prg(0x8000), 14);
+}
+
+#[test]
+fn test_vrc2a_drops_low_chr_bit() {
+    let mut cart = mapper::new(Rom::new(&builder(22, 8, 16).to_ines()).unwrap());
+    cart.write_prg(0xC000, 0x07);
+    assert_eq!(cart.read_chr(0x0800), 0x80 | 3);
+}
+
+#[test]
+fn test_vrc4_cycle_mode_irq() {
+    let mut rom = Rom::new(&builder(25, 8, 16).to_ines()).unwrap();
+    rom.submapper = 1;
+    let mut cart = mapper::new(rom);
+    cart.write_prg(0xF000, 0x0D);
+    cart.write_prg(0xF002, 0x0F);
+    cart.write_prg(0xF001, 0b110);
+
+    for _ in 0..2 {
+        cart.clock_cpu();
+    }
+    assert!(!cart.irq());
+    cart.clock_cpu();
+    assert!(cart.irq());
+
+    cart.write_prg(0xF003, 0);
+    assert!(!cart.irq());
+}
+
+#[test]
+fn test_vrc6b_swaps_register_lines() {
+    let mut cart = mapper::new(Rom::new(&builder(26, 8, 16).to_ines()).unwrap());
+    // $D002 on VRC6b is CHR bank 1
+    cart.write_prg(0xD002, 9);
+    assert_eq!(cart.read_chr(0x0400), 0x80 | 9);
+}
+
+#[test]
+fn test_vrc6_pulse_ignoring_duty_outputs_volume() {
+    let mut cart = mapper::new(Rom::new(&builder(24, 8, 16).to_ines()).unwrap());
+    assert_eq!(cart.audio_output(), 0.0);
+
+    cart.write_prg(0x9000, 0b1000_1111);
+    cart.write_prg(0x9002, 0b1000_0000);
+    cart.clock_cpu();
+    assert!(cart.audio_output() > 0.0);
+
+    cart.write_prg(0x9002, 0);
+    assert_eq!(cart.audio_output(), 0.0);
+}
+
+#[test]
+fn test_vrc7_keyed_channel_produces_sound() {
+    let mut cart = mapper::new(Rom::new(&builder(85, 8, 16).to_ines()).unwrap());
+    let mut write_audio = |register: u8, data: u8| {
+        cart.write_prg(0x9010, register);
+        cart.write_prg(0x9030, data);
+    };
+    // flute at full volume, F-Number 0x100, block 4, key on
+    write_audio(0x30, 0x40);
+    write_audio(0x10, 0x00);
+    write_audio(0x20, 0b1_1001);
+
+    let mut peak: f32 = 0.0;
+    for _ in 0..36 * 1000 {
+        cart.clock_cpu();
+        peak = peak.max(cart.audio_output().abs());
+    }
+    assert!(peak > 0.01);
+
+    // $E000 bit 6 silences and resets the synthesizer
+    cart.write_prg(0xE000, 0b0100_0000);
+    assert_eq!(cart.audio_output(), 0.0);
+}
+
+#[test]
+fn test_opll_tremolo_keeps_running_on_long_renders() {
+    let mut opll = Opll::new();
+    // user patch: sustained sine, tremolo on the carrier, modulator silenced
+    for (register, data) in [0x21, 0xA1, 0x3F, 0, 0xF0, 0xF0, 0, 0]
+        .into_iter()
+        .enumerate()
+    {
+        opll.write_register(register as u8, data);
+    }
+    opll.write_register(0x30, 0x00);
+    opll.write_register(0x10, 0x80);
+    opll.write_register(0x20, 0b1_1000);
+
+    for _ in 0..(opll::SAMPLE_RATE * 600.0) as usize {
+        opll.clock();
+    }
+    // peaks of 20 ms windows across a tremolo cycle vary by its 4.8 dB depth
+    let peaks: Vec<f32> = (0..15)
+        .map(|_| (0..1000).map(|_| opll.clock().abs()).fold(0.0, f32::max))
+        .collect();
+    let loudest = peaks.iter().cloned().fold(0.0, f32::max);
+    let quietest = peaks.iter().cloned().fold(f32::MAX, f32::min);
+    assert!(quietest > 0.0);
+    assert!(loudest / quietest > 1.4, "{:?}", peaks);
+}
+
+// Feeds one scanline's worth of PPU fetches: the two dummy nametable reads that
+// end the previous line, 32 background tiles, 8 sprites and the 2 tiles fetched
+// ahead for the next line.
+fn render_scanline(cart: &mut Box<dyn Mapper>) {
+    cart.read_nametable(0x2000);
+    cart.read_nametable(0x2000);
+    for tile in 0..32 {
+        cart.read_nametable(0x2000 + tile);
+        cart.read_nametable(0x23C0);
+        cart.read_chr(0x0000);
+        cart.read_chr(0x0008);
+    }
+    for _ in 0..8 {
+        cart.read_nametable(0x2000);
+        cart.read_nametable(0x2000);
+        cart.read_chr(0x1000);
+        cart.read_chr(0x1008);
+    }
+    for tile in 0..2 {
+        cart.read_nametable(0x2000 + tile);
+        cart.read_nametable(0x23C0);
+        cart.read_chr(0x0000);
+        cart.read_chr(0x0008);
+    }
+}
+
+#[test]
+fn test_mmc5_prg_modes() {
+    let mut cart = mapper::new(Rom::new(&builder(5, 8, 16).to_ines()).unwrap());
+    // power on: mode 3 with $5117 = $FF
+    assert_eq!(cart.read_prg(0xE000), 15);
+
+    cart.write_prg(0x5100, 1);
+    cart.write_prg(0x5115, 0x80 | 5);
+    cart.write_prg(0x5117, 3);
+    assert_eq!(cart.read_prg(0x8000), 4);
+    assert_eq!(cart.read_prg(0xA000), 5);
+    assert_eq!(cart.read_prg(0xC000), 2);
+    assert_eq!(cart.read_prg(0xE000), 3);
+}
+
+#[test]
+fn test_mmc5_prg_ram_protect() {
+    let mut cart = mapper::new(Rom::new(&builder(5, 8, 16).to_ines()).unwrap());
+    cart.write_prg(0x6000, 0x42);
+    assert_eq!(cart.read_prg(0x6000), 0);
+
+    cart.write_prg(0x5102, 0b10);
+    cart.write_prg(0x5103, 0b01);
+    cart.write_prg(0x6000, 0x42);
+    assert_eq!(cart.read_prg(0x6000), 0x42);
+
+    // RAM bank 0 mapped at $8000 as well
+    cart.write_prg(0x5114, 0);
+    assert_eq!(cart.read_prg(0x8000), 0x42);
+}
+
+#[test]
+fn test_mmc5_prg_ram_size_follows_the_header() {
+    let battery_ram_len = |builder: RomBuilder| {
+        let cart = mapper::new(Rom::new(&builder.battery(true).to_ines()).unwrap());
+        cart.battery_ram().unwrap().len()
+    };
+    let nes2 = builder(5, 8, 16).nes2(true);
+    assert_eq!(battery_ram_len(nes2.clone()), 0x2000);
+    assert_eq!(battery_ram_len(nes2.prg_nvram_size(0x8000)), 0x8000);
+    assert_eq!(
+        battery_ram_len(builder(5, 8, 16).prg_nvram_size(0x8000)),
+        0x8000
+    );
+    // iNES headers mostly leave it out
+    assert_eq!(battery_ram_len(builder(5, 8, 16)), 0x10000);
+}
+
+#[test]
+fn test_mmc5_multiplier() {
+    let mut cart = mapper::new(Rom::new(&builder(5, 8, 16).to_ines()).unwrap());
+    cart.write_prg(0x5205, 200);
+    cart.write_prg(0x5206, 100);
+    assert_eq!(cart.read_prg(0x5205), (20000 & 0xFF) as u8);
+    assert_eq!(cart.read_prg(0x5206), (20000 >> 8) as u8);
+}
+
+#[test]
+fn test_mmc5_fill_mode_and_exram_nametables() {
+    let mut cart = mapper::new(Rom::new(&builder(5, 8, 16).to_ines()).unwrap());
+    // quadrant 0 from CIRAM page 1, quadrant 1 from ExRAM, quadrants 2-3 fill mode
+    cart.write_prg(0x5105, 0b11_11_10_01);
+    cart.write_prg(0x5106, 0x24);
+    cart.write_prg(0x5107, 0b10);
+
+    assert_eq!(cart.read_nametable(0x2000), Nametable::Vram(1));
+    assert_eq!(cart.write_nametable(0x2400, 0x17), None);
+    assert_eq!(cart.read_nametable(0x2400), Nametable::Data(0x17));
+    assert_eq!(cart.read_nametable(0x2800), Nametable::Data(0x24));
+    assert_eq!(cart.read_nametable(0x2BC0), Nametable::Data(0xAA));
+}
+
+#[test]
+fn test_mmc5_nametables_through_the_bus() {
+    let mut bus = Bus::new(Rom::new(&builder(5, 8, 16).to_ines()).unwrap());
+    bus.mem_write(0x5104, 0b00);
+    // quadrant 0 from CIRAM page 1, quadrant 1 from ExRAM, quadrants 2-3 fill mode
+    bus.mem_write(0x5105, 0b11_11_10_01);
+    bus.mem_write(0x5106, 0x24);
+
+    bus.ppu_write(0x2000, 0x11);
+    assert_eq!(bus.ppu_read(0x2000), 0x11);
+    bus.ppu_write(0x2400, 0x17);
+    assert_eq!(bus.ppu_read(0x2400), 0x17);
+    assert_eq!(bus.ppu_read(0x2800), 0x24);
+
+    // three fetches of the same nametable address start a frame
+    for _ in 0..3 {
+        bus.ppu_read(0x2000);
+    }
+    assert_eq!(bus.mem_read(0x5204) & 0b0100_0000, 0b0100_0000);
+}
+
+#[test]
+fn test_mmc5_scanline_irq() {
+    let mut cart = mapper::new(Rom::new(&builder(5, 8, 16).to_ines()).unwrap());
+    cart.write_prg(0x5203, 2);
+    cart.write_prg(0x5204, 0x80);
+
+    render_scanline(&mut cart);
+    render_scanline(&mut cart);
+    assert!(!cart.irq());
+    render_scanline(&mut cart);
+    assert!(cart.irq());
+
+    assert_eq!(cart.read_prg(0x5204), 0b1100_0000);
+    assert!(!cart.irq());
+
+    // the PPU going quiet ends the frame
+    for _ in 0..3 {
+        cart.clock_cpu();
+    }
+    assert_eq!(cart.read_prg(0x5204), 0);
+}
+
+#[test]
+fn test_mmc5_tall_sprites_use_separate_chr_sets() {
+    let mut cart = mapper::new(Rom::new(&builder(5, 8, 16).to_ines()).unwrap());
+    cart.write_prg(0x5101, 3);
+    cart.write_prg(0x5124, 5);
+    cart.write_prg(0x5128, 9);
+    cart.notify_cpu_write(0x2000, 0b10_0000);
+    cart.notify_cpu_write(0x2001, 0b1_1000);
+
+    render_scanline(&mut cart);
+    // background tile
+    cart.read_nametable(0x2000);
+    cart.read_nametable(0x2000);
+    cart.read_nametable(0x2000);
+    assert_eq!(cart.read_chr(0x1000), 0x80 | 9);
+    for _ in 0..63 {
+        cart.read_chr(0x0000);
+    }
+    // first sprite fetch
+    assert_eq!(cart.read_chr(0x1000), 0x80 | 5);
+}
+
+#[test]
+fn test_mmc5_chr_ram() {
+    // CHR-RAM ExROM boards like Koei's have no CHR-ROM at all
+    let mut cart = mapper::new(Rom::new(&builder(5, 8, 0).to_ines()).unwrap());
+    cart.write_chr(0x0123, 0x5A);
+    assert_eq!(cart.read_chr(0x0123), 0x5A);
+
+    // 1K banks 0 and 1 of the 8K
+    cart.write_prg(0x5101, 3);
+    cart.write_prg(0x5121, 0);
+    cart.write_chr(0x0400, 0xA5);
+    assert_eq!(cart.read_chr(0x0000), 0xA5);
+}
+
+#[test]
+fn test_fme7_banking() {
+    let mut cart = mapper::new(Rom::new(&builder(69, 8, 16).to_ines()).unwrap());
+    cart.write_prg(0x8000, 0x9);
+    cart.write_prg(0xA000, 5);
+    cart.write_prg(0x8000, 0xB);
+    cart.write_prg(0xA000, 7);
+    cart.write_prg(0x8000, 0x3);
+    cart.write_prg(0xA000, 0x22);
+    assert_eq!(cart.read_prg(0x8000), 5);
+    assert_eq!(cart.read_prg(0xC000), 7);
+    assert_eq!(cart.read_prg(0xE000), 15);
+    assert_eq!(cart.read_chr(0x0C00), 0x80 | 0x22);
+
+    // $6000 maps ROM until RAM is selected and enabled
+    cart.write_prg(0x8000, 0x8);
+    cart.write_prg(0xA000, 2);
+    assert_eq!(cart.read_prg(0x6000), 2);
+    cart.write_prg(0xA000, 0b1100_0000);
+    cart.write_prg(0x6000, 0x42);
+    assert_eq!(cart.read_prg(0x6000), 0x42);
+}
+
+#[test]
+fn test_fme7_cycle_irq() {
+    let mut cart = mapper::new(Rom::new(&builder(69, 8, 16).to_ines()).unwrap());
+    cart.write_prg(0x8000, 0xE);
+    cart.write_prg(0xA000, 10);
+    cart.write_prg(0x8000, 0xF);
+    cart.write_prg(0xA000, 0);
+    cart.write_prg(0x8000, 0xD);
+    cart.write_prg(0xA000, 0b1000_0001);
+
+    for _ in 0..10 {
+        cart.clock_cpu();
+    }
+    assert!(!cart.irq());
+    cart.clock_cpu();
+    assert!(cart.irq());
+
+    // writing the control register acknowledges
+    cart.write_prg(0xA000, 0b1000_0001);
+    assert!(!cart.irq());
+}
+
+#[test]
+fn test_sunsoft_5b_tone() {
+    let mut cart = mapper::new(Rom::new(&builder(69, 8, 16).to_ines()).unwrap());
+    let mut write = |register: u8, data: u8| {
+        cart.write_prg(0xC000, register);
+        cart.write_prg(0xE000, data);
+    };
+    write(0x00, 4);
+    write(0x07, 0b11_1110);
+    write(0x08, 0x0F);
+
+    let mut levels = Vec::new();
+    for _ in 0..16 * 16 {
+        cart.clock_cpu();
+        levels.push(cart.audio_output());
+    }
+    let loudest = levels.iter().cloned().fold(0.0, f32::max);
+    assert!(loudest > 0.0);
+    assert!(levels.contains(&0.0));
+}
+
+#[test]
+fn test_namco163_banking_and_nametables() {
+    let mut cart = mapper::new(Rom::new(&builder(19, 8, 16).to_ines()).unwrap());
+    cart.write_prg(0xE000, 3);
+    cart.write_prg(0xE800, 4);
+    cart.write_prg(0xF000, 5);
+    assert_eq!(cart.read_prg(0x8000), 3);
+    assert_eq!(cart.read_prg(0xA000), 4);
+    assert_eq!(cart.read_prg(0xC000), 5);
+    assert_eq!(cart.read_prg(0xE000), 15);
+
+    cart.write_prg(0xB800, 0x12);
+    assert_eq!(cart.read_chr(0x1C00), 0x80 | 0x12);
+
+    cart.write_prg(0xC000, 0xE0);
+    cart.write_prg(0xC800, 0xE1);
+    cart.write_prg(0xD000, 0x07);
+    assert_eq!(cart.read_nametable(0x2000), Nametable::Vram(0));
+    assert_eq!(cart.read_nametable(0x2400), Nametable::Vram(1));
+    assert_eq!(cart.read_nametable(0x2800), Nametable::Data(0x80 | 0x07));
+    assert_eq!(cart.write_nametable(0x2800, 0), None);
+}
+
+#[test]
+fn test_namco163_chr_ram() {
+    let mut cart = mapper::new(Rom::new(&builder(19, 8, 0).to_ines()).unwrap());
+    cart.write_prg(0x8000, 1);
+    cart.write_chr(0x0000, 0x5A);
+    assert_eq!(cart.read_chr(0x0000), 0x5A);
+
+    // nametables can point at the CHR-RAM too, and write through to it
+    cart.write_prg(0xC000, 1);
+    assert_eq!(cart.read_nametable(0x2000), Nametable::Data(0x5A));
+    assert_eq!(cart.write_nametable(0x2001, 0xA5), None);
+    assert_eq!(cart.read_chr(0x0001), 0xA5);
+}
+
+#[test]
+fn test_namco163_irq() {
+    let mut cart = mapper::new(Rom::new(&builder(19, 8, 16).to_ines()).unwrap());
+    cart.write_prg(0x5000, 0xFD);
+    cart.write_prg(0x5800, 0x80 | 0x7F);
+    cart.clock_cpu();
+    assert!(!cart.irq());
+    cart.clock_cpu();
+    assert!(cart.irq());
+    // the counter stops at $7FFF
+    cart.clock_cpu();
+    assert_eq!(cart.read_prg(0x5000), 0xFF);
+
+    cart.write_prg(0x5800, 0x7F);
+    assert!(!cart.irq());
+}
+
+#[test]
+fn test_namco163_internal_ram_is_battery_backed() {
+    let raw = builder(19, 8, 16).battery(true).to_ines();
+    let mut cart = mapper::new(Rom::new(&raw).unwrap());
+    cart.write_prg(0xF800, 0x80 | 0x10);
+    cart.write_prg(0x4800, 0xAB);
+    cart.write_prg(0x4800, 0xCD);
+    cart.write_prg(0xF800, 0x11);
+    assert_eq!(cart.read_prg(0x4800), 0xCD);
+
+    // PRG-RAM writes need $4x in $F800
+    cart.write_prg(0x6000, 0x42);
+    assert_eq!(cart.read_prg(0x6000), 0);
+    cart.write_prg(0xF800, 0x40);
+    cart.write_prg(0x6000, 0x42);
+
+    let save = cart.battery_ram().unwrap().to_vec();
+    assert_eq!(save[0], 0x42);
+    assert_eq!(save[0x2000 + 0x10..0x2000 + 0x12], [0xAB, 0xCD]);
+
+    let mut reloaded = mapper::new(Rom::new(&raw).unwrap());
+    reloaded.load_battery_ram(&save);
+    assert_eq!(reloaded.read_prg(0x6000), 0x42);
+
+    // nothing to save without a battery
+    assert!(
+        mapper::new(Rom::new(&builder(19, 8, 16).to_ines()).unwrap())
+            .battery_ram()
+            .is_none()
+    );
+
+    // NES 2.0 sizes the PRG-RAM, here 2K of volatile RAM with only the internal
+    // RAM battery backed
+    let raw = builder(19, 8, 16)
+        .battery(true)
+        .nes2(true)
+        .prg_ram_size(0x800)
+        .prg_nvram_size(0)
+        .to_ines();
+    let cart = mapper::new(Rom::new(&raw).unwrap());
+    assert_eq!(cart.battery_ram().unwrap().len(), 0x800 + 0x80);
+}
+
+#[test]
+fn test_namco163_internal_ram_reads_auto_increment() {
+    let mut cart = mapper::new(Rom::new(&builder(19, 8, 16).to_ines()).unwrap());
+    cart.write_prg(0xF800, 0x80 | 0x20);
+    for data in [1, 2, 3] {
+        cart.write_prg(0x4800, data);
+    }
+
+    cart.write_prg(0xF800, 0x80 | 0x20);
+    let reads: Vec<u8> = (0..3).map(|_| cart.read_prg(0x4800)).collect();
+    assert_eq!(reads, [1, 2, 3]);
+
+    // without bit 7 the address stays put
+    cart.write_prg(0xF800, 0x21);
+    let reads: Vec<u8> = (0..3).map(|_| cart.read_prg(0x4800)).collect();
+    assert_eq!(reads, [2, 2, 2]);
+}
+
+#[test]
+fn test_namco163_wavetable() {
+    let mut cart = mapper::new(Rom::new(&builder(19, 8, 16).to_ines()).unwrap());
+    let mut write_ram = |addr: u8, data: &[u8]| {
+        cart.write_prg(0xF800, 0x80 | addr);
+        for &byte in data {
+            cart.write_prg(0x4800, byte);
+        }
+    };
+    // a square wave of 4 samples at address 0: 15, 15, 0, 0
+    write_ram(0x00, &[0xFF, 0x00]);
+    // channel 7, one channel enabled: frequency $10000, length 4, volume 15
+    write_ram(0x78, &[0x00, 0x00, 0x00, 0x00, 0xFD, 0x00, 0x00, 0x0F]);
+
+    let mut levels = Vec::new();
+    for _ in 0..15 * 4 {
+        cart.clock_cpu();
+        levels.push(cart.audio_output());
+    }
+    assert!(levels.iter().any(|&level| level > 0.0));
+    assert!(levels.iter().any(|&level| level < 0.0));
+}
+
+#[test]
+fn test_rambo1_full_1k_chr_and_third_prg_bank() {
+    let mut cart = mapper::new(Rom::new(&builder(64, 8, 16).to_ines()).unwrap());
+    cart.write_prg(0x8000, 0b0010_0000);
+    cart.write_prg(0x8001, 0x10);
+    cart.write_prg(0x8000, 0b0010_1000);
+    cart.write_prg(0x8001, 0x21);
+    assert_eq!(cart.read_chr(0x0000), 0x80 | 0x10);
+    assert_eq!(cart.read_chr(0x0400), 0x80 | 0x21);
+
+    cart.write_prg(0x8000, 0x0F);
+    cart.write_prg(0x8001, 9);
+    cart.write_prg(0x8000, 0x06);
+    cart.write_prg(0x8001, 3);
+    assert_eq!(cart.read_prg(0x8000), 3);
+    assert_eq!(cart.read_prg(0xC000), 9);
+
+    cart.write_prg(0x8000, 0b0100_0000);
+    assert_eq!(cart.read_prg(0x8000), 9);
+    assert_eq!(cart.read_prg(0xA000), 3);
+    assert_eq!(cart.read_prg(0xE000), 15);
+}
+
+#[test]
+fn test_rambo1_cycle_irq() {
+    let mut cart = mapper::new(Rom::new(&builder(64, 8, 16).to_ines()).unwrap());
+    cart.write_prg(0xC000, 3);
+    cart.write_prg(0xC001, 1);
+    cart.write_prg(0xE001, 0);
+
+    // the reload after $C001 adds two, so the first IRQ comes after latch + 2 clocks
+    for _ in 0..4 * 4 {
+        cart.clock_cpu();
+    }
+    assert!(!cart.irq());
+    for _ in 0..4 {
+        cart.clock_cpu();
+    }
+    assert!(cart.irq());
+
+    cart.write_prg(0xE000, 0);
+    assert!(!cart.irq());
+}
+
+#[test]
+fn test_namco108_chr_wiring() {
+    let mut cart = mapper::new(Rom::new(&builder(88, 8, 16).to_ines()).unwrap());
+    cart.write_prg(0x8000, 0);
+    cart.write_prg(0x8001, 0x44);
+    cart.write_prg(0x8000, 2);
+    cart.write_prg(0x8001, 0x05);
+    // 2K banks stay in the first 64K, 1K banks in the second
+    assert_eq!(cart.read_chr(0x0000), 0x80 | 0x04);
+    assert_eq!(cart.read_chr(0x1000), 0x80 | 0x45);
+
+    cart.write_prg(0x8000, 7);
+    cart.write_prg(0x8001, 2);
+    assert_eq!(cart.read_prg(0xA000), 2);
+    assert_eq!(cart.read_prg(0xC000), 14);
+}
+
+#[test]
+fn test_namco154_and_95_nametables() {
+    let mut cart = mapper::new(Rom::new(&builder(154, 8, 16).to_ines()).unwrap());
+    cart.write_prg(0x8000, 0b0100_0000);
+    assert_eq!(cart.read_nametable(0x2000), Nametable::Vram(1));
+    cart.write_prg(0xC000, 0);
+    assert_eq!(cart.read_nametable(0x2C00), Nametable::Vram(0));
+
+    let mut cart = mapper::new(Rom::new(&builder(95, 8, 16).to_ines()).unwrap());
+    cart.write_prg(0x8000, 0);
+    cart.write_prg(0x8001, 0b10_0000);
+    cart.write_prg(0x8000, 1);
+    cart.write_prg(0x8001, 0);
+    assert_eq!(cart.read_nametable(0x2400), Nametable::Vram(1));
+    assert_eq!(cart.read_nametable(0x2800), Nametable::Vram(0));
+    assert_eq!(cart.read_chr(0x0000), 0x80);
+}
+
+// Sends an SST39SF040 command through UNROM 512's bank register, which supplies
+// A14 of the flash address for writes to $8000-$BFFF.
+fn unrom512_flash_command(cart: &mut Box<dyn Mapper>, command: u8) {
+    cart.write_prg(0xC000, 1);
+    cart.write_prg(0x9555, 0xAA);
+    cart.write_prg(0xC000, 0);
+    cart.write_prg(0xAAAA, 0x55);
+    cart.write_prg(0xC000, 1);
+    cart.write_prg(0x9555, command);
+}
+
+#[test]
+fn test_unrom512_banking_and_one_screen() {
+    let raw = builder(30, 32, 0)
+        .mirroring(Mirroring::FOUR_SCREEN)
+        .to_ines();
+    let mut cart = mapper::new(Rom::new(&raw).unwrap());
+    cart.write_prg(0x8000, 0b1010_0101);
+    assert_eq!(cart.read_prg(0x8000), 10);
+    assert_eq!(cart.read_prg(0xC000), 62);
+    assert_eq!(cart.mirroring(), Mirroring::SINGLE_SCREEN_UPPER);
+
+    // CHR-RAM bank 1
+    cart.write_chr(0x0000, 0x42);
+    cart.write_prg(0x8000, 0);
+    assert_eq!(cart.read_chr(0x0000), 0);
+    assert_eq!(cart.mirroring(), Mirroring::SINGLE_SCREEN_LOWER);
+}
+
+#[test]
+fn test_unrom512_four_screen() {
+    let raw = builder(30, 32, 0)
+        .mirroring(Mirroring::FOUR_SCREEN)
+        .vertical_mirroring_bit(true)
+        .to_ines();
+    let rom = Rom::new(&raw).unwrap();
+    assert!(rom.vertical_mirroring_bit);
+    let mut cart = mapper::new(rom);
+    cart.write_prg(0x8000, 0b1000_0000);
+    assert_eq!(cart.mirroring(), Mirroring::FOUR_SCREEN);
+    assert_eq!(cart.read_nametable(0x2C00), Nametable::Vram(3));
+}
+
+#[test]
+fn test_unrom512_flash_save() {
+    let raw = builder(30, 32, 0).battery(true).to_ines();
+    let mut cart = mapper::new(Rom::new(&raw).unwrap());
+    assert!(cart.battery_ram().is_none());
+
+    unrom512_flash_command(&mut cart, 0x80);
+    cart.write_prg(0xC000, 1);
+    cart.write_prg(0x9555, 0xAA);
+    cart.write_prg(0xC000, 0);
+    cart.write_prg(0xAAAA, 0x55);
+    cart.write_prg(0xC000, 3);
+    cart.write_prg(0x8000, 0x30);
+
+    unrom512_flash_command(&mut cart, 0xA0);
+    cart.write_prg(0xC000, 3);
+    cart.write_prg(0x8000, 0x42);
+
+    assert_eq!(cart.read_prg(0x8000), 0x42);
+    assert_eq!(cart.read_prg(0x8001), 0xFF);
+    // the rest of the 8K page is outside the erased sector
+    assert_eq!(cart.read_prg(0x9000), 6);
+
+    let save = cart.battery_ram().unwrap().to_vec();
+    assert_eq!(save.len(), 512 * 1024);
+    let mut reloaded = mapper::new(Rom::new(&raw).unwrap());
+    reloaded.load_battery_ram(&save);
+    reloaded.write_prg(0xC000, 3);
+    assert_eq!(reloaded.read_prg(0x8000), 0x42);
+}
+
+#[test]
+fn test_gtrom_flash_id_and_nametables() {
+    let mut cart = mapper::new(Rom::new(&builder(111, 32, 0).to_ines()).unwrap());
+    cart.write_prg(0x5000, 2);
+    assert_eq!(cart.read_prg(0x8000), 8);
+
+    cart.write_prg(0x5000, 0);
+    cart.write_prg(0xD555, 0xAA);
+    cart.write_prg(0xAAAA, 0x55);
+    cart.write_prg(0xD555, 0x90);
+    assert_eq!(cart.read_prg(0x8000), 0xBF);
+    assert_eq!(cart.read_prg(0x8001), 0xB7);
+    cart.write_prg(0x8000, 0xF0);
+    assert_eq!(cart.read_prg(0x8000), 0);
+
+    assert_eq!(cart.write_nametable(0x2C00, 0x11), None);
+    cart.write_prg(0x7000, 0b10_0000);
+    assert_eq!(cart.read_nametable(0x2C00), Nametable::Data(0));
+    cart.write_prg(0x7000, 0);
+    assert_eq!(cart.read_nametable(0x2C00), Nametable::Data(0x11));
+}
+
+// Drives the LZ93D50's EEPROM lines through $800D: bit 5 is SCL, bit 6 SDA, and
+// bit 7 releases SDA so the EEPROM can answer.
+struct I2c<'a> {
+    cart: &'a mut Box<dyn Mapper>,
+    lsb_first: bool,
+}
+
+impl I2c<'_> {
+    fn lines(&mut self, scl: bool, sda: bool) {
+        let data = (scl as u8) << 5 | (sda as u8) << 6;
+        self.cart.write_prg(0x800D, data);
+    }
+
+    fn start(&mut self) {
+        self.lines(false, true);
+        self.lines(true, true);
+        self.lines(true, false);
+        self.lines(false, false);
+    }
+
+    fn stop(&mut self) {
+        self.lines(false, false);
+        self.lines(true, false);
+        self.lines(true, true);
+    }
+
+    fn sda(&self) -> bool {
+        self.cart.read_prg(0x6000) & 0b1_0000 != 0
+    }
+
+    // Sends a byte and returns whether the EEPROM acknowledged it.
+    fn write_byte(&mut self, byte: u8) -> bool {
+        for i in 0..8 {
+            let bit = if self.lsb_first {
+                byte >> i
+            } else {
+                byte >> (7 - i)
+            } & 1
+                != 0;
+            self.lines(false, bit);
+            self.lines(true, bit);
+        }
+        self.cart.write_prg(0x800D, 0b1000_0000);
+        self.cart.write_prg(0x800D, 0b1010_0000);
+        let ack = !self.sda();
+        self.lines(false, false);
+        ack
+    }
+
+    fn read_byte(&mut self, ack: bool) -> u8 {
+        let mut byte = 0;
+        for i in 0..8 {
+            self.cart.write_prg(0x800D, 0b1000_0000);
+            self.cart.write_prg(0x800D, 0b1010_0000);
+            let bit = self.sda() as u8;
+            byte |= if self.lsb_first {
+                bit << i
+            } else {
+                bit << (7 - i)
+            };
+        }
+        self.lines(false, !ack);
+        self.lines(true, !ack);
+        self.lines(false, !ack);
+        byte
+    }
+}
+
+#[test]
+fn test_bandai_24c02_eeprom() {
+    let mut cart = mapper::new(Rom::new(&builder(16, 8, 16).to_ines()).unwrap());
+    let mut i2c = I2c {
+        cart: &mut cart,
+        lsb_first: false,
+    };
+    i2c.start();
+    assert!(i2c.write_byte(0xA0));
+    assert!(i2c.write_byte(0x10));
+    assert!(i2c.write_byte(0x12));
+    assert!(i2c.write_byte(0x34));
+    i2c.stop();
+
+    // random read: set the address, then restart in read mode
+    i2c.start();
+    assert!(i2c.write_byte(0xA0));
+    assert!(i2c.write_byte(0x10));
+    i2c.start();
+    assert!(i2c.write_byte(0xA1));
+    assert_eq!(i2c.read_byte(true), 0x12);
+    assert_eq!(i2c.read_byte(false), 0x34);
+    i2c.stop();
+
+    let save = cart.battery_ram().unwrap();
+    assert_eq!(save.len(), 256);
+    assert_eq!(save[0x10..0x12], [0x12, 0x34]);
+}
+
+#[test]
+fn test_bandai_24c01_eeprom() {
+    let mut cart = mapper::new(Rom::new(&builder(159, 8, 16).to_ines()).unwrap());
+    let mut save = vec![0; 128];
+    save[0x21] = 0x5A;
+    cart.load_battery_ram(&save);
+
+    let mut i2c = I2c {
+        cart: &mut cart,
+        lsb_first: true,
+    };
+    // no device address: 7-bit word address with the R/W bit on top
+    i2c.start();
+    assert!(i2c.write_byte(0x80 | 0x21));
+    assert_eq!(i2c.read_byte(false), 0x5A);
+    i2c.stop();
+
+    i2c.start();
+    assert!(i2c.write_byte(0x05));
+    assert!(i2c.write_byte(0xC3));
+    i2c.stop();
+    assert_eq!(cart.battery_ram().unwrap()[0x05], 0xC3);
+}
+
+#[test]
+fn test_bandai_banking_and_irq() {
+    let mut cart = mapper::new(Rom::new(&builder(16, 8, 16).to_ines()).unwrap());
+    cart.write_prg(0x8008, 3);
+    cart.write_prg(0x8003, 0x21);
+    assert_eq!(cart.read_prg(0x8000), 6);
+    assert_eq!(cart.read_prg(0xC000), 14);
+    assert_eq!(cart.read_chr(0x0C00), 0x80 | 0x21);
+
+    // the LZ93D50 loads the counter from the latch when enabling
+    cart.write_prg(0x800B, 5);
+    cart.write_prg(0x800C, 0);
+    cart.write_prg(0x800A, 1);
+    for _ in 0..4 {
+        cart.clock_cpu();
+    }
+    assert!(!cart.irq());
+    cart.clock_cpu();
+    assert!(cart.irq());
+    cart.write_prg(0x800A, 0);
+    assert!(!cart.irq());
+
+    // the FCG at $6000 writes the counter directly
+    cart.write_prg(0x600B, 2);
+    cart.write_prg(0x600A, 1);
+    cart.clock_cpu();
+    cart.clock_cpu();
+    assert!(cart.irq());
+}
+
+// A board that only exists in the tests, answering every PRG read with `tag`.
+struct TestBoard {
+    tag: u8,
+}
+
+impl Mapper for TestBoard {
+    fn read_prg(&self, _addr: u16) -> u8 {
+        self.tag
+    }
+
+    fn write_prg(&mut self, _addr: u16, _data: u8) {}
+
+    fn read_chr(&mut self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn write_chr(&mut self, _addr: u16, _data: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::HORIZONTAL
+    }
+}
+
+#[test]
+fn test_trainer_is_preloaded_on_boards_without_prg_ram() {
+    let mut trainer = [0; 512];
+    trainer[0] = 0x4C;
+    for mapper in [2, 3, 7, 30, 64, 206] {
+        let raw = RomBuilder::new(vec![0; 0x20000], vec![])
+            .mapper(mapper)
+            .trainer(trainer)
+            .to_ines();
+        let mut cart = mapper::new(Rom::new(&raw).unwrap());
+        assert_eq!(cart.read_prg(0x7000), 0x4C, "mapper {}", mapper);
+        cart.write_prg(0x6000, 0x12);
+        assert_eq!(cart.read_prg(0x6000), 0x12, "mapper {}", mapper);
+    }
+}
+
+#[test]
+fn test_trainer_is_refused_where_7000_is_taken() {
+    for mapper in [16, 111, 157, 159] {
+        let raw = RomBuilder::new(vec![0; 0x20000], vec![])
+            .mapper(mapper)
+            .trainer([0; 512])
+            .to_ines();
+        assert_eq!(
+            Rom::new(&raw).err(),
+            Some(RomError::UnsupportedTrainer { mapper })
+        );
+    }
+}
+
+#[test]
+fn test_unknown_mapper_is_a_load_error() {
+    let error = Rom::new(&builder(252, 2, 1).to_ines()).err().unwrap();
+    assert_eq!(
+        error,
+        RomError::UnsupportedMapper {
+            mapper: 252,
+            submapper: 0
+        }
+    );
+}
+
+#[test]
+fn test_registered_mapper_is_used_for_its_number_and_submapper() {
+    mapper::register(251, None, |_| Box::new(TestBoard { tag: 1 }));
+    mapper::register(251, Some(2), |_| Box::new(TestBoard { tag: 2 }));
+
+    let mut rom = Rom::new(&builder(251, 2, 1).to_ines()).unwrap();
+    assert_eq!(mapper::new(rom).read_prg(0x8000), 1);
+
+    rom = Rom::new(&builder(251, 2, 1).to_ines()).unwrap();
+    rom.submapper = 2;
+    assert_eq!(mapper::new(rom).read_prg(0x8000), 2);
+}
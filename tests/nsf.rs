@@ -0,0 +1,272 @@
+use rust_NES::cartridge::{Mirroring, RomError, Timing};
+use rust_NES::cpu::Mem;
+use rust_NES::mapper::{self, Mapper};
+use rust_NES::nsf::{self, Nsf, Player};
+use rust_NES::wav;
+
+const SAMPLE_RATE: u32 = 44_100;
+
+fn nsf_file(
+    load: u16,
+    init: u16,
+    play: u16,
+    bankswitch: [u8; 8],
+    expansion: u8,
+    program: &[u8],
+) -> Vec<u8> {
+    let mut raw = vec![0; nsf::HEADER_SIZE];
+    raw[0..5].copy_from_slice(nsf::MAGIC);
+    raw[5] = 1;
+    raw[6] = 3;
+    raw[7] = 2;
+    raw[0x08..0x0A].copy_from_slice(&load.to_le_bytes());
+    raw[0x0A..0x0C].copy_from_slice(&init.to_le_bytes());
+    raw[0x0C..0x0E].copy_from_slice(&play.to_le_bytes());
+    raw[0x0E..0x13].copy_from_slice(b"Title");
+    raw[0x2E..0x34].copy_from_slice(b"Artist");
+    raw[0x4E..0x52].copy_from_slice(b"2024");
+    raw[0x6E..0x70].copy_from_slice(&16639u16.to_le_bytes());
+    raw[0x70..0x78].copy_from_slice(&bankswitch);
+    raw[0x78..0x7A].copy_from_slice(&19997u16.to_le_bytes());
+    raw[0x7B] = expansion;
+    raw.extend_from_slice(program);
+    raw
+}
+
+// INIT at $8000 stores A and X at $10/$11, PLAY at $8005 counts calls in $12
+const COUNTER: [u8; 8] = [0x85, 0x10, 0x86, 0x11, 0x60, 0xE6, 0x12, 0x60];
+
+#[test]
+fn test_parse_header() {
+    let nsf = Nsf::parse(&nsf_file(
+        0x8000,
+        0x8000,
+        0x8005,
+        [0; 8],
+        nsf::VRC6,
+        &COUNTER,
+    ))
+    .unwrap();
+    assert_eq!(nsf.version, 1);
+    assert_eq!(nsf.songs, 3);
+    assert_eq!(nsf.starting_song, 2);
+    assert_eq!(nsf.load_addr, 0x8000);
+    assert_eq!(nsf.init_addr, 0x8000);
+    assert_eq!(nsf.play_addr, 0x8005);
+    assert_eq!(nsf.name, "Title");
+    assert_eq!(nsf.artist, "Artist");
+    assert_eq!(nsf.copyright, "2024");
+    assert_eq!(nsf.ntsc_speed, 16639);
+    assert_eq!(nsf.pal_speed, 19997);
+    assert_eq!(nsf.timing, Timing::NTSC);
+    assert_eq!(nsf.expansion, nsf::VRC6);
+    assert!(!nsf.is_bankswitched());
+    assert_eq!(nsf.data, COUNTER);
+
+    assert_eq!(
+        Nsf::parse(&[0; 10]).err(),
+        Some(RomError::TruncatedHeader { len: 10 })
+    );
+    let mut raw = nsf_file(0x8000, 0x8000, 0x8005, [0; 8], 0, &COUNTER);
+    raw[0] = b'X';
+    assert!(matches!(Nsf::parse(&raw), Err(RomError::BadMagic { .. })));
+}
+
+#[test]
+fn test_init_and_play_rate() {
+    let nsf = Nsf::parse(&nsf_file(0x8000, 0x8000, 0x8005, [0; 8], 0, &COUNTER)).unwrap();
+    let mut player = Player::new(&nsf, 1, SAMPLE_RATE);
+    assert_eq!(player.cpu.mem_read(0x10), 1);
+    assert_eq!(player.cpu.mem_read(0x11), 0);
+    assert_eq!(player.cpu.mem_read(0x12), 0);
+
+    let samples = player.render(SAMPLE_RATE as usize);
+    assert_eq!(samples.len(), SAMPLE_RATE as usize);
+    let calls = player.cpu.mem_read(0x12);
+    assert!((60..=61).contains(&calls), "{}", calls);
+}
+
+#[test]
+fn test_pal_tunes_play_at_50_hz() {
+    let mut raw = nsf_file(0x8000, 0x8000, 0x8005, [0; 8], 0, &COUNTER);
+    raw[0x7A] = 1;
+    let nsf = Nsf::parse(&raw).unwrap();
+    assert_eq!(nsf.timing, Timing::PAL);
+
+    let mut player = Player::new(&nsf, 0, SAMPLE_RATE);
+    assert_eq!(player.cpu.mem_read(0x11), 1);
+    player.render(SAMPLE_RATE as usize);
+    let calls = player.cpu.mem_read(0x12);
+    assert!((50..=51).contains(&calls), "{}", calls);
+}
+
+#[test]
+fn test_bankswitching() {
+    // bank 0 holds $AA, bank 1 the code: it reads $9000, switches bank 1 in
+    // there and reads it again
+    let mut program = vec![0; 0x2000];
+    program[0] = 0xAA;
+    program[0x1000..0x1000 + 16].copy_from_slice(&[
+        0xAD, 0x00, 0x90, // LDA $9000
+        0x85, 0x10, // STA $10
+        0xA9, 0x01, // LDA #1
+        0x8D, 0xF9, 0x5F, // STA $5FF9
+        0xAD, 0x00, 0x90, // LDA $9000
+        0x85, 0x11, // STA $11
+        0x60, // RTS
+    ]);
+    let nsf = Nsf::parse(&nsf_file(
+        0x8000,
+        0x8000,
+        0x800F,
+        [1, 0, 1, 1, 1, 1, 1, 1],
+        0,
+        &program,
+    ))
+    .unwrap();
+    assert!(nsf.is_bankswitched());
+
+    let player = Player::new(&nsf, 0, SAMPLE_RATE);
+    assert_eq!(player.cpu.mem_read(0x10), 0xAA);
+    assert_eq!(player.cpu.mem_read(0x11), 0xAD);
+}
+
+#[test]
+fn test_load_address_within_a_bank() {
+    let nsf = Nsf::parse(&nsf_file(0x8100, 0x8100, 0x8105, [0; 8], 0, &COUNTER)).unwrap();
+    let player = Player::new(&nsf, 2, SAMPLE_RATE);
+    assert_eq!(player.cpu.mem_read(0x8100), 0x85);
+    assert_eq!(player.cpu.mem_read(0x10), 2);
+}
+
+#[test]
+fn test_fds_tunes_run_from_ram() {
+    let program = [
+        0xA9, 0x42, // LDA #$42
+        0x8D, 0x00, 0x90, // STA $9000
+        0xAD, 0x00, 0x90, // LDA $9000
+        0x85, 0x10, // STA $10
+        0x60, // RTS
+    ];
+    let nsf = Nsf::parse(&nsf_file(
+        0x6000,
+        0x6000,
+        0x600A,
+        [0; 8],
+        nsf::FDS,
+        &program,
+    ))
+    .unwrap();
+    let player = Player::new(&nsf, 0, SAMPLE_RATE);
+    assert_eq!(player.cpu.mem_read(0x10), 0x42);
+    assert_eq!(player.cpu.mem_read(0x6000), 0xA9);
+}
+
+#[test]
+fn test_expansion_audio() {
+    // VRC6 pulse 1 at full volume with the duty cycle ignored
+    let program = [
+        0xA9, 0x8F, // LDA #$8F
+        0x8D, 0x00, 0x90, // STA $9000
+        0xA9, 0x80, // LDA #$80
+        0x8D, 0x02, 0x90, // STA $9002
+        0x60, // RTS
+    ];
+    let nsf = Nsf::parse(&nsf_file(
+        0x8000,
+        0x8000,
+        0x800A,
+        [0; 8],
+        nsf::VRC6,
+        &program,
+    ))
+    .unwrap();
+    assert!(nsf.is_audible());
+    let samples = Player::new(&nsf, 0, SAMPLE_RATE).render(1000);
+    assert!(samples.iter().all(|&sample| sample > 0.0));
+
+    let nsf = Nsf::parse(&nsf_file(0x8000, 0x8000, 0x800A, [0; 8], 0, &program)).unwrap();
+    assert!(!nsf.is_audible());
+    let samples = Player::new(&nsf, 0, SAMPLE_RATE).render(1000);
+    assert!(samples.iter().all(|&sample| sample == 0.0));
+}
+
+// A board that only exists in the tests and makes no sound.
+struct SilentBoard;
+
+impl Mapper for SilentBoard {
+    fn read_prg(&self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn write_prg(&mut self, _addr: u16, _data: u8) {}
+
+    fn read_chr(&mut self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn write_chr(&mut self, _addr: u16, _data: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::HORIZONTAL
+    }
+}
+
+#[test]
+fn test_expansion_chips_ignore_the_mapper_registry() {
+    mapper::register(24, None, |_| Box::new(SilentBoard));
+
+    // VRC6 pulse 1 at full volume, as above
+    let program = [
+        0xA9, 0x8F, 0x8D, 0x00, 0x90, 0xA9, 0x80, 0x8D, 0x02, 0x90, 0x60,
+    ];
+    let nsf = Nsf::parse(&nsf_file(
+        0x8000,
+        0x8000,
+        0x800A,
+        [0; 8],
+        nsf::VRC6,
+        &program,
+    ))
+    .unwrap();
+    let samples = Player::new(&nsf, 0, SAMPLE_RATE).render(1000);
+    assert!(samples.iter().all(|&sample| sample > 0.0));
+}
+
+#[test]
+fn test_wav_output() {
+    let mut out = Vec::new();
+    wav::write(&mut out, SAMPLE_RATE, &[0.0, 0.5, 2.0, -1.0]).unwrap();
+    assert_eq!(out.len(), 44 + 8);
+    assert_eq!(&out[0..4], b"RIFF");
+    assert_eq!(u32::from_le_bytes(out[4..8].try_into().unwrap()), 36 + 8);
+    assert_eq!(&out[8..16], b"WAVEfmt ");
+    assert_eq!(
+        u32::from_le_bytes(out[24..28].try_into().unwrap()),
+        SAMPLE_RATE
+    );
+    assert_eq!(&out[36..40], b"data");
+    assert_eq!(u32::from_le_bytes(out[40..44].try_into().unwrap()), 8);
+
+    let samples: Vec<i16> = out[44..]
+        .chunks(2)
+        .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    assert_eq!(samples, [0, 16383, i16::MAX, -i16::MAX]);
+}
+
+#[test]
+fn test_every_expansion_chip_at_once() {
+    let expansion = nsf::VRC6 | nsf::VRC7 | nsf::FDS | nsf::MMC5 | nsf::NAMCO163 | nsf::SUNSOFT_5B;
+    let nsf = Nsf::parse(&nsf_file(
+        0x8000, 0x8000, 0x8005, [0; 8], expansion, &COUNTER,
+    ))
+    .unwrap();
+    let mut player = Player::new(&nsf, 0, SAMPLE_RATE);
+    player.render(1000);
+    assert!(player.cpu.mem_read(0x12) > 0);
+
+    // MMC5 ExRAM works as RAM
+    player.cpu.mem_write(0x5C00, 0x5A);
+    assert_eq!(player.cpu.mem_read(0x5C00), 0x5A);
+}
@@ -0,0 +1,246 @@
+use rust_NES::cartridge::{Rom, RomBuilder};
+use rust_NES::hash::crc32;
+use rust_NES::patch::{self, Checksum, PatchError};
+
+fn number(mut value: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte | 0x80);
+            return bytes;
+        }
+        bytes.push(byte);
+        value -= 1;
+    }
+}
+
+fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+    patch.extend_from_slice(&crc32(source).to_le_bytes());
+    patch.extend_from_slice(&crc32(target).to_le_bytes());
+    let crc = crc32(&patch);
+    patch.extend_from_slice(&crc.to_le_bytes());
+    patch
+}
+
+fn ups(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = b"UPS1".to_vec();
+    patch.extend(number(source.len()));
+    patch.extend(number(target.len()));
+    let byte = |data: &[u8], i: usize| data.get(i).copied().unwrap_or(0);
+    let mut last = 0;
+    let mut i = 0;
+    while i < target.len() {
+        if byte(source, i) == target[i] {
+            i += 1;
+            continue;
+        }
+        patch.extend(number(i - last));
+        while i < target.len() && byte(source, i) != target[i] {
+            patch.push(byte(source, i) ^ target[i]);
+            i += 1;
+        }
+        patch.push(0);
+        i += 1;
+        last = i;
+    }
+    with_footer(patch, source, target)
+}
+
+fn ips_record(offset: u32, data: &[u8]) -> Vec<u8> {
+    let mut record = offset.to_be_bytes()[1..].to_vec();
+    record.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    record.extend_from_slice(data);
+    record
+}
+
+#[test]
+fn test_ips() {
+    let rom = vec![0; 16];
+    let mut patch = b"PATCH".to_vec();
+    patch.extend(ips_record(2, &[1, 2, 3]));
+    // run-length record: 4 times $FF at 8
+    patch.extend_from_slice(&[0, 0, 8, 0, 0, 0, 4, 0xFF]);
+    // records can grow the file
+    patch.extend(ips_record(18, &[9]));
+    patch.extend_from_slice(b"EOF");
+
+    let out = patch::apply(&rom, &patch).unwrap();
+    assert_eq!(
+        out,
+        [0, 0, 1, 2, 3, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 9]
+    );
+
+    // the truncation extension
+    patch.extend_from_slice(&[0, 0, 4]);
+    assert_eq!(patch::apply(&rom, &patch).unwrap(), [0, 0, 1, 2]);
+
+    let truncated = &patch[..patch.len() - 8];
+    assert!(matches!(
+        patch::apply(&rom, truncated),
+        Err(PatchError::Truncated { .. })
+    ));
+}
+
+#[test]
+fn test_ips_patches_the_header_before_parsing() {
+    let rom = RomBuilder::new(vec![0xEA; 0x4000], vec![0; 0x2000]).to_ines();
+    let mut patch = b"PATCH".to_vec();
+    // mapper 2
+    patch.extend(ips_record(6, &[0x20]));
+    patch.extend_from_slice(b"EOF");
+
+    let patched = patch::apply(&rom, &patch).unwrap();
+    assert_eq!(Rom::new(&patched).unwrap().mapper, 2);
+}
+
+#[test]
+fn test_ups() {
+    let source: Vec<u8> = (0..200).map(|i| i as u8).collect();
+    let mut target = source.clone();
+    target[3] = 0xAA;
+    target[4] = 0xBB;
+    target[100] = 0;
+    target.extend_from_slice(&[1, 2, 3]);
+
+    let patch = ups(&source, &target);
+    assert_eq!(patch::apply(&source, &patch).unwrap(), target);
+
+    let mut other = source.clone();
+    other[0] = 1;
+    assert_eq!(
+        patch::apply(&other, &patch),
+        Err(PatchError::ChecksumMismatch {
+            checksum: Checksum::Source,
+            expected: crc32(&source),
+            found: crc32(&other),
+        })
+    );
+
+    let mut damaged = patch.clone();
+    damaged[8] ^= 1;
+    assert!(matches!(
+        patch::apply(&source, &damaged),
+        Err(PatchError::ChecksumMismatch {
+            checksum: Checksum::Patch,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_bps() {
+    let source = b"ABCDEFGHIJ".to_vec();
+    let target = b"ABCDxyzxyzxyzGHIJAB".to_vec();
+
+    let mut patch = b"BPS1".to_vec();
+    patch.extend(number(source.len()));
+    patch.extend(number(target.len()));
+    patch.extend(number(3));
+    patch.extend_from_slice(b"m=1");
+    // source read of "ABCD"
+    patch.extend(number(3 << 2));
+    // target read of "xyz"
+    patch.extend(number((2 << 2) | 1));
+    patch.extend_from_slice(b"xyz");
+    // target copy of "xyzxyz" from 4, overlapping what it writes
+    patch.extend(number((5 << 2) | 3));
+    patch.extend(number(4 << 1));
+    // source copy of "GHIJ" from 6, then "AB" from 0
+    patch.extend(number((3 << 2) | 2));
+    patch.extend(number(6 << 1));
+    patch.extend(number((1 << 2) | 2));
+    patch.extend(number((10 << 1) | 1));
+    let patch = with_footer(patch, &source, &target);
+
+    assert_eq!(patch::apply(&source, &patch).unwrap(), target);
+
+    assert!(matches!(
+        patch::apply(b"ABCDEFGHIK", &patch),
+        Err(PatchError::ChecksumMismatch {
+            checksum: Checksum::Source,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_bps_target_checksum() {
+    let source = b"ABCD".to_vec();
+    let mut patch = b"BPS1".to_vec();
+    patch.extend(number(4));
+    patch.extend(number(4));
+    patch.extend(number(0));
+    patch.extend(number(3 << 2));
+    // claims a different result than the actions produce
+    let patch = with_footer(patch, &source, b"ABCE");
+
+    assert_eq!(
+        patch::apply(&source, &patch),
+        Err(PatchError::ChecksumMismatch {
+            checksum: Checksum::Target,
+            expected: crc32(b"ABCE"),
+            found: crc32(b"ABCD"),
+        })
+    );
+}
+
+#[test]
+fn test_forged_sizes_are_errors() {
+    let source = b"ABCD".to_vec();
+    let target = b"ABCD".to_vec();
+
+    // a target far beyond any ROM, with footer checksums that still match
+    for magic in [b"UPS1", b"BPS1"] {
+        let mut patch = magic.to_vec();
+        patch.extend(number(4));
+        patch.extend(number(patch::MAX_TARGET_SIZE + 1));
+        patch.extend(number(0));
+        let patch = with_footer(patch, &source, &target);
+        assert_eq!(
+            patch::apply(&source, &patch),
+            Err(PatchError::TargetTooLarge {
+                size: patch::MAX_TARGET_SIZE + 1
+            })
+        );
+    }
+
+    // a number too long for usize
+    let mut patch = b"UPS1".to_vec();
+    patch.extend(number(4));
+    patch.extend(number(4));
+    patch.extend_from_slice(&[0x7F; 12]);
+    patch.push(0xFF);
+    let patch = with_footer(patch, &source, &target);
+    assert!(matches!(
+        patch::apply(&source, &patch),
+        Err(PatchError::OutOfBounds { .. })
+    ));
+
+    // a BPS target copy longer than the target
+    let mut patch = b"BPS1".to_vec();
+    patch.extend(number(4));
+    patch.extend(number(4));
+    patch.extend(number(0));
+    patch.extend(number(1 << 2));
+    patch.extend(number((usize::MAX >> 3 << 2) | 3));
+    patch.extend(number(0));
+    let patch = with_footer(patch, &source, &target);
+    assert_eq!(
+        patch::apply(&source, &patch),
+        Err(PatchError::OutOfBounds { offset: 8 })
+    );
+}
+
+#[test]
+fn test_unknown_format() {
+    assert_eq!(
+        patch::apply(&[0; 4], b"NOTAPATCH"),
+        Err(PatchError::UnknownFormat {
+            found: b"NOTAP".to_vec()
+        })
+    );
+    let err = patch::apply(&[0; 4], b"NOTAPATCH").unwrap_err();
+    assert!(err.to_string().starts_with("Not an IPS, UPS or BPS patch"));
+}
@@ -0,0 +1,135 @@
+use std::path::Path;
+
+use rust_NES::bus::Bus;
+use rust_NES::cartridge::{Rom, RomBuilder};
+use rust_NES::cpu::Mem;
+use rust_NES::save;
+
+fn temp_save(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("rust_nes_{}_{}.sav", name, std::process::id()))
+}
+
+fn program_flash(bus: &mut Bus, addr: u16, data: u8) {
+    bus.mem_write(0xD555, 0xAA);
+    bus.mem_write(0xAAAA, 0x55);
+    bus.mem_write(0xD555, 0xA0);
+    bus.mem_write(addr, data);
+}
+
+#[test]
+fn test_sav_path_sits_next_to_rom() {
+    assert_eq!(
+        save::sav_path(Path::new("roms/game.nes")),
+        Path::new("roms/game.sav")
+    );
+}
+
+#[test]
+fn test_flash_save_round_trip() {
+    let path = temp_save("flash");
+    // GTROM, where the whole PRG is flash the game can save into
+    let gtrom = RomBuilder::new(vec![0xFF; 512 * 1024], Vec::new())
+        .mapper(111)
+        .build()
+        .unwrap();
+
+    let mut bus = Bus::new(gtrom.clone());
+    bus.load_save(&path).unwrap();
+    program_flash(&mut bus, 0x8123, 0x5A);
+    bus.flush_save().unwrap();
+
+    let mut bus = Bus::new(gtrom);
+    assert_eq!(bus.mem_read(0x8123), 0xFF);
+    bus.load_save(&path).unwrap();
+    assert_eq!(bus.mem_read(0x8123), 0x5A);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_no_save_written_until_flash_changes() {
+    let path = temp_save("unused");
+    let gtrom = RomBuilder::new(vec![0xFF; 512 * 1024], Vec::new())
+        .mapper(111)
+        .build()
+        .unwrap();
+    let mut bus = Bus::new(gtrom);
+    bus.load_save(&path).unwrap();
+    bus.flush_save().unwrap();
+    drop(bus);
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_prg_ram_size_comes_from_header() {
+    let builder = RomBuilder::new(vec![0; 0x4000], vec![0; 0x2000]);
+    let rom = Rom::new(&builder.to_ines()).unwrap();
+    assert_eq!(rom.prg_ram_size, 0x2000);
+    let rom = Rom::new(&builder.clone().prg_ram_size(0x8000).to_ines()).unwrap();
+    assert_eq!(rom.prg_ram_size, 0x8000);
+    let rom = Rom::new(&builder.battery(true).to_ines()).unwrap();
+    assert_eq!(rom.prg_nvram_size, 0x2000);
+}
+
+#[test]
+fn test_battery_prg_ram_saved_on_drop() {
+    let path = temp_save("drop");
+    let nrom = RomBuilder::new(vec![0; 0x4000], vec![0; 0x2000])
+        .battery(true)
+        .build()
+        .unwrap();
+
+    let mut bus = Bus::new(nrom.clone());
+    bus.load_save(&path).unwrap();
+    bus.mem_write(0x6000, 0x11);
+    bus.mem_write(0x7FFF, 0x22);
+    drop(bus);
+
+    let saved = std::fs::read(&path).unwrap();
+    assert_eq!(saved.len(), 0x2000);
+    assert_eq!((saved[0], saved[0x1FFF]), (0x11, 0x22));
+
+    let mut bus = Bus::new(nrom);
+    bus.load_save(&path).unwrap();
+    assert_eq!(bus.mem_read(0x7FFF), 0x22);
+    drop(bus);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_battery_prg_ram_saved_periodically() {
+    let path = temp_save("periodic");
+    let nrom = RomBuilder::new(vec![0; 0x4000], vec![0; 0x2000])
+        .battery(true)
+        .build()
+        .unwrap();
+
+    let mut bus = Bus::new(nrom);
+    bus.load_save(&path).unwrap();
+    bus.mem_write(0x6000, 0x33);
+    // a few seconds of CPU time
+    for _ in 0..10_000_000 / 255 {
+        bus.tick(255);
+    }
+    assert_eq!(std::fs::read(&path).unwrap()[0], 0x33);
+
+    drop(bus);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_prg_ram_without_battery_is_not_saved() {
+    let path = temp_save("volatile");
+    let nrom = RomBuilder::new(vec![0; 0x4000], vec![0; 0x2000])
+        .build()
+        .unwrap();
+
+    let mut bus = Bus::new(nrom);
+    bus.load_save(&path).unwrap();
+    bus.mem_write(0x6000, 0x44);
+    assert_eq!(bus.mem_read(0x6000), 0x44);
+    drop(bus);
+
+    assert!(!path.exists());
+}
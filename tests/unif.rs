@@ -0,0 +1,106 @@
+use rust_NES::cartridge::{Mirroring, Rom, RomError, Timing};
+use rust_NES::mapper;
+use rust_NES::unif;
+
+fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(data);
+    chunk
+}
+
+fn unif(chunks: &[Vec<u8>]) -> Vec<u8> {
+    let mut raw = b"UNIF".to_vec();
+    raw.extend_from_slice(&7u32.to_le_bytes());
+    raw.resize(32, 0);
+    for chunk in chunks {
+        raw.extend_from_slice(chunk);
+    }
+    raw
+}
+
+#[test]
+fn test_board_names_map_to_mappers() {
+    assert_eq!(unif::board_mapper("NES-TLROM"), Some((4, 0)));
+    assert_eq!(unif::board_mapper("HVC-UNROM"), Some((2, 0)));
+    assert_eq!(unif::board_mapper("UNL-UNROM-512-32"), Some((30, 0)));
+    assert_eq!(unif::board_mapper("ANROM"), Some((7, 0)));
+    assert_eq!(unif::board_mapper("NES-NOSUCHROM"), None);
+}
+
+#[test]
+fn test_chunks_build_a_rom() {
+    let raw = unif(&[
+        chunk(b"NAME", b"Test\0"),
+        chunk(b"MAPR", b"NES-TLROM\0"),
+        // chunks are ordered by their number, not by where they are in the file
+        chunk(b"PRG1", &[2; 0x4000]),
+        chunk(b"PRG0", &[1; 0x4000]),
+        chunk(b"CHR0", &[3; 0x2000]),
+        chunk(b"MIRR", &[1]),
+        chunk(b"BATR", &[1]),
+        chunk(b"TVCI", &[1]),
+    ]);
+    let rom = Rom::load(&raw).unwrap();
+
+    assert_eq!(rom.mapper, 4);
+    assert_eq!(rom.prg_rom.len(), 0x8000);
+    assert_eq!(rom.prg_rom[0], 1);
+    assert_eq!(rom.prg_rom[0x4000], 2);
+    assert_eq!(rom.chr_rom, vec![3; 0x2000]);
+    assert_eq!(rom.screen_mirroring, Mirroring::VERTICAL);
+    assert!(rom.battery);
+    assert_eq!(rom.prg_nvram_size, 0x2000);
+    assert_eq!(rom.timing, Timing::PAL);
+
+    let cart = mapper::new(rom);
+    assert_eq!(cart.read_prg(0x8000), 1);
+    assert_eq!(cart.read_prg(0xE000), 2);
+}
+
+#[test]
+fn test_no_chr_chunks_means_chr_ram() {
+    let raw = unif(&[chunk(b"MAPR", b"UNL-UNROM\0"), chunk(b"PRG0", &[0; 0x8000])]);
+    let rom = Rom::load(&raw).unwrap();
+    assert!(rom.chr_rom.is_empty());
+    assert_eq!(rom.chr_ram_size, 0x2000);
+
+    let mut cart = mapper::new(rom);
+    cart.write_chr(0x0100, 0x55);
+    assert_eq!(cart.read_chr(0x0100), 0x55);
+}
+
+#[test]
+fn test_unif_errors() {
+    let raw = unif(&[chunk(b"PRG0", &[0; 0x4000])]);
+    assert_eq!(
+        Rom::load(&raw).err(),
+        Some(RomError::MissingChunk { id: "MAPR" })
+    );
+
+    let raw = unif(&[
+        chunk(b"MAPR", b"BMC-SUPER700IN1\0"),
+        chunk(b"PRG0", &[0; 0x4000]),
+    ]);
+    assert_eq!(
+        Rom::load(&raw).err(),
+        Some(RomError::UnsupportedBoard {
+            name: "BMC-SUPER700IN1".to_string()
+        })
+    );
+
+    let mut raw = unif(&[
+        chunk(b"MAPR", b"NES-NROM-256\0"),
+        chunk(b"PRG0", &[0; 0x8000]),
+    ]);
+    raw.truncate(raw.len() - 0x100);
+    assert_eq!(
+        Rom::load(&raw).err(),
+        Some(RomError::TruncatedChunk {
+            id: "PRG0".to_string(),
+            offset: 32 + 8 + 13 + 8,
+            expected: 0x8000,
+            available: 0x7F00
+        })
+    );
+}